@@ -3,6 +3,17 @@ pub use sea_orm_migration::prelude::*;
 mod m20220101_000001_create_table;
 mod m20250413_212102_add_mediagroup;
 mod m20250419_183421_create_voting;
+mod m20261018_101500_hashes_any_orientation;
+mod m20261018_120000_create_chat_settings;
+mod m20261018_130000_add_chat_settings_stickers;
+mod m20261018_140000_create_pair_exclusions;
+mod m20261018_150000_add_hash_mutes;
+mod m20261018_160000_add_hashes_crop;
+mod m20261018_170000_add_chat_settings_retention;
+mod m20261018_180000_add_chat_settings_hash_only;
+mod m20261018_190000_create_index_metadata;
+mod m20261018_200000_add_alert_hashes_message;
+mod m20261018_210000_incremental_auto_vacuum;
 
 pub struct Migrator;
 
@@ -13,6 +24,17 @@ impl MigratorTrait for Migrator {
             Box::new(m20220101_000001_create_table::Migration),
             Box::new(m20250413_212102_add_mediagroup::Migration),
             Box::new(m20250419_183421_create_voting::Migration),
+            Box::new(m20261018_101500_hashes_any_orientation::Migration),
+            Box::new(m20261018_120000_create_chat_settings::Migration),
+            Box::new(m20261018_130000_add_chat_settings_stickers::Migration),
+            Box::new(m20261018_140000_create_pair_exclusions::Migration),
+            Box::new(m20261018_150000_add_hash_mutes::Migration),
+            Box::new(m20261018_160000_add_hashes_crop::Migration),
+            Box::new(m20261018_170000_add_chat_settings_retention::Migration),
+            Box::new(m20261018_180000_add_chat_settings_hash_only::Migration),
+            Box::new(m20261018_190000_create_index_metadata::Migration),
+            Box::new(m20261018_200000_add_alert_hashes_message::Migration),
+            Box::new(m20261018_210000_incremental_auto_vacuum::Migration),
         ]
     }
 }
@@ -0,0 +1,70 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// SQLite can't drop a CHECK constraint in place, so the table is rebuilt.
+// Hash type is validated by `HashType::from_str` on the Rust side.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        db.execute_unprepared(
+            r#"CREATE TABLE "hashes_new" (
+                "id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+                "chat_id" integer NOT NULL,
+                "message_id" integer NOT NULL,
+                "filename" varchar NOT NULL,
+                "file_id" varchar NOT NULL,
+                "orientation" varchar NOT NULL,
+                "base64_hash" varchar NOT NULL,
+                "created_at" integer NOT NULL,
+                "media_group_id" varchar NULL
+            )"#,
+        )
+        .await?;
+
+        db.execute_unprepared(
+            r#"INSERT INTO "hashes_new"("id", "chat_id", "message_id", "filename", "file_id", "orientation", "base64_hash", "created_at", "media_group_id")
+            SELECT "id", "chat_id", "message_id", "filename", "file_id", "orientation", "base64_hash", "created_at", "media_group_id" FROM "hashes""#,
+        )
+        .await?;
+
+        db.execute_unprepared(r#"DROP TABLE "hashes""#).await?;
+        db.execute_unprepared(r#"ALTER TABLE "hashes_new" RENAME TO "hashes""#)
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        db.execute_unprepared(
+            r#"CREATE TABLE "hashes_old" (
+                "id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+                "chat_id" integer NOT NULL,
+                "message_id" integer NOT NULL,
+                "filename" varchar NOT NULL,
+                "file_id" varchar NOT NULL,
+                "orientation" varchar NOT NULL CHECK ("orientation" IN ('portrait', 'landscape', 'square')),
+                "base64_hash" varchar NOT NULL,
+                "created_at" integer NOT NULL,
+                "media_group_id" varchar NULL
+            )"#,
+        )
+        .await?;
+
+        db.execute_unprepared(
+            r#"INSERT INTO "hashes_old"("id", "chat_id", "message_id", "filename", "file_id", "orientation", "base64_hash", "created_at", "media_group_id")
+            SELECT "id", "chat_id", "message_id", "filename", "file_id", "orientation", "base64_hash", "created_at", "media_group_id" FROM "hashes"
+            WHERE "orientation" IN ('portrait', 'landscape', 'square')"#,
+        )
+        .await?;
+
+        db.execute_unprepared(r#"DROP TABLE "hashes""#).await?;
+        db.execute_unprepared(r#"ALTER TABLE "hashes_old" RENAME TO "hashes""#)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ChatSettings::Table)
+                    .if_not_exists()
+                    .col(integer(ChatSettings::ChatId).primary_key())
+                    .col(integer(ChatSettings::HashTolerance))
+                    .col(integer(ChatSettings::SearchDistanceSeconds))
+                    .col(integer(ChatSettings::MinVotesCount))
+                    .col(double_null(ChatSettings::MatchThreshold))
+                    .col(boolean(ChatSettings::BlockhashEnabled))
+                    .col(boolean(ChatSettings::Siglip2Enabled))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ChatSettings::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ChatSettings {
+    Table,
+    ChatId,
+    HashTolerance,
+    SearchDistanceSeconds,
+    MinVotesCount,
+    MatchThreshold,
+    BlockhashEnabled,
+    Siglip2Enabled,
+}
@@ -0,0 +1,99 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Hashes of flagged message, kept until NOTDUPE voting on alert is finished
+        manager
+            .create_table(
+                Table::create()
+                    .table(AlertHashes::Table)
+                    .if_not_exists()
+                    .col(pk_auto(AlertHashes::Id))
+                    .col(integer(AlertHashes::ChatId))
+                    .col(integer(AlertHashes::AlertMessageId))
+                    .col(string(AlertHashes::OriginalFileId))
+                    .col(string(AlertHashes::FileId))
+                    .col(string(AlertHashes::Orientation))
+                    .col(string(AlertHashes::Base64Hash))
+                    .col(integer(AlertHashes::CreatedAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_alert_hashes_alert")
+                    .table(AlertHashes::Table)
+                    .col(AlertHashes::ChatId)
+                    .col(AlertHashes::AlertMessageId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(PairExclusions::Table)
+                    .if_not_exists()
+                    .col(pk_auto(PairExclusions::Id))
+                    .col(integer(PairExclusions::ChatId))
+                    .col(string(PairExclusions::OriginalFileId))
+                    .col(string(PairExclusions::FileId))
+                    .col(string(PairExclusions::Orientation))
+                    .col(string(PairExclusions::Base64Hash))
+                    .col(integer(PairExclusions::CreatedAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_pair_exclusions_original")
+                    .table(PairExclusions::Table)
+                    .col(PairExclusions::ChatId)
+                    .col(PairExclusions::OriginalFileId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PairExclusions::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(AlertHashes::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum AlertHashes {
+    Table,
+    Id,
+    ChatId,
+    AlertMessageId,
+    OriginalFileId,
+    FileId,
+    Orientation,
+    Base64Hash,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum PairExclusions {
+    Table,
+    Id,
+    ChatId,
+    OriginalFileId,
+    FileId,
+    Orientation,
+    Base64Hash,
+    CreatedAt,
+}
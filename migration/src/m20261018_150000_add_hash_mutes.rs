@@ -0,0 +1,81 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+/// Default mute duration of ignored images, 30 days
+const DEFAULT_MUTE_DURATION_SECONDS: i64 = 30 * 24 * 60 * 60;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite can add only one column per statement
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Hashes::Table)
+                    .add_column_if_not_exists(big_integer_null(Hashes::MutedUntil))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Hashes::Table)
+                    .add_column_if_not_exists(integer(Hashes::MutedMatchesCount).default(0))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ChatSettings::Table)
+                    .add_column_if_not_exists(
+                        big_integer_null(ChatSettings::MuteDurationSeconds)
+                            .default(DEFAULT_MUTE_DURATION_SECONDS),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ChatSettings::Table)
+                    .drop_column(ChatSettings::MuteDurationSeconds)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Hashes::Table)
+                    .drop_column(Hashes::MutedMatchesCount)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Hashes::Table)
+                    .drop_column(Hashes::MutedUntil)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Hashes {
+    Table,
+    MutedUntil,
+    MutedMatchesCount,
+}
+
+#[derive(DeriveIden)]
+enum ChatSettings {
+    Table,
+    MuteDurationSeconds,
+}
@@ -0,0 +1,34 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Options hashes were calculated with, e.g. hasher sizes
+        manager
+            .create_table(
+                Table::create()
+                    .table(IndexMetadata::Table)
+                    .if_not_exists()
+                    .col(string(IndexMetadata::Key).primary_key())
+                    .col(string(IndexMetadata::Value))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(IndexMetadata::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum IndexMetadata {
+    Table,
+    Key,
+    Value,
+}
@@ -0,0 +1,54 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Flagged message and its stored file, so voted alerts can be exported as dataset.
+        // Sqlite alters one column per statement
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AlertHashes::Table)
+                    .add_column_if_not_exists(big_integer_null(AlertHashes::MessageId))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AlertHashes::Table)
+                    .add_column_if_not_exists(string_null(AlertHashes::Filename))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AlertHashes::Table)
+                    .drop_column(AlertHashes::Filename)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AlertHashes::Table)
+                    .drop_column(AlertHashes::MessageId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum AlertHashes {
+    Table,
+    MessageId,
+    Filename,
+}
@@ -0,0 +1,27 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Retention frees pages with `PRAGMA incremental_vacuum` instead of rewriting whole db.
+// Existing db switches auto_vacuum mode only by one full VACUUM, SQLite migrations
+// aren't wrapped in transaction so it may run here.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        db.execute_unprepared("PRAGMA auto_vacuum = INCREMENTAL")
+            .await?;
+        db.execute_unprepared("VACUUM").await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        db.execute_unprepared("PRAGMA auto_vacuum = NONE").await?;
+        db.execute_unprepared("VACUUM").await?;
+        Ok(())
+    }
+}
@@ -5,81 +5,148 @@ use frankenstein::{
     client_reqwest::Bot,
     methods::{AnswerCallbackQueryParams, GetFileParams, GetUpdatesParams, SendMessageParams},
     response::MethodResponse,
-    types::{CallbackQuery, File, MaybeInaccessibleMessage, Message, ReplyParameters, User},
-    updates::UpdateContent,
+    types::{
+        CallbackQuery, File, MaybeInaccessibleMessage, Message, PhotoSize, ReplyParameters, User,
+    },
+    updates::{Update, UpdateContent},
     AsyncTelegramApi,
 };
 
 use img_hashing_bot::{
-    data::{CallbackQueryCommand, CallbackQueryData},
-    hasher::PHashIndexer,
+    config::{build_indexer, build_storage, Config},
+    data::{parse_bot_command, CallbackQueryCommand, CallbackQueryData},
+    db::apply_migrations,
+    hasher::{CalculatedHash, HashType, PHashIndexer, ScoredMatch, Transform},
+    health::{self, BotStatus},
     keyboards::build_keyboard,
+    keyframes::KeyframeExtractor,
     metrics,
-    storage::{s3_storage::S3FileStorage, FileStorage},
+    retention::run_retention,
+    storage::{download_to_memory, telegram_file_url, AnyFileStorage, FileStorage},
     tg_callbacks::{
         process_contra_callback, process_ignore_callback, process_pro_callback,
         process_wrong_callback,
     },
+    tg_commands::process_settings_command,
     tracing_setup::init_tracing,
+    webhook::{self, DeliveryMode, WebhookConfig},
 };
-use migration::sea_orm::{
-    sqlx::{sqlite::SqliteConnectOptions, SqlitePool},
-    SqlxSqliteConnector,
+use tokio::{
+    signal,
+    sync::{mpsc, Mutex},
 };
-use tokio::{signal, sync::Mutex};
+use tokio_util::sync::CancellationToken;
 
 const MESSAGE_FOUND_MSG: &str = "Эту картинку уже постили тут:";
 const REPLY_NOT_FOUND_ERROR: &str = "Bad Request: message to be replied not found";
-
-async fn apply_migrations(db_path: &str) {
-    use migration::{Migrator, MigratorTrait};
-    let opts = SqliteConnectOptions::new()
-        .filename(db_path)
-        .create_if_missing(true);
-
-    let pool = SqlitePool::connect_with(opts)
-        .await
-        .expect("Failed to connect to apply migrations");
-    let db = SqlxSqliteConnector::from_sqlx_sqlite_pool(pool);
-    Migrator::up(&db, None)
-        .await
-        .expect("Failed to apply transactions");
-}
+const WEBHOOK_UPDATES_BUFFER: usize = 100;
+const DOCUMENT_IMAGE_MIME_TYPES: [&str; 4] = ["image/jpeg", "image/png", "image/webp", "image/bmp"];
 
 #[tokio::main]
 async fn main() -> Result<(), ()> {
     dotenv().ok();
 
-    let otlp_endpoint =
-        &dotenvy::var("OTLP_ENDPOINT").expect("Failed to find OTLP_ENDPOINT env var");
-    let otlp_token = &dotenvy::var("OTLP_TOKEN").expect("Failed to find OTLP_TOKEN env var");
-
-    let s3_endpoint = &dotenvy::var("S3_ENDPOINT").expect("Failed to find S3_ENDPOINT env var");
-    let s3_bucket = &dotenvy::var("S3_BUCKET").expect("Failed to find S3_BUCKET env var");
-    let s3_access_key =
-        &dotenvy::var("S3_ACCESS_KEY").expect("Failed to find S3_ACCESS_KEY env var");
-    let s3_secret_key =
-        &dotenvy::var("S3_SECRET_KEY").expect("Failed to find S3_SECRET_KEY env var");
-    let bot_api_token = &dotenvy::var("TELEGRAM_BOT_API_TOKEN")
-        .expect("Failed to find TELEGRAM_BOT_API_TOKEN env var");
-
-    let db_path = "./hashes.db";
+    let config = Config::load().and_then(|config| {
+        config.validate_bot()?;
+        Ok(config)
+    });
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            return Err(());
+        }
+    };
+    let bot_api_token = config.telegram.bot_token.as_deref().unwrap_or_default();
+    let db_path = &config.database.path;
 
     apply_migrations(db_path).await;
 
-    let finisher = init_tracing(&otlp_endpoint, &otlp_token).map_err(|_| ())?;
-    let indexer = Arc::new(Mutex::new(PHashIndexer::new(db_path)));
+    let finisher = init_tracing(&config.telemetry_settings())
+        .await
+        .map_err(|e| eprintln!("Failed to init telemetry: {e}"))?;
+    let indexer = build_indexer(&config).expect("Failed to build indexer");
+    indexer
+        .check_hasher_signature()
+        .await
+        .expect("Incompatible hasher config");
+    let indexer = Arc::new(Mutex::new(indexer));
+
+    let storage = build_storage(&config.storage).expect("Failed to open storage");
+    let storage = Arc::new(Mutex::new(storage));
+
+    let shutdown = CancellationToken::new();
+    if let Some(retention_config) = config.retention_config() {
+        tracing::info!("Retention enabled");
+        tokio::spawn(run_retention(
+            indexer.clone(),
+            storage.clone(),
+            retention_config,
+            shutdown.clone(),
+        ));
+    }
+
+    let api_url = config.telegram.api_url.trim_end_matches('/');
+    let api = Bot::new_url(format!("{api_url}/bot{bot_api_token}"));
+    let files = TelegramFiles {
+        endpoint: format!("{api_url}/file/bot{bot_api_token}"),
+        hash_only: config.storage.hash_only,
+        max_memory_file_size: config.storage.max_memory_file_size,
+    };
+    if files.hash_only {
+        tracing::info!("Hash-only mode enabled for all chats");
+    }
 
-    let storage = Arc::new(Mutex::new(S3FileStorage::new(
-        s3_endpoint,
-        s3_bucket,
-        s3_access_key,
-        s3_secret_key,
-    )));
+    let keyframes = config.video.ffmpeg_path.as_deref().map(|ffmpeg_path| {
+        tracing::info!("Video keyframes enabled");
+        KeyframeExtractor::new(ffmpeg_path, config.video.keyframes_count)
+    });
+
+    let status = Arc::new(BotStatus::new(config.limits.max_concurrent_updates));
+    if let Some(health_config) = config.health_config() {
+        let status = status.clone();
+        let db_path = db_path.to_owned();
+        let storage = storage.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(e) = health::serve(&health_config, status, &db_path, storage, shutdown).await
+            {
+                tracing::error!("{e}");
+            }
+        });
+    }
+    match config.telegram.delivery_mode {
+        DeliveryMode::Polling => {
+            run_polling(&api, &files, &indexer, &storage, &keyframes, &status).await
+        }
+        DeliveryMode::Webhook => {
+            run_webhook(
+                &api,
+                config.webhook_config().expect("Invalid webhook config"),
+                &files,
+                &indexer,
+                &storage,
+                &keyframes,
+                &status,
+            )
+            .await
+        }
+    }
 
-    let api = Bot::new(bot_api_token);
-    let files_endpoint = format!("https://api.telegram.org/file/bot{bot_api_token}/");
+    shutdown.cancel();
+    indexer.lock().await.flush();
+    finisher();
+    Ok(())
+}
 
+async fn run_polling(
+    api: &Bot,
+    files: &TelegramFiles,
+    indexer: &Arc<Mutex<PHashIndexer>>,
+    storage: &Arc<Mutex<AnyFileStorage>>,
+    keyframes: &Option<KeyframeExtractor>,
+    status: &Arc<BotStatus>,
+) {
     let update_params_builder = GetUpdatesParams::builder();
     let mut update_params = update_params_builder.build();
 
@@ -88,40 +155,10 @@ async fn main() -> Result<(), ()> {
             result = api.get_updates(&update_params) => {
                 match result {
                     Ok(response) => {
+                        status.record_poll_success();
                         for update in response.result {
-                            match update.content {
-                                UpdateContent::Message(message) => {
-
-                                        let api_clone = api.clone();
-                                        let files_endpoint = files_endpoint.clone();
-
-                                        let indexer = indexer.clone();
-                                        let storage = storage.clone();
-                                        tokio::spawn(async move {
-                                            if message.photo.is_none() {
-                                                return;
-                                            }
-                                            if let Err(e) = process_message(&message, api_clone, &files_endpoint, indexer, storage).await {
-                                                tracing::error!("Failed to start message processing: {e}");
-                                            }
-                                        });
-
-                                }
-                                UpdateContent::CallbackQuery(callback_message) => {
-                                    let api_clone = api.clone();
-                                    let indexer = indexer.clone();
-                                    tokio::spawn(async move {
-                                        let result = process_callback(&api_clone, &callback_message, indexer).await;
-                                        if let Err(err) = result {
-                                            tracing::warn!("Failed to process buttons: {err}");
-                                        }
-                                    });
-                                }
-                                _ => {
-                                    tracing::info!("Other {:?}", update.content);
-                                }
-                            }
                             update_params.offset = Some(i64::from(update.update_id) + 1);
+                            process_update(update, api, files, indexer, storage, keyframes, status);
                         }
                     }
                     Err(error) => {
@@ -137,17 +174,135 @@ async fn main() -> Result<(), ()> {
             }
         }
     }
-    finisher();
-    Ok(())
 }
 
-#[tracing::instrument(name = "Process new message", skip(api, storage, indexer))]
+async fn run_webhook(
+    api: &Bot,
+    config: WebhookConfig,
+    files: &TelegramFiles,
+    indexer: &Arc<Mutex<PHashIndexer>>,
+    storage: &Arc<Mutex<AnyFileStorage>>,
+    keyframes: &Option<KeyframeExtractor>,
+    status: &Arc<BotStatus>,
+) {
+    let (updates_sender, mut updates) = mpsc::channel(WEBHOOK_UPDATES_BUFFER);
+    let shutdown = CancellationToken::new();
+
+    let server = {
+        let config = config.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(e) = webhook::serve(&config, updates_sender, shutdown).await {
+                tracing::error!("{e}");
+            }
+        })
+    };
+
+    let registration = {
+        let api = api.clone();
+        let config = config.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move { webhook::register_webhook(&api, &config, shutdown).await })
+    };
+
+    loop {
+        tokio::select! {
+            update = updates.recv() => {
+                match update {
+                    Some(update) => {
+                        process_update(update, api, files, indexer, storage, keyframes, status)
+                    }
+                    None => {
+                        tracing::error!("Webhook server stopped");
+                        break;
+                    }
+                }
+            }
+
+            _ = signal::ctrl_c() => {
+                tracing::info!("Bot finished");
+                break;
+            }
+        }
+    }
+
+    shutdown.cancel();
+    if let Err(e) = registration.await {
+        tracing::error!("Failed to stop webhook registration: {e}");
+    }
+    if let Err(e) = webhook::delete_webhook(api, &config).await {
+        tracing::error!("{e}");
+    }
+    if let Err(e) = server.await {
+        tracing::error!("Failed to stop webhook server: {e}");
+    }
+}
+
+fn process_update(
+    update: Update,
+    api: &Bot,
+    files: &TelegramFiles,
+    indexer: &Arc<Mutex<PHashIndexer>>,
+    storage: &Arc<Mutex<AnyFileStorage>>,
+    keyframes: &Option<KeyframeExtractor>,
+    status: &Arc<BotStatus>,
+) {
+    status.set_update_offset(i64::from(update.update_id) + 1);
+    match update.content {
+        UpdateContent::Message(message) => {
+            let api_clone = api.clone();
+            let files = files.clone();
+
+            let indexer = indexer.clone();
+            let storage = storage.clone();
+            let keyframes = keyframes.clone();
+            let status = status.clone();
+            tokio::spawn(async move {
+                let Some(_task) = status.start_task().await else {
+                    return;
+                };
+                let has_media = message.photo.is_some()
+                    || message.document.is_some()
+                    || message.sticker.is_some()
+                    || get_video_from_message(&message).is_some();
+                if !has_media && message.text.is_none() {
+                    return;
+                }
+                if let Err(e) =
+                    process_message(&message, api_clone, &files, indexer, storage, keyframes).await
+                {
+                    tracing::error!("Failed to start message processing: {e}");
+                }
+            });
+        }
+        UpdateContent::CallbackQuery(callback_message) => {
+            let api_clone = api.clone();
+            let indexer = indexer.clone();
+            let status = status.clone();
+            tokio::spawn(async move {
+                let Some(_task) = status.start_task().await else {
+                    return;
+                };
+                let result = process_callback(&api_clone, &callback_message, indexer).await;
+                if let Err(err) = result {
+                    tracing::warn!("Failed to process buttons: {err}");
+                }
+            });
+        }
+        _ => {
+            tracing::info!("Other {:?}", update.content);
+        }
+    }
+}
+
+#[tracing::instrument(name = "Process new message", skip(api, storage, indexer, keyframes))]
 async fn process_message<T: FileStorage>(
     message: &Message,
     api: Bot,
-    files_endpoint: &str,
+    files: &TelegramFiles,
     indexer: Arc<Mutex<PHashIndexer>>,
     storage: Arc<Mutex<T>>,
+    keyframes: Option<KeyframeExtractor>,
 ) -> Result<(), anyhow::Error> {
     // Skip all replies
     if message.reply_to_message.is_some() {
@@ -155,6 +310,30 @@ async fn process_message<T: FileStorage>(
         return Ok(());
     }
 
+    if let Some(video) = get_video_from_message(message) {
+        return process_video(
+            message,
+            &video,
+            &api,
+            files,
+            indexer,
+            storage,
+            keyframes.as_ref(),
+        )
+        .await;
+    }
+
+    if message.sticker.is_some() {
+        let settings = indexer
+            .lock()
+            .await
+            .get_chat_settings(message.chat.id)
+            .await?;
+        if !settings.stickers_enabled {
+            return Ok(());
+        }
+    }
+
     if let Some(response) = get_image_from_message(&message, &api).await {
         let file_processed_info = {
             let indexer = indexer.lock().await;
@@ -182,6 +361,7 @@ async fn process_message<T: FileStorage>(
                     .message_id
                     .try_into()
                     .expect("Failed to cast message id"),
+                None,
             )
             .await
             {
@@ -202,51 +382,49 @@ async fn process_message<T: FileStorage>(
             //Existing file not found, process fully
 
             // Download file
-            let file_path = response
-                .file_path
-                .ok_or(anyhow::format_err!("File path not found in message"))?;
-
-            let storage = storage.clone();
-            let storage = storage.lock().await;
-
-            match download_file_from_tg(
-                &file_path,
-                &response.file_unique_id,
-                files_endpoint,
-                storage.deref(),
-            )
-            .await
-            {
-                Ok(file_uri) => {
+            let hash_only = files.hash_only
+                || indexer
+                    .lock()
+                    .await
+                    .get_chat_settings(message.chat.id)
+                    .await?
+                    .hash_only;
+
+            match fetch_telegram_file(&response, files, &storage, hash_only).await {
+                Ok(fetched) => {
                     {
                         if let Some(size) = response.file_size {
                             metrics::mtr_image_size(size, message.chat.id);
                         }
 
-                        let image = &storage.load_file(&file_uri).await.map_err(|e| {
-                            anyhow::format_err!("Failed to load image from s3: {e}")
-                        })?;
+                        let image = &image::load_from_memory(&fetched.data)
+                            .map_err(|e| anyhow::format_err!("Failed to load image: {e}"))?;
+                        let file_uri = fetched.filename;
 
                         let mut indexer = indexer.lock().await;
 
                         // Generate hashes
+                        let normalized = indexer.normalize_image(image);
+                        if let Some(crop) = &normalized.crop {
+                            tracing::info!("Image borders trimmed to {crop}");
+                        }
+                        let image = normalized.image.as_ref();
                         let calculated_hashes =
                             indexer.hash_image(image);
+                        let variants = indexer.hash_image_variants(image);
 
                         // Search hash in db
                         let result = indexer
-                            .find_similar_hashes(
-                                &calculated_hashes,
-                                message.chat.id,
-                            )
+                            .find_similar_variants(&calculated_hashes, &variants, message.chat.id)
                             .await;
 
                         // Hash found
                         if !result.is_empty() {
                             log::info!("Found similar images images {result:?}");
-                            let found_result_in_chat = result.first().ok_or(
-                                anyhow::format_err!("Failed to find first image in found"),
-                            )?;
+                            let found = result.first().ok_or(anyhow::format_err!(
+                                "Failed to find first image in found"
+                            ))?;
+                            let found_result_in_chat = &found.record;
 
                             //Check if have same media group - check if same like in found
                             if message.media_group_id.is_some()
@@ -259,18 +437,51 @@ async fn process_message<T: FileStorage>(
                             {
                                 metrics::mtr_duplicate_count(1, message.chat.id, user_id);
                             }
+                            if found.transform != Transform::Identity {
+                                tracing::info!(
+                                    "Matched with {} transform",
+                                    found.transform.as_str()
+                                );
+                                metrics::mtr_transformed_matches_count(1, found.transform.as_str());
+                            }
 
-                            if let Err(e) = send_message(
+                            // Community voted to ignore original, duplicate is only counted
+                            if indexer
+                                .count_muted_match(message.chat.id, found_result_in_chat.message_id)
+                                .await
+                            {
+                                metrics::mtr_muted_matches_count(1, message.chat.id);
+                                return Ok(());
+                            }
+
+                            match send_message(
                                 &api,
                                 message.chat.id,
                                 found_result_in_chat
                                     .message_id
                                     .try_into()
                                     .expect("Failed to convert message id"),
+                                found.details().as_deref(),
                             )
                             .await
                             {
-                                if is_message_removed(&e) {
+                                Ok(alert) => {
+                                    if let Err(e) = indexer
+                                        .save_alert(
+                                            message.chat.id,
+                                            alert.result.message_id.into(),
+                                            &found_result_in_chat.file_id,
+                                            &response.file_unique_id,
+                                            message.message_id as i64,
+                                            Some(&file_uri),
+                                            &calculated_hashes,
+                                        )
+                                        .await
+                                    {
+                                        tracing::error!("{e}");
+                                    }
+                                }
+                                Err(e) if is_message_removed(&e) => {
                                     tracing::warn!("Reply not found, update existing record");
                                     metrics::mtr_removed_originals_count(1);
                                     let hash_record = found_result_in_chat;
@@ -283,8 +494,11 @@ async fn process_message<T: FileStorage>(
                                         .await;
 
                                     //remove hashed image if original removed
-                                    storage.remove_file(&file_uri).await?;
-                                } else {
+                                    if fetched.stored {
+                                        storage.lock().await.remove_file(&file_uri).await?;
+                                    }
+                                }
+                                Err(e) => {
                                     tracing::error!(
                                         "Failed to send message about same file id {e}"
                                     );
@@ -300,6 +514,7 @@ async fn process_message<T: FileStorage>(
                                     &response.file_unique_id,
                                     message.media_group_id.as_deref(),
                                     &calculated_hashes,
+                                    normalized.crop.as_ref(),
                                 )
                                 .await
                             {
@@ -319,14 +534,443 @@ async fn process_message<T: FileStorage>(
             return Ok(());
         } else if text == "/help" {
             return Ok(());
+        } else if let Some(("/settings", args)) = parse_bot_command(&text) {
+            return process_settings_command(&api, message, args, indexer).await;
+        } else {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+/// Video, animation or video note attached to message
+#[derive(Debug)]
+struct VideoInfo {
+    file_id: String,
+    file_unique_id: String,
+    duration: u32,
+    thumbnail: Option<PhotoSize>,
+}
+
+fn get_video_from_message(message: &Message) -> Option<VideoInfo> {
+    if let Some(video) = &message.video {
+        return Some(VideoInfo {
+            file_id: video.file_id.clone(),
+            file_unique_id: video.file_unique_id.clone(),
+            duration: video.duration,
+            thumbnail: video.thumbnail.clone(),
+        });
+    }
+    if let Some(animation) = &message.animation {
+        return Some(VideoInfo {
+            file_id: animation.file_id.clone(),
+            file_unique_id: animation.file_unique_id.clone(),
+            duration: animation.duration,
+            thumbnail: animation.thumbnail.clone(),
+        });
+    }
+    let video_note = message.video_note.as_ref()?;
+    Some(VideoInfo {
+        file_id: video_note.file_id.clone(),
+        file_unique_id: video_note.file_unique_id.clone(),
+        duration: video_note.duration,
+        thumbnail: video_note.thumbnail.clone(),
+    })
+}
+
+#[tracing::instrument(name = "Process new video", skip(api, storage, indexer, keyframes))]
+async fn process_video<T: FileStorage>(
+    message: &Message,
+    video: &VideoInfo,
+    api: &Bot,
+    files: &TelegramFiles,
+    indexer: Arc<Mutex<PHashIndexer>>,
+    storage: Arc<Mutex<T>>,
+    keyframes: Option<&KeyframeExtractor>,
+) -> Result<(), anyhow::Error> {
+    if let Some(Ok(user_id)) = message.from.clone().map(|f| f.id.try_into()) {
+        metrics::mtr_images_count(1, user_id);
+    }
+
+    let file_processed_info = indexer
+        .lock()
+        .await
+        .is_file_processed_info(&video.file_unique_id, message.chat.id)
+        .await;
+    if let Some(file_processed_info) = file_processed_info {
+        metrics::mtr_samefiles_count(1);
+        tracing::info!("Found same video in db");
+        reply_duplicate(
+            api,
+            message,
+            file_processed_info.id,
+            file_processed_info.message_id,
+            None,
+            &indexer,
+        )
+        .await;
+        return Ok(());
+    }
+
+    let hash_only = files.hash_only
+        || indexer
+            .lock()
+            .await
+            .get_chat_settings(message.chat.id)
+            .await?
+            .hash_only;
+
+    // Fast first pass by thumbnail, full video is downloaded only if it's not enough.
+    // Thumbnail is kept in memory, it's stored only if video itself isn't
+    let mut file_uri = None;
+    let mut video_stored = false;
+    let mut thumbnail_file_id = None;
+    let mut thumbnail = None;
+    if let Some(thumbnail_size) = &video.thumbnail {
+        match download_telegram_file(api, &thumbnail_size.file_id, files, &storage, true).await {
+            Ok(fetched) => {
+                thumbnail = image::load_from_memory(&fetched.data)
+                    .map_err(|e| tracing::error!("Failed to load video thumbnail: {e}"))
+                    .ok();
+                thumbnail_file_id = Some(fetched.filename);
+            }
+            Err(e) => tracing::error!("Failed to download video thumbnail: {e}"),
+        }
+    }
+
+    let mut calculated_hashes = indexer.lock().await.hash_video(thumbnail.as_ref(), &[]);
+    let mut thumbnail_match = None;
+    if !calculated_hashes.is_empty() {
+        let result = indexer
+            .lock()
+            .await
+            .find_similar_hashes(&calculated_hashes, message.chat.id)
+            .await;
+        thumbnail_match = result.into_iter().next();
+        if thumbnail_match.is_some() {
+            metrics::mtr_video_thumbnail_matches_count(1);
+        }
+    }
+
+    // Without keyframes thumbnail is the only detector
+    if let (None, Some(found)) = (keyframes, &thumbnail_match) {
+        if let Some(thumbnail_file_id) = &thumbnail_file_id {
+            file_uri = store_thumbnail(api, thumbnail_file_id, files, &storage, hash_only).await;
+        }
+        report_similar_video(
+            api,
+            message,
+            video,
+            found,
+            file_uri.as_deref(),
+            &calculated_hashes,
+            &indexer,
+        )
+        .await;
+        return Ok(());
+    }
+
+    if let Some(keyframes) = keyframes {
+        match download_telegram_file(api, &video.file_id, files, &storage, hash_only).await {
+            Ok(fetched) => {
+                let send_metric = metrics::mtr_keyframes_extraction_time();
+                // Hash-only chats must not have video on disk even temporarily
+                let frames = if hash_only {
+                    keyframes.extract_piped(&fetched.data, video.duration).await
+                } else {
+                    keyframes.extract(&fetched.data, video.duration).await
+                };
+                send_metric();
+                // Stored video is indexed even without keyframes, so it isn't left orphaned
+                file_uri = Some(fetched.filename);
+                video_stored = fetched.stored;
+                match frames {
+                    Ok(frames) => {
+                        calculated_hashes =
+                            indexer.lock().await.hash_video(thumbnail.as_ref(), &frames);
+                    }
+                    Err(e) => tracing::error!("Failed to extract video keyframes: {e}"),
+                }
+            }
+            // Public Bot API doesn't allow to download files over 20 MB, only thumbnail is used
+            Err(e) => tracing::error!("Failed to download video from TG: {e}"),
+        }
+
+        // Same thumbnail is only a candidate until sequences agree, it's trusted alone
+        // only if sequence of this video is unavailable
+        let is_confirmed = |found: &ScoredMatch| {
+            found
+                .similarities
+                .iter()
+                .any(|(hash_type, _)| *hash_type == HashType::VideoKeyframes)
+        };
+        let found = if calculated_hashes
+            .iter()
+            .any(|hash| hash.hash_type == HashType::VideoKeyframes)
+        {
+            indexer
+                .lock()
+                .await
+                .find_similar_hashes(&calculated_hashes, message.chat.id)
+                .await
+                .into_iter()
+                .find(is_confirmed)
         } else {
+            thumbnail_match
+        };
+        if let Some(found) = found {
+            if file_uri.is_none() {
+                if let Some(thumbnail_file_id) = &thumbnail_file_id {
+                    file_uri =
+                        store_thumbnail(api, thumbnail_file_id, files, &storage, hash_only).await;
+                }
+            }
+            report_similar_video(
+                api,
+                message,
+                video,
+                &found,
+                file_uri.as_deref(),
+                &calculated_hashes,
+                &indexer,
+            )
+            .await;
             return Ok(());
         }
     }
 
+    if calculated_hashes.is_empty() {
+        if let Some(file_uri) = file_uri.filter(|_| video_stored) {
+            storage.lock().await.remove_file(&file_uri).await?;
+        }
+        return Ok(());
+    }
+    if file_uri.is_none() {
+        if let Some(thumbnail_file_id) = &thumbnail_file_id {
+            file_uri = store_thumbnail(api, thumbnail_file_id, files, &storage, hash_only).await;
+        }
+    }
+    let Some(file_uri) = file_uri else {
+        tracing::warn!("Nothing to index for video");
+        return Ok(());
+    };
+    if let Err(e) = indexer
+        .lock()
+        .await
+        .save_to_index(
+            &file_uri,
+            message.chat.id,
+            message.message_id as i64,
+            &video.file_unique_id,
+            message.media_group_id.as_deref(),
+            &calculated_hashes,
+            None,
+        )
+        .await
+    {
+        tracing::error!("Failed to index video {e:?}");
+    }
     Ok(())
 }
 
+async fn report_similar_video(
+    api: &Bot,
+    message: &Message,
+    video: &VideoInfo,
+    found: &ScoredMatch,
+    file_uri: Option<&str>,
+    calculated_hashes: &[CalculatedHash],
+    indexer: &Arc<Mutex<PHashIndexer>>,
+) {
+    log::info!("Found similar video {found:?}");
+    let details = found.details();
+    let found = &found.record;
+
+    //Check if have same media group - check if same like in found
+    if message.media_group_id.is_some() && message.media_group_id == found.media_group_id {
+        return;
+    }
+
+    if let Some(Ok(user_id)) = message.from.clone().map(|f| f.id.try_into()) {
+        metrics::mtr_duplicate_count(1, message.chat.id, user_id);
+    }
+
+    // Community voted to ignore original, duplicate is only counted
+    if indexer
+        .lock()
+        .await
+        .count_muted_match(message.chat.id, found.message_id)
+        .await
+    {
+        metrics::mtr_muted_matches_count(1, message.chat.id);
+        return;
+    }
+
+    let Some(alert_message_id) = reply_duplicate(
+        api,
+        message,
+        found.id,
+        found.message_id,
+        details.as_deref(),
+        indexer,
+    )
+    .await
+    else {
+        return;
+    };
+    if let Err(e) = indexer
+        .lock()
+        .await
+        .save_alert(
+            message.chat.id,
+            alert_message_id,
+            &found.file_id,
+            &video.file_unique_id,
+            message.message_id as i64,
+            file_uri,
+            calculated_hashes,
+        )
+        .await
+    {
+        tracing::error!("{e}");
+    }
+}
+
+/// Reply to duplicate with link to original, original record is moved to new message if removed.
+///
+/// Returns id of sent alert message.
+async fn reply_duplicate(
+    api: &Bot,
+    message: &Message,
+    hash_id: i32,
+    original_message_id: i64,
+    details: Option<&str>,
+    indexer: &Arc<Mutex<PHashIndexer>>,
+) -> Option<i64> {
+    match send_message(
+        api,
+        message.chat.id,
+        original_message_id
+            .try_into()
+            .expect("Failed to convert message id"),
+        details,
+    )
+    .await
+    {
+        Ok(alert) => Some(alert.result.message_id.into()),
+        Err(e) => {
+            if is_message_removed(&e) {
+                tracing::warn!("Reply not found, update existing record");
+                metrics::mtr_removed_originals_count(1);
+                indexer
+                    .lock()
+                    .await
+                    .update_old_hash(hash_id, message.chat.id, message.message_id as i64)
+                    .await;
+            } else {
+                tracing::error!("Failed to send message about same file id {e}");
+            }
+            None
+        }
+    }
+}
+
+/// Where Telegram files are downloaded from and how they are kept
+#[derive(Debug, Clone)]
+struct TelegramFiles {
+    endpoint: String,
+    /// All chats are hash-only regardless of their settings
+    hash_only: bool,
+    max_memory_file_size: u64,
+}
+
+/// Downloaded Telegram file and name for index
+struct FetchedFile {
+    /// Storage URL, or Telegram file_id if file was kept only in memory
+    filename: String,
+    data: Vec<u8>,
+    stored: bool,
+}
+
+/// Name of video thumbnail for index or alert, stored thumbnail is downloaded once more
+async fn store_thumbnail<T: FileStorage>(
+    api: &Bot,
+    thumbnail_file_id: &str,
+    files: &TelegramFiles,
+    storage: &Mutex<T>,
+    hash_only: bool,
+) -> Option<String> {
+    if hash_only {
+        return Some(thumbnail_file_id.to_owned());
+    }
+    download_telegram_file(api, thumbnail_file_id, files, storage, false)
+        .await
+        .map_err(|e| tracing::error!("Failed to store video thumbnail: {e}"))
+        .ok()
+        .map(|fetched| fetched.filename)
+}
+
+async fn download_telegram_file<T: FileStorage>(
+    api: &Bot,
+    file_id: &str,
+    files: &TelegramFiles,
+    storage: &Mutex<T>,
+    hash_only: bool,
+) -> Result<FetchedFile, anyhow::Error> {
+    let file = api
+        .get_file(&GetFileParams::builder().file_id(file_id).build())
+        .await
+        .map_err(|e| anyhow::format_err!("Failed to get file info: {e}"))?
+        .result;
+
+    fetch_telegram_file(&file, files, storage, hash_only).await
+}
+
+/// In hash-only mode file is downloaded to memory only, so it's never persisted.
+///
+/// Storage is locked only for its own calls, not while file is processed.
+async fn fetch_telegram_file<T: FileStorage>(
+    file: &File,
+    files: &TelegramFiles,
+    storage: &Mutex<T>,
+    hash_only: bool,
+) -> Result<FetchedFile, anyhow::Error> {
+    let file_path = file
+        .file_path
+        .as_deref()
+        .ok_or(anyhow::format_err!("File path not found in response"))?;
+
+    if hash_only {
+        let tg_file_url = telegram_file_url(&files.endpoint, file_path)?;
+        let data = download_to_memory(&tg_file_url, files.max_memory_file_size).await?;
+        return Ok(FetchedFile {
+            filename: file.file_id.clone(),
+            data,
+            stored: false,
+        });
+    }
+
+    let file_uri = download_file_from_tg(
+        file_path,
+        &file.file_unique_id,
+        &files.endpoint,
+        storage.lock().await.deref(),
+    )
+    .await?;
+    let data = storage
+        .lock()
+        .await
+        .load_raw_file(&file_uri)
+        .await
+        .map_err(|e| anyhow::format_err!("Failed to load file from storage: {e}"))?;
+    Ok(FetchedFile {
+        filename: file_uri,
+        data,
+        stored: true,
+    })
+}
+
 #[tracing::instrument(name = "Download file from tg", skip(storage))]
 async fn download_file_from_tg<T: FileStorage>(
     file_path: &str,
@@ -335,7 +979,7 @@ async fn download_file_from_tg<T: FileStorage>(
     storage: &T,
 ) -> Result<String, anyhow::Error> {
     let destination_path_str = get_filename(file_path, file_id);
-    let tg_file_url = format!("{files_endpoint}/{file_path}");
+    let tg_file_url = telegram_file_url(files_endpoint, file_path)?;
     tracing::info!("Telegram file url: {}", tg_file_url);
     let file_uri = storage
         .save_file(&tg_file_url, &destination_path_str)
@@ -346,11 +990,25 @@ async fn download_file_from_tg<T: FileStorage>(
 
 #[tracing::instrument(name = "Extract image from message", skip(api))]
 async fn get_image_from_message(message: &Message, api: &Bot) -> Option<File> {
-    let pics = message.photo.clone()?;
-    let best_quality = pics.last()?;
-    let params = GetFileParams::builder()
-        .file_id(&best_quality.file_id)
-        .build();
+    let file_id = if let Some(pics) = &message.photo {
+        let best_quality = pics.last()?;
+        best_quality.file_id.clone()
+    } else if let Some(sticker) = &message.sticker {
+        // Only static stickers are WebP images, animated and video ones are skipped
+        if sticker.is_animated || sticker.is_video {
+            return None;
+        }
+        sticker.file_id.clone()
+    } else {
+        // Uncompressed images sent as files
+        let document = message.document.as_ref()?;
+        let mime_type = document.mime_type.as_deref()?;
+        if !DOCUMENT_IMAGE_MIME_TYPES.contains(&mime_type) {
+            return None;
+        }
+        document.file_id.clone()
+    };
+    let params = GetFileParams::builder().file_id(file_id).build();
     let response = api.get_file(&params).await.ok()?;
     Some(response.result)
 }
@@ -384,14 +1042,20 @@ async fn send_message(
     api: &Bot,
     chat_id: i64,
     message_id: i32,
+    details: Option<&str>,
 ) -> Result<MethodResponse<Message>, frankenstein::Error> {
     let reply_params = ReplyParameters::builder()
         .message_id(message_id) // original message id
         .build();
 
+    // Transform and original crop explain why match doesn't look identical
+    let text = match details {
+        Some(details) => format!("{MESSAGE_FOUND_MSG}\n({details})"),
+        None => MESSAGE_FOUND_MSG.to_owned(),
+    };
     let send_message_params = SendMessageParams::builder()
         .chat_id(chat_id)
-        .text(MESSAGE_FOUND_MSG)
+        .text(text)
         .reply_parameters(reply_params)
         .reply_markup(build_keyboard(chat_id, message_id))
         .build();
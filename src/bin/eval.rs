@@ -0,0 +1,516 @@
+//! Evaluate every detector on labelled image pairs, so thresholds are chosen from data.
+//!
+//! Usage: img_eval <pairs_file> [report_json]
+//!
+//! Pairs file has tab separated `label<TAB>image_a<TAB>image_b` lines, label is `dup` or `diff`,
+//! relative paths are resolved from pairs file directory and `#` starts a comment.
+//! Images are hashed with the same env config as the bot, transform variants are not checked.
+
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+};
+
+use dotenvy::dotenv;
+use image_hasher::ImageHash;
+use img_hashing_bot::{
+    config::{build_hashing_indexer, Config},
+    hasher::{hash_similarity, CalculatedHash, HashType, PHashIndexer},
+};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde_json::{json, Value};
+
+const DEFAULT_REPORT_PATH: &str = "./eval_report.json";
+/// Blockhash tolerances are swept from 1, pair matches if distance is less than tolerance
+const MAX_HASH_TOLERANCE: u32 = 40;
+/// Similarity thresholds are swept from 0 to 1 with `1 / SIMILARITY_STEPS` step
+const SIMILARITY_STEPS: u32 = 100;
+const BLOCKHASH_DETECTORS: [HashType; 4] = [
+    HashType::PHashLandscape,
+    HashType::PHashPortrait,
+    HashType::PHashSquare,
+    HashType::CenterCrop,
+];
+
+struct LabelledPair {
+    duplicate: bool,
+    image_a: PathBuf,
+    image_b: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Rule {
+    /// Blockhash tolerance like `PERCEPTIVE_HASH_TOLERANCE`
+    MaxDistance,
+    /// SigLIP2 similarity or hybrid confidence like `MATCH_THRESHOLD`
+    MinSimilarity,
+}
+
+impl Rule {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Rule::MaxDistance => "max_distance",
+            Rule::MinSimilarity => "min_similarity",
+        }
+    }
+
+    fn thresholds(&self) -> Vec<f32> {
+        match self {
+            Rule::MaxDistance => (1..=MAX_HASH_TOLERANCE).map(|t| t as f32).collect(),
+            Rule::MinSimilarity => (0..=SIMILARITY_STEPS)
+                .map(|step| step as f32 / SIMILARITY_STEPS as f32)
+                .collect(),
+        }
+    }
+
+    fn is_match(&self, value: f32, threshold: f32) -> bool {
+        match self {
+            Rule::MaxDistance => value < threshold,
+            Rule::MinSimilarity => value >= threshold,
+        }
+    }
+}
+
+struct Detector {
+    name: &'static str,
+    rule: Rule,
+    /// Distance or similarity of pair with its label
+    values: Vec<(f32, bool)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SweepPoint {
+    threshold: f32,
+    true_positives: usize,
+    false_positives: usize,
+    true_negatives: usize,
+    false_negatives: usize,
+}
+
+impl SweepPoint {
+    fn precision(&self) -> f32 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_positives,
+        )
+    }
+
+    fn recall(&self) -> f32 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_negatives,
+        )
+    }
+
+    fn f1(&self) -> f32 {
+        let (precision, recall) = (self.precision(), self.recall());
+        if precision + recall == 0.0 {
+            0.0
+        } else {
+            2.0 * precision * recall / (precision + recall)
+        }
+    }
+
+    fn false_positive_rate(&self) -> f32 {
+        ratio(
+            self.false_positives,
+            self.false_positives + self.true_negatives,
+        )
+    }
+
+    fn to_json(self) -> Value {
+        json!({
+            "threshold": self.threshold,
+            "precision": self.precision(),
+            "recall": self.recall(),
+            "f1": self.f1(),
+            "tpr": self.recall(),
+            "fpr": self.false_positive_rate(),
+            "tp": self.true_positives,
+            "fp": self.false_positives,
+            "tn": self.true_negatives,
+            "fn": self.false_negatives,
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    dotenv().ok();
+
+    let pairs_file = env::args().nth(1).ok_or(anyhow::format_err!(
+        "Usage: img_eval <pairs_file> [report_json]"
+    ))?;
+    let report_path = env::args().nth(2).unwrap_or(DEFAULT_REPORT_PATH.to_owned());
+
+    let pairs = read_pairs(Path::new(&pairs_file))?;
+    let config = Config::load()?;
+    config.init_hashing_threads()?;
+    let indexer = build_hashing_indexer(&config)?;
+    let hashes = hash_images(&indexer, &pairs);
+
+    let mut detectors: Vec<Detector> = BLOCKHASH_DETECTORS
+        .iter()
+        .map(|hash_type| Detector {
+            name: hash_type.as_str(),
+            rule: Rule::MaxDistance,
+            values: vec![],
+        })
+        .collect();
+    detectors.push(Detector {
+        name: HashType::Siglip2.as_str(),
+        rule: Rule::MinSimilarity,
+        values: vec![],
+    });
+    detectors.push(Detector {
+        name: "hybrid",
+        rule: Rule::MinSimilarity,
+        values: vec![],
+    });
+
+    let mut evaluated = 0;
+    for pair in &pairs {
+        let (Some(hashes_a), Some(hashes_b)) =
+            (hashes.get(&pair.image_a), hashes.get(&pair.image_b))
+        else {
+            continue;
+        };
+        evaluated += 1;
+
+        for (detector, hash_type) in detectors.iter_mut().zip(BLOCKHASH_DETECTORS) {
+            if let Some(distance) = hash_distance(hashes_a, hashes_b, hash_type) {
+                detector.values.push((distance as f32, pair.duplicate));
+            }
+        }
+        let siglip2 = &mut detectors[BLOCKHASH_DETECTORS.len()];
+        if let (Some(a), Some(b)) = (
+            find_hash(hashes_a, HashType::Siglip2),
+            find_hash(hashes_b, HashType::Siglip2),
+        ) {
+            match hash_similarity(a, b) {
+                Ok(similarity) => siglip2.values.push((similarity, pair.duplicate)),
+                Err(e) => eprintln!("Failed to compare siglip2 embeddings: {e}"),
+            }
+        }
+        let confidence = indexer
+            .scoring()
+            .score(hashes_a, hashes_b, config.detection.hash_tolerance)
+            .confidence;
+        detectors[BLOCKHASH_DETECTORS.len() + 1]
+            .values
+            .push((confidence, pair.duplicate));
+    }
+
+    let duplicates = pairs.iter().filter(|pair| pair.duplicate).count();
+    println!(
+        "Evaluated {evaluated}/{} pairs, {duplicates} labelled as duplicates",
+        pairs.len()
+    );
+
+    let mut report = vec![];
+    let mut summary = vec![];
+    for detector in detectors
+        .iter()
+        .filter(|detector| !detector.values.is_empty())
+    {
+        let sweep = sweep(detector);
+        let auc = roc_auc(&sweep);
+        let best = *sweep
+            .iter()
+            .reduce(|best, point| if point.f1() > best.f1() { point } else { best })
+            .expect("Sweep is never empty");
+
+        print_sweep(detector, &sweep, best.threshold);
+        summary.push((detector.name, detector.values.len(), best, auc));
+        report.push(json!({
+            "name": detector.name,
+            "rule": detector.rule.as_str(),
+            "pairs": detector.values.len(),
+            "auc": auc,
+            "best": best.to_json(),
+            "sweep": sweep.into_iter().map(SweepPoint::to_json).collect::<Vec<_>>(),
+        }));
+    }
+
+    println!();
+    println!(
+        "{:<16} {:>6} {:>10} {:>10} {:>8} {:>8} {:>8}",
+        "detector", "pairs", "threshold", "precision", "recall", "f1", "auc"
+    );
+    for (name, pairs, best, auc) in &summary {
+        println!(
+            "{name:<16} {pairs:>6} {:>10.2} {:>10.3} {:>8.3} {:>8.3} {auc:>8.3}",
+            best.threshold,
+            best.precision(),
+            best.recall(),
+            best.f1()
+        );
+    }
+
+    let report = json!({
+        "pairs": pairs.len(),
+        "evaluated_pairs": evaluated,
+        "duplicates": duplicates,
+        "detectors": report,
+    });
+    let report = serde_json::to_string_pretty(&report)
+        .map_err(|e| anyhow::format_err!("Failed to serialize report: {e}"))?;
+    std::fs::write(&report_path, report)
+        .map_err(|e| anyhow::format_err!("Failed to write {report_path}: {e}"))?;
+    println!("Report saved to {report_path}");
+
+    Ok(())
+}
+
+fn read_pairs(pairs_file: &Path) -> Result<Vec<LabelledPair>, anyhow::Error> {
+    let content = std::fs::read_to_string(pairs_file)
+        .map_err(|e| anyhow::format_err!("Failed to read pairs file: {e}"))?;
+    let base_dir = pairs_file.parent().unwrap_or(Path::new("."));
+
+    let mut pairs = vec![];
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        let [label, image_a, image_b] = fields[..] else {
+            return Err(anyhow::format_err!(
+                "Line {}: expected label, image_a and image_b separated by tabs",
+                number + 1
+            ));
+        };
+        let duplicate = match label {
+            "dup" | "duplicate" | "1" | "true" => true,
+            "diff" | "different" | "0" | "false" => false,
+            _ => {
+                return Err(anyhow::format_err!(
+                    "Line {}: unknown label `{label}`",
+                    number + 1
+                ))
+            }
+        };
+        pairs.push(LabelledPair {
+            duplicate,
+            image_a: base_dir.join(image_a),
+            image_b: base_dir.join(image_b),
+        });
+    }
+    Ok(pairs)
+}
+
+/// Hashes of every image used in pairs, failed images are reported and skipped
+fn hash_images(
+    indexer: &PHashIndexer,
+    pairs: &[LabelledPair],
+) -> HashMap<PathBuf, Vec<CalculatedHash>> {
+    let mut paths: Vec<&PathBuf> = pairs
+        .iter()
+        .flat_map(|pair| [&pair.image_a, &pair.image_b])
+        .collect();
+    paths.sort();
+    paths.dedup();
+
+    paths
+        .par_iter()
+        .filter_map(|path| match hash_file(indexer, path) {
+            Ok(hashes) => Some(((*path).clone(), hashes)),
+            Err(e) => {
+                eprintln!("Failed to hash {}: {e}", path.display());
+                None
+            }
+        })
+        .collect()
+}
+
+fn hash_file(indexer: &PHashIndexer, path: &Path) -> Result<Vec<CalculatedHash>, anyhow::Error> {
+    let img = image::ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| anyhow::format_err!("Failed to open file: {e}"))?
+        .decode()
+        .map_err(|e| anyhow::format_err!("Failed to load image: {e}"))?;
+
+    let normalized = indexer.normalize_image(&img);
+    Ok(indexer.hash_image(normalized.image.as_ref()))
+}
+
+fn find_hash(hashes: &[CalculatedHash], hash_type: HashType) -> Option<&CalculatedHash> {
+    hashes.iter().find(|hash| hash.hash_type == hash_type)
+}
+
+fn hash_distance(
+    hashes_a: &[CalculatedHash],
+    hashes_b: &[CalculatedHash],
+    hash_type: HashType,
+) -> Option<u32> {
+    let decode = |hashes: &[CalculatedHash]| {
+        ImageHash::<Box<[u8]>>::from_base64(&find_hash(hashes, hash_type)?.hash).ok()
+    };
+    Some(decode(hashes_a)?.dist(&decode(hashes_b)?))
+}
+
+fn sweep(detector: &Detector) -> Vec<SweepPoint> {
+    detector
+        .rule
+        .thresholds()
+        .into_iter()
+        .map(|threshold| {
+            let mut point = SweepPoint {
+                threshold,
+                true_positives: 0,
+                false_positives: 0,
+                true_negatives: 0,
+                false_negatives: 0,
+            };
+            for (value, duplicate) in &detector.values {
+                match (detector.rule.is_match(*value, threshold), duplicate) {
+                    (true, true) => point.true_positives += 1,
+                    (true, false) => point.false_positives += 1,
+                    (false, false) => point.true_negatives += 1,
+                    (false, true) => point.false_negatives += 1,
+                }
+            }
+            point
+        })
+        .collect()
+}
+
+/// Area under ROC curve of sweep points with both curve ends added
+fn roc_auc(sweep: &[SweepPoint]) -> f32 {
+    let mut points: Vec<(f32, f32)> = sweep
+        .iter()
+        .map(|point| (point.false_positive_rate(), point.recall()))
+        .chain([(0.0, 0.0), (1.0, 1.0)])
+        .collect();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+
+    points
+        .windows(2)
+        .map(|pair| (pair[1].0 - pair[0].0) * (pair[1].1 + pair[0].1) / 2.0)
+        .sum()
+}
+
+fn print_sweep(detector: &Detector, sweep: &[SweepPoint], best_threshold: f32) {
+    println!();
+    println!(
+        "{} ({}), {} pairs",
+        detector.name,
+        detector.rule.as_str(),
+        detector.values.len()
+    );
+    println!(
+        "{:>10} {:>10} {:>8} {:>8} {:>8} {:>6} {:>6} {:>6} {:>6}",
+        "threshold", "precision", "recall", "f1", "fpr", "tp", "fp", "tn", "fn"
+    );
+    for point in sweep {
+        let mark = if point.threshold == best_threshold {
+            " *"
+        } else {
+            ""
+        };
+        println!(
+            "{:>10.2} {:>10.3} {:>8.3} {:>8.3} {:>8.3} {:>6} {:>6} {:>6} {:>6}{mark}",
+            point.threshold,
+            point.precision(),
+            point.recall(),
+            point.f1(),
+            point.false_positive_rate(),
+            point.true_positives,
+            point.false_positives,
+            point.true_negatives,
+            point.false_negatives,
+        );
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f32 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f32 / denominator as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector(rule: Rule, values: &[(f32, bool)]) -> Detector {
+        Detector {
+            name: "test",
+            rule,
+            values: values.to_vec(),
+        }
+    }
+
+    fn point_at(sweep: &[SweepPoint], threshold: f32) -> SweepPoint {
+        *sweep
+            .iter()
+            .find(|point| point.threshold == threshold)
+            .unwrap()
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{actual} is not {expected}"
+        );
+    }
+
+    #[test]
+    fn sweep_counts_pairs_by_distance() {
+        // Duplicates at 2 and 5, different images at 3 and 10
+        let points = sweep(&detector(
+            Rule::MaxDistance,
+            &[(2.0, true), (5.0, true), (3.0, false), (10.0, false)],
+        ));
+        assert_eq!(points.len(), MAX_HASH_TOLERANCE as usize);
+
+        // (threshold, precision, recall, f1, fpr)
+        let table = [
+            (1.0, 0.0, 0.0, 0.0, 0.0),
+            (3.0, 1.0, 0.5, 2.0 / 3.0, 0.0),
+            (4.0, 0.5, 0.5, 0.5, 0.5),
+            (6.0, 2.0 / 3.0, 1.0, 0.8, 0.5),
+            (11.0, 0.5, 1.0, 2.0 / 3.0, 1.0),
+        ];
+        for (threshold, precision, recall, f1, fpr) in table {
+            let point = point_at(&points, threshold);
+            assert_close(point.precision(), precision);
+            assert_close(point.recall(), recall);
+            assert_close(point.f1(), f1);
+            assert_close(point.false_positive_rate(), fpr);
+        }
+        let point = point_at(&points, 4.0);
+        assert_eq!(
+            (
+                point.true_positives,
+                point.false_positives,
+                point.true_negatives,
+                point.false_negatives
+            ),
+            (1, 1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn auc_is_share_of_correctly_ordered_pairs() {
+        // 3 of 4 duplicate and different pairs are ordered right, 5 is farther than 3
+        let points = sweep(&detector(
+            Rule::MaxDistance,
+            &[(2.0, true), (5.0, true), (3.0, false), (10.0, false)],
+        ));
+        assert_close(roc_auc(&points), 0.75);
+
+        let separated = sweep(&detector(
+            Rule::MinSimilarity,
+            &[(0.9, true), (0.95, true), (0.2, false)],
+        ));
+        assert_eq!(separated.len(), SIMILARITY_STEPS as usize + 1);
+        assert_close(roc_auc(&separated), 1.0);
+
+        let inverted = sweep(&detector(Rule::MinSimilarity, &[(0.2, true), (0.9, false)]));
+        assert_close(roc_auc(&inverted), 0.0);
+    }
+}
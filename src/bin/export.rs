@@ -0,0 +1,49 @@
+//! Export finished votings on alerts as labelled dataset.
+//!
+//! Usage: img_export <output_dir> [db_path]
+//!
+//! Writes `manifest.jsonl` with hashes and labels of every voted pair, `images/` with stored
+//! images and `pairs.tsv` which can be passed to `img_eval` directly.
+
+use std::{env, path::Path};
+
+use dotenvy::dotenv;
+use img_hashing_bot::{
+    config::{build_storage, Config},
+    dataset::{export_dataset, MANIFEST_FILE, PAIRS_FILE},
+    db::apply_migrations,
+    hasher::PHashIndexer,
+};
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    dotenv().ok();
+
+    let output_dir = env::args().nth(1).ok_or(anyhow::format_err!(
+        "Usage: img_export <output_dir> [db_path]"
+    ))?;
+    let output_dir = Path::new(&output_dir);
+    let config = Config::load()?;
+    let db_path = env::args().nth(2).unwrap_or(config.database.path.clone());
+
+    apply_migrations(&db_path).await;
+    // Hashes are read from db as is, hashing options are not needed
+    let indexer = PHashIndexer::new(&db_path);
+    let storage = build_storage(&config.storage)?;
+
+    let report = export_dataset(&indexer, &storage, output_dir).await?;
+    println!(
+        "Exported {} voted alerts: {} duplicates, {} false positives",
+        report.alerts, report.duplicates, report.false_positives
+    );
+    println!(
+        "Saved {} images, {} missing",
+        report.images, report.missing_images
+    );
+    println!(
+        "Dataset written to {}, {} and images/",
+        output_dir.join(MANIFEST_FILE).display(),
+        PAIRS_FILE
+    );
+    Ok(())
+}
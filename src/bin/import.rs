@@ -0,0 +1,240 @@
+//! Backfill index from Telegram Desktop chat export (`result.json` and `photos/` folder).
+//!
+//! Usage: img_import <export_dir> [chat_id] [db_path]
+//!
+//! Messages which already have hashes are skipped, so interrupted import can be run again.
+//! Restart the bot after import, its in-memory index doesn't see new rows.
+//!
+//! Rows keep dates of original messages, so old ones are only useful if the chat search window
+//! covers them: `/settings days <N>` or `detection.search_distance_seconds` for chats without
+//! own settings. Retention deletes rows older than `/settings retention <N>` or `retention.days`.
+//! Import reports how many rows fall outside of both.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use dotenvy::dotenv;
+use img_hashing_bot::{
+    config::{build_indexer, Config},
+    db::apply_migrations,
+    hasher::{IndexEntry, PHashIndexer},
+};
+use serde_json::Value;
+
+const EXPORT_FILE: &str = "result.json";
+const PROGRESS_INTERVAL: usize = 100;
+/// Bot API id of supergroup or channel is export id with -100 prefix
+const CHANNEL_ID_OFFSET: i64 = 1_000_000_000_000;
+const IMAGE_MIME_TYPES: [&str; 4] = ["image/jpeg", "image/png", "image/webp", "image/bmp"];
+const SECONDS_IN_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug)]
+struct ExportedImage {
+    message_id: i64,
+    created_at: u64,
+    // Desktop export doesn't include album ids yet, it's kept if present
+    media_group_id: Option<String>,
+    path: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    dotenv().ok();
+
+    let export_dir = env::args().nth(1).ok_or(anyhow::format_err!(
+        "Usage: img_import <export_dir> [chat_id] [db_path]"
+    ))?;
+    let export_dir = Path::new(&export_dir)
+        .canonicalize()
+        .map_err(|e| anyhow::format_err!("Failed to open export directory: {e}"))?;
+    let mut config = Config::load()?;
+    if let Some(db_path) = env::args().nth(3) {
+        config.database.path = db_path;
+    }
+
+    let export = read_export(&export_dir)?;
+    let chat_id = match env::args().nth(2).filter(|chat_id| !chat_id.is_empty()) {
+        Some(chat_id) => i64::from_str(&chat_id)
+            .map_err(|e| anyhow::format_err!("Failed to parse chat id: {e}"))?,
+        None => bot_api_chat_id(&export).ok_or(anyhow::format_err!(
+            "Failed to find chat id in export, pass it as argument"
+        ))?,
+    };
+    let images = find_images(&export, &export_dir);
+
+    apply_migrations(&config.database.path).await;
+    let mut indexer = build_indexer(&config)?;
+    indexer.check_hasher_signature().await?;
+    let indexed_messages = indexer.find_indexed_messages(chat_id).await?;
+    println!(
+        "Importing {} images to chat {chat_id}, {} messages already indexed",
+        images.len(),
+        indexed_messages.len()
+    );
+
+    // Old rows are imported anyway, window or retention may be raised later
+    let settings = indexer.get_chat_settings(chat_id).await?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+    let search_from = now.saturating_sub(settings.search_distance_seconds);
+    // Retention task runs only if it's enabled globally
+    let retention_seconds = (config.retention.days > 0).then(|| {
+        settings
+            .retention_seconds
+            .unwrap_or(config.retention.days * SECONDS_IN_DAY)
+    });
+    let retention_from = retention_seconds.map(|seconds| now.saturating_sub(seconds));
+    let (mut outside_window, mut outside_retention) = (0, 0);
+
+    let (mut imported, mut skipped, mut failed) = (0, 0, 0);
+    for (number, image) in images.iter().enumerate() {
+        if indexed_messages.contains(&image.message_id) {
+            skipped += 1;
+        } else {
+            match import_image(&mut indexer, chat_id, image).await {
+                Ok(()) => {
+                    imported += 1;
+                    if image.created_at <= search_from {
+                        outside_window += 1;
+                    }
+                    if retention_from.is_some_and(|from| image.created_at < from) {
+                        outside_retention += 1;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to import message {}: {e}", image.message_id);
+                    failed += 1;
+                }
+            }
+        }
+
+        if (number + 1) % PROGRESS_INTERVAL == 0 {
+            println!(
+                "Processed {}/{}: {imported} imported, {skipped} skipped, {failed} failed",
+                number + 1,
+                images.len()
+            );
+        }
+    }
+    indexer.flush();
+
+    println!("Import finished: {imported} imported, {skipped} skipped, {failed} failed");
+    if outside_window > 0 {
+        eprintln!(
+            "Warning: {outside_window} imported images are older than search window of {} days and won't be found, raise it with `/settings days <N>`",
+            settings.search_distance_seconds / SECONDS_IN_DAY
+        );
+    }
+    if let Some(retention_seconds) = retention_seconds.filter(|_| outside_retention > 0) {
+        eprintln!(
+            "Warning: {outside_retention} imported images are older than retention period of {} days and will be deleted, raise it with `/settings retention <N>`",
+            retention_seconds / SECONDS_IN_DAY
+        );
+    }
+    Ok(())
+}
+
+/// Hash image the same way as the bot does and save it with original message info
+async fn import_image(
+    indexer: &mut PHashIndexer,
+    chat_id: i64,
+    image: &ExportedImage,
+) -> Result<(), anyhow::Error> {
+    // Format is detected by content, exported files may have wrong extension
+    let img = image::ImageReader::open(&image.path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| anyhow::format_err!("Failed to open {}: {e}", image.path.display()))?
+        .decode()
+        .map_err(|e| anyhow::format_err!("Failed to load {}: {e}", image.path.display()))?;
+
+    let normalized = indexer.normalize_image(&img);
+    let hashes = indexer.hash_image(normalized.image.as_ref());
+    if hashes.is_empty() {
+        return Err(anyhow::format_err!("No hashes calculated"));
+    }
+
+    // Export has no Telegram file ids, path is kept to find the image later
+    let filename = image.path.display().to_string();
+    let file_id = format!("export_{chat_id}_{}", image.message_id);
+    indexer
+        .save_entries_to_index(&[IndexEntry {
+            filename: &filename,
+            chat_id,
+            message_id: image.message_id,
+            file_id: &file_id,
+            media_group_id: image.media_group_id.as_deref(),
+            hashes: &hashes,
+            crop: normalized.crop.as_ref(),
+            created_at: image.created_at,
+        }])
+        .await
+        .map_err(|_| anyhow::format_err!("Failed to save hashes"))
+}
+
+fn read_export(export_dir: &Path) -> Result<Value, anyhow::Error> {
+    let data = std::fs::read(export_dir.join(EXPORT_FILE))
+        .map_err(|e| anyhow::format_err!("Failed to read {EXPORT_FILE}: {e}"))?;
+    let export: Value = serde_json::from_slice(&data)
+        .map_err(|e| anyhow::format_err!("Failed to parse {EXPORT_FILE}: {e}"))?;
+    if !export["messages"].is_array() {
+        return Err(anyhow::format_err!(
+            "Messages not found, only single chat export is supported"
+        ));
+    }
+    Ok(export)
+}
+
+fn bot_api_chat_id(export: &Value) -> Option<i64> {
+    let id = export["id"].as_i64()?;
+    match export["type"].as_str()? {
+        "private_supergroup" | "public_supergroup" | "private_channel" | "public_channel" => {
+            Some(-CHANNEL_ID_OFFSET - id)
+        }
+        "private_group" => Some(-id),
+        _ => Some(id),
+    }
+}
+
+/// Photos and images sent as files, media which wasn't exported is skipped
+fn find_images(export: &Value, export_dir: &Path) -> Vec<ExportedImage> {
+    let Some(messages) = export["messages"].as_array() else {
+        return vec![];
+    };
+
+    messages
+        .iter()
+        .filter(|message| message["type"].as_str() == Some("message"))
+        .filter_map(|message| {
+            let file = match message["photo"].as_str() {
+                Some(photo) => photo,
+                None => {
+                    // Stickers have media type and are skipped like in chats without stickers
+                    let mime_type = message["mime_type"].as_str()?;
+                    if message["media_type"].is_string() || !IMAGE_MIME_TYPES.contains(&mime_type) {
+                        return None;
+                    }
+                    message["file"].as_str()?
+                }
+            };
+            let path = export_dir.join(file);
+            if !path.is_file() {
+                return None;
+            }
+
+            Some(ExportedImage {
+                message_id: message["id"].as_i64()?,
+                created_at: message["date_unixtime"]
+                    .as_str()
+                    .and_then(|date| u64::from_str(date).ok())?,
+                media_group_id: message["media_group_id"].as_str().map(str::to_owned),
+                path,
+            })
+        })
+        .collect()
+}
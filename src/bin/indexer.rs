@@ -1,71 +1,178 @@
-use std::sync::{Arc, Mutex};
+//! Bulk index images from directory into chat, hashes are made the same way as in the bot.
+//!
+//! Usage: img_indexer <source_dir> <chat_id> [db_path]
+//!
+//! Hashing options and db path default come from bot config.
+//!
+//! Files have no message, so first repost of indexed file in chat becomes the original.
+//! Files which are already indexed for the chat are skipped. Path is stored as filename only,
+//! file id is `dir_<chat_id>_<hash of path>` like `export_<chat_id>_<message_id>` of import.
 
-use image_hasher::{HashAlg, HasherConfig};
+use std::{
+    env,
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use glob::glob;
-use img_hashing_bot::db;
+use dotenvy::dotenv;
+use glob::{glob, Pattern};
+use img_hashing_bot::{
+    config::{build_indexer, Config},
+    db::apply_migrations,
+    hasher::{CalculatedHash, IndexEntry, PHashIndexer},
+    normalize::Crop,
+    storage::local_storage::fnv1a,
+};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
-fn main() {
-    let db = Arc::new(Mutex::new(
-        db::create_db("hashes.db").expect("Failed to open db"),
-    ));
-
-    let files = read_files();
-
-    //dbg!(files);
-    let hash_landscape_config = HasherConfig::new()
-        .hash_size(30, 20)
-        .hash_alg(HashAlg::Blockhash);
-    let hasher_landscape = hash_landscape_config.to_hasher();
-
-    let hash_portrait_config = HasherConfig::new()
-        .hash_size(20, 30)
-        .hash_alg(HashAlg::Blockhash);
-    let hasher_portrait = hash_portrait_config.to_hasher();
-
-    let hash_square_config = HasherConfig::new()
-        .hash_size(30, 30)
-        .hash_alg(HashAlg::Blockhash);
-    let hasher_square = hash_square_config.to_hasher();
-
-    files.par_iter().for_each(|file| match image::open(file) {
-        Ok(img) => {
-            let hash_landscape = hasher_landscape.hash_image(&img).to_base64();
-            let hash_portrait = hasher_portrait.hash_image(&img).to_base64();
-            let hash_square = hasher_square.hash_image(&img).to_base64();
-            let db = db.lock().unwrap();
-            let _ = db.execute(
-                r"INSERT INTO hashes(filename, orientation, base64_hash) VALUES(?, ?, ?)",
-                rusqlite::params![file, "landscape", hash_landscape],
-            );
-            let _ = db.execute(
-                r"INSERT INTO hashes(filename, orientation, base64_hash) VALUES(?, ?, ?)",
-                rusqlite::params![file, "portrait", hash_portrait],
-            );
-            let _ = db.execute(
-                r"INSERT INTO hashes(filename, orientation, base64_hash) VALUES(?, ?, ?)",
-                rusqlite::params![file, "square", hash_square],
-            );
-        }
-        Err(_) => {
-            println!("Failed to load {}", file);
-        }
-    });
+const BATCH_SIZE: usize = 100;
+const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "webp", "bmp"];
+/// Reply to missing message fails, so bot moves the record to the repost
+const NO_MESSAGE_ID: i64 = 0;
+
+struct HashedFile {
+    filename: String,
+    // Synthetic, paths must not look like Telegram file ids
+    file_id: String,
+    hashes: Vec<CalculatedHash>,
+    crop: Option<Crop>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    dotenv().ok();
+
+    let usage = || anyhow::format_err!("Usage: img_indexer <source_dir> <chat_id> [db_path]");
+    let source_dir = env::args().nth(1).ok_or_else(usage)?;
+    let chat_id = env::args().nth(2).ok_or_else(usage)?;
+    let chat_id =
+        i64::from_str(&chat_id).map_err(|e| anyhow::format_err!("Failed to parse chat id: {e}"))?;
+    let mut config = Config::load()?;
+    if let Some(db_path) = env::args().nth(3) {
+        config.database.path = db_path;
+    }
+    config.init_hashing_threads()?;
+
+    let source_dir = Path::new(&source_dir)
+        .canonicalize()
+        .map_err(|e| anyhow::format_err!("Failed to open source directory: {e}"))?;
+
+    apply_migrations(&config.database.path).await;
+    let mut indexer = build_indexer(&config)?;
+    indexer.check_hasher_signature().await?;
+
+    let indexed_files = indexer.find_indexed_files(chat_id).await?;
+    let files = read_files(&source_dir)?;
+    let total = files.len();
+    let files: Vec<String> = files
+        .into_iter()
+        .filter(|file| !indexed_files.contains(file))
+        .collect();
+    println!(
+        "Indexing {} files to chat {chat_id}, {} already indexed",
+        files.len(),
+        total - files.len()
+    );
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+    let (mut indexed, mut failed) = (0, 0);
+    for batch in files.chunks(BATCH_SIZE) {
+        let hashed: Vec<HashedFile> = batch
+            .par_iter()
+            .filter_map(|file| match hash_file(&indexer, chat_id, file) {
+                Ok(hashed) => Some(hashed),
+                Err(e) => {
+                    eprintln!("Failed to hash {file}: {e}");
+                    None
+                }
+            })
+            .collect();
+        failed += batch.len() - hashed.len();
+
+        let entries: Vec<IndexEntry> = hashed
+            .iter()
+            .map(|file| IndexEntry {
+                filename: &file.filename,
+                chat_id,
+                message_id: NO_MESSAGE_ID,
+                file_id: &file.file_id,
+                media_group_id: None,
+                hashes: &file.hashes,
+                crop: file.crop.as_ref(),
+                created_at: now,
+            })
+            .collect();
+        indexer
+            .save_entries_to_index(&entries)
+            .await
+            .map_err(|_| anyhow::format_err!("Failed to save hashes"))?;
+        indexed += entries.len();
+
+        println!(
+            "Processed {}/{}: {indexed} indexed, {failed} failed",
+            indexed + failed,
+            files.len()
+        );
+    }
+    indexer.flush();
+
+    println!("Indexing finished: {indexed} indexed, {failed} failed");
+    Ok(())
 }
 
-fn read_files() -> Vec<String> {
+fn hash_file(
+    indexer: &PHashIndexer,
+    chat_id: i64,
+    file: &str,
+) -> Result<HashedFile, anyhow::Error> {
+    let img = image::ImageReader::open(file)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| anyhow::format_err!("Failed to open file: {e}"))?
+        .decode()
+        .map_err(|e| anyhow::format_err!("Failed to load image: {e}"))?;
+
+    let normalized = indexer.normalize_image(&img);
+    let hashes = indexer.hash_image(normalized.image.as_ref());
+    if hashes.is_empty() {
+        return Err(anyhow::format_err!("No hashes calculated"));
+    }
+    Ok(HashedFile {
+        filename: file.to_owned(),
+        file_id: format!("dir_{chat_id}_{:016x}", fnv1a(file.as_bytes())),
+        hashes,
+        crop: normalized.crop,
+    })
+}
+
+/// Images in directory and its subdirectories, sorted so batches are stable between runs
+fn read_files(source_dir: &Path) -> Result<Vec<String>, anyhow::Error> {
+    let pattern = format!(
+        "{}/**/*",
+        Pattern::escape(&source_dir.display().to_string())
+    );
     let mut result = vec![];
-    for entry in glob("demo_data/**/*.jpg").expect("Failed to read glob pattern") {
+    for entry in
+        glob(&pattern).map_err(|e| anyhow::format_err!("Failed to read glob pattern: {e}"))?
+    {
         match entry {
             Ok(path) => {
-                //println!("{:?}", path.display());
-                result.push(path.display().to_string());
-            }
-            Err(_e) => {
-                //println!("{:?}", e)
+                let is_image = path
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| {
+                        IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+                    });
+                if is_image && path.is_file() {
+                    result.push(path.display().to_string());
+                }
             }
+            Err(e) => eprintln!("Failed to read {}: {e}", e.path().display()),
         }
     }
-    result
+    result.sort();
+    Ok(result)
 }
@@ -1,14 +1,12 @@
 use dotenvy::dotenv;
-use img_hashing_bot::tracing_setup::init_tracing;
+use img_hashing_bot::{config::Config, tracing_setup::init_tracing};
 
-fn main() {
+#[tokio::main]
+async fn main() {
     dotenv().ok();
 
-    let finisher = init_tracing(
-        &dotenvy::var("OTLP_ENDPOINT").unwrap(),
-        &dotenvy::var("OTLP_TOKEN").unwrap(),
-    )
-    .unwrap();
+    let config = Config::load().unwrap();
+    let finisher = init_tracing(&config.telemetry_settings()).await.unwrap();
 
     finisher();
 }
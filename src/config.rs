@@ -0,0 +1,1122 @@
+//! Configuration shared by bot and CLI tools.
+//!
+//! Loaded from TOML file at CONFIG_PATH (`./config.toml` by default), then env vars override
+//! single values, so old `.env` files keep working. Every section and value is optional.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    db::DEFAULT_DB_PATH,
+    embedding_index::EmbeddingSearchParams,
+    hasher::{
+        MatchScoring, PHashIndexer, Siglip2Indexer, Transform, MIN_VOTES_COUNT,
+        MUTE_DURATION_IN_SECONDS, PERCEPTIVE_HASH_TOLERANCE, SEARCH_DISTANCE_IN_SECONDS,
+        SIGLIP2_MIN_SIMILARITY,
+    },
+    health::{HealthConfig, DEFAULT_MAX_POLL_AGE_SECONDS},
+    keyframes::{DEFAULT_KEYFRAMES_COUNT, KEYFRAMES_MIN_SIMILARITY},
+    models::ChatDefaults,
+    normalize::{
+        BorderTrimmer, DEFAULT_BORDER_COLOR_TOLERANCE, DEFAULT_BORDER_MAX_TRIM_FRACTION,
+        DEFAULT_BORDER_MIN_UNIFORM_FRACTION,
+    },
+    prometheus::DEFAULT_PROMETHEUS_PATH,
+    retention::{
+        RetentionConfig, DEFAULT_RETENTION_BATCH_SIZE, DEFAULT_RETENTION_INTERVAL_SECONDS,
+    },
+    storage::{
+        local_storage::{LocalFileStorage, DEFAULT_LOCAL_STORAGE_PATH},
+        s3_storage::S3FileStorage,
+        AnyFileStorage, StorageBackend, DEFAULT_MAX_MEMORY_FILE_SIZE,
+    },
+    tracing_setup::{
+        otlp_metadata, TelemetryMode, TelemetrySettings, DEFAULT_PROMETHEUS_LISTEN_ADDR,
+    },
+    webhook::{DeliveryMode, WebhookConfig, DEFAULT_WEBHOOK_PATH},
+};
+
+pub const DEFAULT_CONFIG_PATH: &str = "./config.toml";
+pub const DEFAULT_TELEGRAM_API_URL: &str = "https://api.telegram.org";
+pub const DEFAULT_WEBHOOK_LISTEN_ADDR: &str = "0.0.0.0:8080";
+pub const DEFAULT_MAX_CONCURRENT_UPDATES: usize = 32;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database: DatabaseConfig,
+    pub telegram: TelegramConfig,
+    pub storage: StorageConfig,
+    pub detection: DetectionConfig,
+    pub voting: VotingConfig,
+    pub video: VideoConfig,
+    pub retention: RetentionSettings,
+    pub telemetry: TelemetryConfig,
+    pub limits: LimitsConfig,
+    pub health: HealthSettings,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub path: String,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            path: DEFAULT_DB_PATH.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelegramConfig {
+    pub bot_token: Option<String>,
+    /// Other server is used by tests and self-hosted Bot API
+    pub api_url: String,
+    #[serde(deserialize_with = "parse_str")]
+    pub delivery_mode: DeliveryMode,
+    pub webhook: WebhookSettings,
+}
+
+impl Default for TelegramConfig {
+    fn default() -> Self {
+        Self {
+            bot_token: None,
+            api_url: DEFAULT_TELEGRAM_API_URL.to_owned(),
+            delivery_mode: DeliveryMode::Polling,
+            webhook: WebhookSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookSettings {
+    #[serde(deserialize_with = "parse_str")]
+    pub listen_addr: SocketAddr,
+    pub path: String,
+    pub url: Option<String>,
+    pub secret_token: Option<String>,
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            listen_addr: SocketAddr::from_str(DEFAULT_WEBHOOK_LISTEN_ADDR)
+                .expect("Invalid default listen address"),
+            path: DEFAULT_WEBHOOK_PATH.to_owned(),
+            url: None,
+            secret_token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    #[serde(deserialize_with = "parse_str")]
+    pub backend: StorageBackend,
+    pub local_path: String,
+    /// All chats are hash-only regardless of their settings
+    pub hash_only: bool,
+    /// Files over this size aren't hashed in hash-only mode
+    pub max_memory_file_size: u64,
+    pub s3: S3Settings,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::S3,
+            local_path: DEFAULT_LOCAL_STORAGE_PATH.to_owned(),
+            hash_only: false,
+            max_memory_file_size: DEFAULT_MAX_MEMORY_FILE_SIZE,
+            s3: S3Settings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Settings {
+    pub endpoint: Option<String>,
+    pub bucket: Option<String>,
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DetectionConfig {
+    /// Default blockhash distance of chats, changed per chat with /settings
+    pub hash_tolerance: usize,
+    /// Default age of images searched for duplicates
+    pub search_distance_seconds: u64,
+    /// Confidence every detector reaches at its own limit, chats may only raise it
+    pub match_threshold: f32,
+    /// Zero weight leaves detector out of confidence
+    pub weights: MatchWeights,
+    /// Extra query variants, `["all"]` enables every one
+    #[serde(deserialize_with = "parse_transforms")]
+    pub transforms: Vec<Transform>,
+    /// Fraction of image kept by center crop hash, disabled if not set
+    pub center_crop_fraction: Option<f32>,
+    pub border_trim: BorderTrimSettings,
+    pub siglip2: Siglip2Settings,
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        Self {
+            hash_tolerance: PERCEPTIVE_HASH_TOLERANCE,
+            search_distance_seconds: SEARCH_DISTANCE_IN_SECONDS,
+            match_threshold: MatchScoring::default().threshold,
+            weights: MatchWeights::default(),
+            transforms: vec![],
+            center_crop_fraction: None,
+            border_trim: BorderTrimSettings::default(),
+            siglip2: Siglip2Settings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatchWeights {
+    pub landscape: f32,
+    pub portrait: f32,
+    pub square: f32,
+    pub siglip2: f32,
+    pub video_thumbnail: f32,
+    pub video_keyframes: f32,
+    pub center_crop: f32,
+}
+
+impl Default for MatchWeights {
+    fn default() -> Self {
+        let scoring = MatchScoring::default();
+        Self {
+            landscape: scoring.landscape_weight,
+            portrait: scoring.portrait_weight,
+            square: scoring.square_weight,
+            siglip2: scoring.siglip2_weight,
+            video_thumbnail: scoring.video_thumbnail_weight,
+            video_keyframes: scoring.video_keyframes_weight,
+            center_crop: scoring.center_crop_weight,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BorderTrimSettings {
+    pub enabled: bool,
+    pub color_tolerance: u8,
+    pub min_uniform_fraction: f32,
+    pub max_fraction: f32,
+}
+
+impl Default for BorderTrimSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            color_tolerance: DEFAULT_BORDER_COLOR_TOLERANCE,
+            min_uniform_fraction: DEFAULT_BORDER_MIN_UNIFORM_FRACTION,
+            max_fraction: DEFAULT_BORDER_MAX_TRIM_FRACTION,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Siglip2Settings {
+    /// Embeddings are disabled if not set
+    pub model_path: Option<String>,
+    pub min_similarity: f32,
+    pub top_k: usize,
+    pub ef_search: usize,
+}
+
+impl Default for Siglip2Settings {
+    fn default() -> Self {
+        let search_params = EmbeddingSearchParams::default();
+        Self {
+            model_path: None,
+            min_similarity: SIGLIP2_MIN_SIMILARITY,
+            top_k: search_params.top_k,
+            ef_search: search_params.ef_search,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VotingConfig {
+    /// Default quorum of chats, changed per chat with /settings
+    pub min_votes_count: i64,
+    /// Default mute of ignored images, 0 mutes them forever
+    pub mute_duration_seconds: u64,
+}
+
+impl Default for VotingConfig {
+    fn default() -> Self {
+        Self {
+            min_votes_count: MIN_VOTES_COUNT,
+            mute_duration_seconds: MUTE_DURATION_IN_SECONDS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoConfig {
+    /// Only video thumbnails are hashed if not set
+    pub ffmpeg_path: Option<String>,
+    pub keyframes_count: usize,
+    /// Keyframes similarity at which clips reach match threshold
+    pub keyframes_min_similarity: f32,
+}
+
+impl Default for VideoConfig {
+    fn default() -> Self {
+        Self {
+            ffmpeg_path: None,
+            keyframes_count: DEFAULT_KEYFRAMES_COUNT,
+            keyframes_min_similarity: KEYFRAMES_MIN_SIMILARITY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionSettings {
+    /// Retention is disabled if 0
+    pub days: u64,
+    pub interval_minutes: u64,
+    pub batch_size: usize,
+}
+
+impl Default for RetentionSettings {
+    fn default() -> Self {
+        Self {
+            days: 0,
+            interval_minutes: DEFAULT_RETENTION_INTERVAL_SECONDS / 60,
+            batch_size: DEFAULT_RETENTION_BATCH_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// off, stdout, otlp or prometheus; otlp if endpoint is set, stdout otherwise
+    #[serde(deserialize_with = "parse_optional_str")]
+    pub mode: Option<TelemetryMode>,
+    pub otlp_endpoint: Option<String>,
+    /// Shortcut for OpenObserve: basic authorization with default organization and stream
+    pub otlp_token: Option<String>,
+    /// gRPC metadata of every export, overrides ones from token
+    pub otlp_headers: BTreeMap<String, String>,
+    #[serde(deserialize_with = "parse_str")]
+    pub prometheus_listen_addr: SocketAddr,
+    pub prometheus_path: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            mode: None,
+            otlp_endpoint: None,
+            otlp_token: None,
+            otlp_headers: BTreeMap::new(),
+            prometheus_listen_addr: SocketAddr::from_str(DEFAULT_PROMETHEUS_LISTEN_ADDR)
+                .expect("Invalid default listen address"),
+            prometheus_path: DEFAULT_PROMETHEUS_PATH.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Updates processed at the same time, others wait for their turn
+    pub max_concurrent_updates: usize,
+    /// Threads of CLI tools hashing files in parallel, 0 uses every core
+    pub hashing_threads: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_updates: DEFAULT_MAX_CONCURRENT_UPDATES,
+            hashing_threads: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthSettings {
+    /// Health server is disabled if not set
+    #[serde(deserialize_with = "parse_optional_str")]
+    pub listen_addr: Option<SocketAddr>,
+    /// Polling bot isn't ready if `getUpdates` didn't succeed for this long
+    pub max_poll_age_seconds: u64,
+}
+
+impl Default for HealthSettings {
+    fn default() -> Self {
+        Self {
+            listen_addr: None,
+            max_poll_age_seconds: DEFAULT_MAX_POLL_AGE_SECONDS,
+        }
+    }
+}
+
+impl Config {
+    /// Read config file and env overrides, all found problems are reported at once
+    pub fn load() -> Result<Self, anyhow::Error> {
+        let (path, required) = match optional_var("CONFIG_PATH") {
+            Some(path) => (PathBuf::from(path), true),
+            None => (PathBuf::from(DEFAULT_CONFIG_PATH), false),
+        };
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(content) => Self::from_toml(&content)
+                .map_err(|e| anyhow::format_err!("Invalid config {}: {e}", path.display()))?,
+            // Env only setup doesn't need config file
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => Self::default(),
+            Err(e) => {
+                return Err(anyhow::format_err!(
+                    "Failed to read config {}: {e}",
+                    path.display()
+                ))
+            }
+        };
+
+        let mut errors = config.apply_env();
+        errors.extend(config.find_errors());
+        if !errors.is_empty() {
+            return Err(anyhow::format_err!(
+                "Invalid configuration:\n  {}",
+                errors.join("\n  ")
+            ));
+        }
+        Ok(config)
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, anyhow::Error> {
+        toml::from_str(content).map_err(|e| anyhow::format_err!("{e}"))
+    }
+
+    /// Values which only bot needs, CLI tools work without them
+    pub fn validate_bot(&self) -> Result<(), anyhow::Error> {
+        let mut errors = vec![];
+        if self.telegram.bot_token.is_none() {
+            errors.push("telegram.bot_token (TELEGRAM_BOT_API_TOKEN) is required".to_owned());
+        }
+        if self.telegram.delivery_mode == DeliveryMode::Webhook {
+            if let Err(e) = self.webhook_config() {
+                errors.push(format!("telegram.webhook: {e}"));
+            }
+        }
+        if self.storage.backend == StorageBackend::S3 {
+            errors.extend(self.storage.s3.find_missing());
+        }
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow::format_err!(
+            "Invalid bot configuration:\n  {}",
+            errors.join("\n  ")
+        ))
+    }
+
+    /// Env vars of the times before config file, every value can still be set this way
+    fn apply_env(&mut self) -> Vec<String> {
+        self.apply_vars(&optional_var)
+    }
+
+    /// Values from `var` lookup replace config values, returns parse errors
+    fn apply_vars(&mut self, var: &dyn Fn(&str) -> Option<String>) -> Vec<String> {
+        let mut env = EnvOverrides {
+            var,
+            errors: vec![],
+        };
+
+        env.set(&mut self.database.path, "DB_PATH");
+
+        let telegram = &mut self.telegram;
+        env.set_optional(&mut telegram.bot_token, "TELEGRAM_BOT_API_TOKEN");
+        env.set(&mut telegram.api_url, "TELEGRAM_API_URL");
+        env.set(&mut telegram.delivery_mode, "BOT_DELIVERY_MODE");
+        env.set(&mut telegram.webhook.listen_addr, "WEBHOOK_LISTEN_ADDR");
+        env.set(&mut telegram.webhook.path, "WEBHOOK_PATH");
+        env.set_optional(&mut telegram.webhook.url, "WEBHOOK_URL");
+        env.set_optional(&mut telegram.webhook.secret_token, "WEBHOOK_SECRET_TOKEN");
+
+        let storage = &mut self.storage;
+        env.set(&mut storage.backend, "STORAGE_BACKEND");
+        env.set(&mut storage.local_path, "LOCAL_STORAGE_PATH");
+        env.set_bool(&mut storage.hash_only, "HASH_ONLY");
+        env.set(&mut storage.max_memory_file_size, "HASH_ONLY_MAX_FILE_SIZE");
+        env.set_optional(&mut storage.s3.endpoint, "S3_ENDPOINT");
+        env.set_optional(&mut storage.s3.bucket, "S3_BUCKET");
+        env.set_optional(&mut storage.s3.access_key, "S3_ACCESS_KEY");
+        env.set_optional(&mut storage.s3.secret_key, "S3_SECRET_KEY");
+
+        let detection = &mut self.detection;
+        env.set(&mut detection.hash_tolerance, "HASH_TOLERANCE");
+        env.set(
+            &mut detection.search_distance_seconds,
+            "SEARCH_DISTANCE_SECONDS",
+        );
+        env.set(&mut detection.match_threshold, "MATCH_THRESHOLD");
+        let weights = &mut detection.weights;
+        env.set(&mut weights.landscape, "MATCH_WEIGHT_LANDSCAPE");
+        env.set(&mut weights.portrait, "MATCH_WEIGHT_PORTRAIT");
+        env.set(&mut weights.square, "MATCH_WEIGHT_SQUARE");
+        env.set(&mut weights.siglip2, "MATCH_WEIGHT_SIGLIP2");
+        env.set(&mut weights.video_thumbnail, "MATCH_WEIGHT_VIDEO_THUMBNAIL");
+        env.set(&mut weights.video_keyframes, "MATCH_WEIGHT_VIDEO_KEYFRAMES");
+        env.set(&mut weights.center_crop, "MATCH_WEIGHT_CENTER_CROP");
+        if let Some(value) = optional_var("MATCH_TRANSFORMS") {
+            match read_transforms(value.split(',')) {
+                Ok(transforms) => detection.transforms = transforms,
+                Err(e) => env.errors.push(format!("MATCH_TRANSFORMS: {e}")),
+            }
+        }
+        env.set_optional(&mut detection.center_crop_fraction, "CENTER_CROP_FRACTION");
+        let border_trim = &mut detection.border_trim;
+        env.set_bool(&mut border_trim.enabled, "BORDER_TRIM");
+        env.set(
+            &mut border_trim.color_tolerance,
+            "BORDER_TRIM_COLOR_TOLERANCE",
+        );
+        env.set(
+            &mut border_trim.min_uniform_fraction,
+            "BORDER_TRIM_MIN_UNIFORM_FRACTION",
+        );
+        env.set(&mut border_trim.max_fraction, "BORDER_TRIM_MAX_FRACTION");
+        let siglip2 = &mut detection.siglip2;
+        env.set_optional(&mut siglip2.model_path, "SIGLIP2_MODEL_PATH");
+        env.set(&mut siglip2.min_similarity, "SIGLIP2_MIN_SIMILARITY");
+        env.set(&mut siglip2.top_k, "SIGLIP2_TOP_K");
+        env.set(&mut siglip2.ef_search, "SIGLIP2_EF_SEARCH");
+
+        env.set(&mut self.voting.min_votes_count, "MIN_VOTES_COUNT");
+        env.set(
+            &mut self.voting.mute_duration_seconds,
+            "MUTE_DURATION_SECONDS",
+        );
+
+        env.set_optional(&mut self.video.ffmpeg_path, "FFMPEG_PATH");
+        env.set(&mut self.video.keyframes_count, "VIDEO_KEYFRAMES_COUNT");
+        env.set(
+            &mut self.video.keyframes_min_similarity,
+            "VIDEO_KEYFRAMES_MIN_SIMILARITY",
+        );
+
+        env.set(&mut self.retention.days, "RETENTION_DAYS");
+        env.set(
+            &mut self.retention.interval_minutes,
+            "RETENTION_INTERVAL_MINUTES",
+        );
+        env.set(&mut self.retention.batch_size, "RETENTION_BATCH_SIZE");
+
+        let telemetry = &mut self.telemetry;
+        env.set_optional(&mut telemetry.mode, "TELEMETRY_MODE");
+        env.set_optional(&mut telemetry.otlp_endpoint, "OTLP_ENDPOINT");
+        env.set_optional(&mut telemetry.otlp_token, "OTLP_TOKEN");
+        if let Some(value) = optional_var("OTLP_HEADERS") {
+            match read_headers(&value) {
+                Ok(headers) => telemetry.otlp_headers.extend(headers),
+                Err(e) => env.errors.push(format!("OTLP_HEADERS: {e}")),
+            }
+        }
+        env.set(
+            &mut telemetry.prometheus_listen_addr,
+            "PROMETHEUS_LISTEN_ADDR",
+        );
+        env.set(&mut telemetry.prometheus_path, "PROMETHEUS_PATH");
+
+        let limits = &mut self.limits;
+        env.set(&mut limits.max_concurrent_updates, "MAX_CONCURRENT_UPDATES");
+        env.set(&mut limits.hashing_threads, "HASHING_THREADS");
+
+        env.set_optional(&mut self.health.listen_addr, "HEALTH_LISTEN_ADDR");
+        env.set(
+            &mut self.health.max_poll_age_seconds,
+            "HEALTH_MAX_POLL_AGE_SECONDS",
+        );
+
+        env.errors
+    }
+
+    /// Values out of range, checked for every tool
+    fn find_errors(&self) -> Vec<String> {
+        let mut errors = vec![];
+        let mut check = |valid: bool, message: &str| {
+            if !valid {
+                errors.push(message.to_owned());
+            }
+        };
+
+        check(
+            !self.database.path.is_empty(),
+            "database.path should not be empty",
+        );
+        check(
+            url::Url::parse(&self.telegram.api_url).is_ok(),
+            "telegram.api_url should be valid URL",
+        );
+
+        let detection = &self.detection;
+        check(
+            detection.hash_tolerance > 0,
+            "detection.hash_tolerance should be positive",
+        );
+        check(
+            is_fraction(detection.match_threshold),
+            "detection.match_threshold should be in [0, 1]",
+        );
+        let weights = &detection.weights;
+        check(
+            [
+                weights.landscape,
+                weights.portrait,
+                weights.square,
+                weights.siglip2,
+                weights.video_thumbnail,
+                weights.video_keyframes,
+                weights.center_crop,
+            ]
+            .iter()
+            .all(|weight| weight.is_finite() && *weight >= 0.0),
+            "detection.weights should not be negative",
+        );
+        check(
+            detection
+                .center_crop_fraction
+                .is_none_or(|fraction| (0.1..=1.0).contains(&fraction)),
+            "detection.center_crop_fraction should be in [0.1, 1]",
+        );
+        let border_trim = &detection.border_trim;
+        check(
+            is_fraction(border_trim.min_uniform_fraction),
+            "detection.border_trim.min_uniform_fraction should be in [0, 1]",
+        );
+        check(
+            (0.0..0.5).contains(&border_trim.max_fraction),
+            "detection.border_trim.max_fraction should be in [0, 0.5)",
+        );
+        let siglip2 = &detection.siglip2;
+        check(
+            is_fraction(siglip2.min_similarity),
+            "detection.siglip2.min_similarity should be in [0, 1]",
+        );
+        check(
+            siglip2.top_k > 0,
+            "detection.siglip2.top_k should be positive",
+        );
+        check(
+            siglip2.ef_search >= siglip2.top_k,
+            "detection.siglip2.ef_search should not be less than top_k",
+        );
+        if let Some(model_path) = &siglip2.model_path {
+            check(
+                Path::new(model_path).exists(),
+                &format!("detection.siglip2.model_path {model_path} doesn't exist"),
+            );
+        }
+
+        check(
+            self.voting.min_votes_count > 0,
+            "voting.min_votes_count should be positive",
+        );
+        check(
+            self.video.keyframes_count > 0,
+            "video.keyframes_count should be positive",
+        );
+        check(
+            is_fraction(self.video.keyframes_min_similarity),
+            "video.keyframes_min_similarity should be in [0, 1]",
+        );
+        check(
+            self.retention.interval_minutes > 0,
+            "retention.interval_minutes should be positive",
+        );
+        check(
+            self.retention.batch_size > 0,
+            "retention.batch_size should be positive",
+        );
+        let telemetry = self.telemetry_settings();
+        check(
+            telemetry.mode != TelemetryMode::Otlp || telemetry.otlp_endpoint.is_some(),
+            "telemetry.otlp_endpoint (OTLP_ENDPOINT) is required in otlp mode",
+        );
+        if let Err(e) = otlp_metadata(&telemetry.otlp_headers) {
+            check(false, &format!("telemetry.otlp_headers: {e}"));
+        }
+        check(
+            telemetry.prometheus_path.starts_with('/'),
+            "telemetry.prometheus_path should start with /",
+        );
+        check(
+            self.limits.max_concurrent_updates > 0,
+            "limits.max_concurrent_updates should be positive",
+        );
+        check(
+            self.health.max_poll_age_seconds > 0,
+            "health.max_poll_age_seconds should be positive",
+        );
+        errors
+    }
+
+    pub fn chat_defaults(&self) -> ChatDefaults {
+        ChatDefaults {
+            hash_tolerance: self.detection.hash_tolerance,
+            search_distance_seconds: self.detection.search_distance_seconds,
+            min_votes_count: self.voting.min_votes_count,
+            mute_duration_seconds: Some(self.voting.mute_duration_seconds)
+                .filter(|duration| *duration > 0),
+        }
+    }
+
+    pub fn match_scoring(&self) -> MatchScoring {
+        let weights = &self.detection.weights;
+        MatchScoring {
+            landscape_weight: weights.landscape,
+            portrait_weight: weights.portrait,
+            square_weight: weights.square,
+            siglip2_weight: weights.siglip2,
+            video_thumbnail_weight: weights.video_thumbnail,
+            video_keyframes_weight: weights.video_keyframes,
+            center_crop_weight: weights.center_crop,
+            threshold: self.detection.match_threshold,
+            siglip2_min_similarity: self.detection.siglip2.min_similarity,
+            video_keyframes_min_similarity: self.video.keyframes_min_similarity,
+        }
+    }
+
+    /// Retention is enabled only if days are set
+    pub fn retention_config(&self) -> Option<RetentionConfig> {
+        if self.retention.days == 0 {
+            return None;
+        }
+        Some(
+            RetentionConfig::new(self.retention.days * 24 * 60 * 60)
+                .with_interval(std::time::Duration::from_secs(
+                    self.retention.interval_minutes * 60,
+                ))
+                .with_batch_size(self.retention.batch_size),
+        )
+    }
+
+    pub fn webhook_config(&self) -> Result<WebhookConfig, anyhow::Error> {
+        let webhook = &self.telegram.webhook;
+        let secret_token = webhook.secret_token.as_deref().ok_or(anyhow::format_err!(
+            "secret_token (WEBHOOK_SECRET_TOKEN) is required"
+        ))?;
+        WebhookConfig::new(
+            webhook.listen_addr,
+            &webhook.path,
+            webhook.url.clone(),
+            secret_token,
+        )
+    }
+
+    /// Health server is enabled only if address is set
+    pub fn health_config(&self) -> Option<HealthConfig> {
+        let max_poll_age = match self.telegram.delivery_mode {
+            DeliveryMode::Polling => Some(std::time::Duration::from_secs(
+                self.health.max_poll_age_seconds,
+            )),
+            DeliveryMode::Webhook => None,
+        };
+        self.health.listen_addr.map(|listen_addr| HealthConfig {
+            listen_addr,
+            max_poll_age,
+        })
+    }
+
+    pub fn telemetry_settings(&self) -> TelemetrySettings {
+        let telemetry = &self.telemetry;
+        let mode = telemetry.mode.unwrap_or(match telemetry.otlp_endpoint {
+            Some(_) => TelemetryMode::Otlp,
+            None => TelemetryMode::Stdout,
+        });
+        let mut headers: BTreeMap<String, String> = telemetry
+            .otlp_headers
+            .iter()
+            .map(|(name, value)| (name.to_lowercase(), value.clone()))
+            .collect();
+        if let Some(token) = &telemetry.otlp_token {
+            let defaults = [
+                ("authorization", format!("Basic {token}")),
+                ("organization", "default".to_owned()),
+                ("stream-name", "default".to_owned()),
+            ];
+            for (name, value) in defaults {
+                headers.entry(name.to_owned()).or_insert(value);
+            }
+        }
+        TelemetrySettings {
+            mode,
+            otlp_endpoint: telemetry.otlp_endpoint.clone(),
+            otlp_headers: headers,
+            prometheus_listen_addr: telemetry.prometheus_listen_addr,
+            prometheus_path: telemetry.prometheus_path.clone(),
+        }
+    }
+
+    /// Rayon pool of CLI tools, must be called before first parallel iterator
+    pub fn init_hashing_threads(&self) -> Result<(), anyhow::Error> {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.limits.hashing_threads)
+            .build_global()
+            .map_err(|e| anyhow::format_err!("Failed to start hashing threads: {e}"))
+    }
+}
+
+impl S3Settings {
+    fn find_missing(&self) -> Vec<String> {
+        [
+            (&self.endpoint, "storage.s3.endpoint (S3_ENDPOINT)"),
+            (&self.bucket, "storage.s3.bucket (S3_BUCKET)"),
+            (&self.access_key, "storage.s3.access_key (S3_ACCESS_KEY)"),
+            (&self.secret_key, "storage.s3.secret_key (S3_SECRET_KEY)"),
+        ]
+        .into_iter()
+        .filter(|(value, _)| value.is_none())
+        .map(|(_, name)| format!("{name} is required for S3 storage"))
+        .collect()
+    }
+}
+
+/// Optional env var, empty value is the same as not set
+pub fn optional_var(name: &str) -> Option<String> {
+    dotenvy::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Env values replacing config values, parse errors are collected
+struct EnvOverrides<'a> {
+    var: &'a dyn Fn(&str) -> Option<String>,
+    errors: Vec<String>,
+}
+
+impl EnvOverrides<'_> {
+    /// Empty value is the same as not set
+    fn value(&self, name: &str) -> Option<String> {
+        (self.var)(name).filter(|value| !value.is_empty())
+    }
+
+    fn set<T: FromStr>(&mut self, target: &mut T, name: &str)
+    where
+        T::Err: Display,
+    {
+        if let Some(value) = self.value(name) {
+            match T::from_str(&value) {
+                Ok(value) => *target = value,
+                Err(e) => self.errors.push(format!("Failed to parse {name}: {e}")),
+            }
+        }
+    }
+
+    fn set_optional<T: FromStr>(&mut self, target: &mut Option<T>, name: &str)
+    where
+        T::Err: Display,
+    {
+        if let Some(value) = self.value(name) {
+            match T::from_str(&value) {
+                Ok(value) => *target = Some(value),
+                Err(e) => self.errors.push(format!("Failed to parse {name}: {e}")),
+            }
+        }
+    }
+
+    fn set_bool(&mut self, target: &mut bool, name: &str) {
+        match self.value(name).as_deref() {
+            None => {}
+            Some("1" | "true" | "on") => *target = true,
+            Some("0" | "false" | "off") => *target = false,
+            Some(value) => self
+                .errors
+                .push(format!("Failed to parse {name}: `{value}` is not boolean")),
+        }
+    }
+}
+
+/// Indexer with hashing and matching options from config, so every tool hashes images the same way
+pub fn build_indexer(config: &Config) -> Result<PHashIndexer, anyhow::Error> {
+    configure_indexer(PHashIndexer::new(&config.database.path), config)
+}
+
+/// Same options without db, for tools which only hash and compare images
+pub fn build_hashing_indexer(config: &Config) -> Result<PHashIndexer, anyhow::Error> {
+    configure_indexer(PHashIndexer::hashing_only(), config)
+}
+
+fn configure_indexer(
+    indexer: PHashIndexer,
+    config: &Config,
+) -> Result<PHashIndexer, anyhow::Error> {
+    let detection = &config.detection;
+    let mut indexer = indexer
+        .with_scoring(config.match_scoring())
+        .with_transforms(detection.transforms.clone())
+        .with_chat_defaults(config.chat_defaults());
+    let border_trim = &detection.border_trim;
+    if border_trim.enabled {
+        let border_trimmer = BorderTrimmer::new(
+            border_trim.color_tolerance,
+            border_trim.min_uniform_fraction,
+            border_trim.max_fraction,
+        );
+        indexer = indexer.with_border_trimmer(border_trimmer);
+        tracing::info!("Border trimming enabled");
+    }
+    if let Some(fraction) = detection.center_crop_fraction {
+        indexer = indexer.with_center_crop(fraction);
+        tracing::info!("Center crop hashes enabled");
+    }
+    let siglip2 = &detection.siglip2;
+    if let Some(model_path) = &siglip2.model_path {
+        let search_params = EmbeddingSearchParams {
+            top_k: siglip2.top_k,
+            ef_search: siglip2.ef_search,
+        };
+        let siglip2 = Siglip2Indexer::new(Path::new(model_path), siglip2.min_similarity)
+            .map_err(|e| anyhow::format_err!("Failed to load siglip2 model: {e}"))?
+            .with_search_params(search_params);
+        indexer = indexer.with_siglip2(siglip2);
+        tracing::info!("Siglip2 embeddings enabled");
+    }
+    Ok(indexer)
+}
+
+/// Storage selected by backend, S3 by default
+pub fn build_storage(config: &StorageConfig) -> Result<AnyFileStorage, anyhow::Error> {
+    match config.backend {
+        StorageBackend::Local => {
+            let storage = LocalFileStorage::new(Path::new(&config.local_path))?;
+            tracing::info!("Local file storage enabled");
+            Ok(AnyFileStorage::Local(storage))
+        }
+        StorageBackend::S3 => {
+            let s3 = &config.s3;
+            let (Some(endpoint), Some(bucket), Some(access_key), Some(secret_key)) =
+                (&s3.endpoint, &s3.bucket, &s3.access_key, &s3.secret_key)
+            else {
+                return Err(anyhow::format_err!(
+                    "Incomplete S3 config:\n  {}",
+                    s3.find_missing().join("\n  ")
+                ));
+            };
+            Ok(AnyFileStorage::S3(S3FileStorage::new(
+                endpoint, bucket, access_key, secret_key,
+            )))
+        }
+    }
+}
+
+fn is_fraction(value: f32) -> bool {
+    (0.0..=1.0).contains(&value)
+}
+
+/// Transform names, `all` enables every one
+fn read_transforms<'a>(
+    names: impl Iterator<Item = &'a str>,
+) -> Result<Vec<Transform>, anyhow::Error> {
+    let mut transforms = vec![];
+    for name in names.map(str::trim) {
+        if name == "all" {
+            return Ok(Transform::ALL_VARIANTS.to_vec());
+        }
+        let transform = Transform::from_str(name)?;
+        if transform != Transform::Identity {
+            transforms.push(transform);
+        }
+    }
+    Ok(transforms)
+}
+
+/// Headers in `name=value,name=value` form
+fn read_headers(value: &str) -> Result<BTreeMap<String, String>, anyhow::Error> {
+    value
+        .split(',')
+        .filter(|header| !header.trim().is_empty())
+        .map(|header| {
+            let (name, value) = header
+                .split_once('=')
+                .ok_or(anyhow::format_err!("`{header}` should be name=value"))?;
+            Ok((name.trim().to_owned(), value.trim().to_owned()))
+        })
+        .collect()
+}
+
+fn parse_transforms<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Transform>, D::Error> {
+    let names = Vec::<String>::deserialize(deserializer)?;
+    read_transforms(names.iter().map(String::as_str)).map_err(serde::de::Error::custom)
+}
+
+fn parse_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = String::deserialize(deserializer)?;
+    T::from_str(&value).map_err(serde::de::Error::custom)
+}
+
+fn parse_optional_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    parse_str(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Config from TOML with given env vars instead of process env
+    fn load(toml: &str, vars: &[(&str, &str)]) -> (Config, Vec<String>) {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        let mut config = Config::from_toml(toml).unwrap();
+        let mut errors = config.apply_vars(&|name| vars.get(name).map(|value| value.to_string()));
+        errors.extend(config.find_errors());
+        (config, errors)
+    }
+
+    #[test]
+    fn empty_config_has_defaults() {
+        let (config, errors) = load("", &[]);
+
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(config.database.path, DEFAULT_DB_PATH);
+        assert_eq!(config.telegram.api_url, DEFAULT_TELEGRAM_API_URL);
+        assert_eq!(config.detection.hash_tolerance, PERCEPTIVE_HASH_TOLERANCE);
+        assert_eq!(config.detection.match_threshold, 0.95);
+        assert_eq!(
+            config.detection.siglip2.min_similarity,
+            SIGLIP2_MIN_SIMILARITY
+        );
+        assert_eq!(config.voting.min_votes_count, MIN_VOTES_COUNT);
+        assert_eq!(config.video.keyframes_count, DEFAULT_KEYFRAMES_COUNT);
+        assert!(!config.storage.hash_only);
+        assert!(config.retention_config().is_none());
+        assert!(config.health_config().is_none());
+    }
+
+    #[test]
+    fn toml_values_replace_defaults() {
+        let (config, errors) = load(
+            r#"
+            [detection]
+            hash_tolerance = 8
+            transforms = ["flip_h"]
+
+            [detection.weights]
+            siglip2 = 0.0
+
+            [voting]
+            min_votes_count = 3
+            "#,
+            &[],
+        );
+
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(config.detection.hash_tolerance, 8);
+        assert_eq!(config.detection.transforms, vec![Transform::FlipHorizontal]);
+        assert_eq!(config.match_scoring().siglip2_weight, 0.0);
+        assert_eq!(config.match_scoring().square_weight, 1.0);
+        assert_eq!(config.voting.min_votes_count, 3);
+    }
+
+    #[test]
+    fn env_overrides_toml() {
+        let (config, errors) = load(
+            r#"
+            [detection]
+            hash_tolerance = 8
+
+            [voting]
+            min_votes_count = 3
+            "#,
+            &[
+                ("HASH_TOLERANCE", "10"),
+                ("MIN_VOTES_COUNT", ""),
+                ("HASH_ONLY", "on"),
+                ("DB_PATH", "/tmp/hashes.db"),
+            ],
+        );
+
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(config.detection.hash_tolerance, 10);
+        // Empty value is the same as not set
+        assert_eq!(config.voting.min_votes_count, 3);
+        assert!(config.storage.hash_only);
+        assert_eq!(config.database.path, "/tmp/hashes.db");
+    }
+
+    #[test]
+    fn unknown_toml_field_is_rejected() {
+        assert!(Config::from_toml("[detection]\nhash_tolerace = 8").is_err());
+        assert!(Config::from_toml("[detection]\ntransforms = [\"skew\"]").is_err());
+    }
+
+    #[test]
+    fn invalid_values_are_reported_together() {
+        let (_, errors) = load(
+            r#"
+            [detection]
+            hash_tolerance = 0
+            match_threshold = 1.5
+
+            [detection.weights]
+            siglip2 = -1.0
+
+            [detection.siglip2]
+            top_k = 20
+            ef_search = 10
+            "#,
+            &[("HASH_ONLY", "maybe"), ("MIN_VOTES_COUNT", "many")],
+        );
+
+        for expected in [
+            "Failed to parse HASH_ONLY: `maybe` is not boolean",
+            "detection.hash_tolerance should be positive",
+            "detection.match_threshold should be in [0, 1]",
+            "detection.weights should not be negative",
+            "detection.siglip2.ef_search should not be less than top_k",
+        ] {
+            assert!(
+                errors.iter().any(|error| error == expected),
+                "{expected} not in {errors:?}"
+            );
+        }
+        assert!(
+            errors
+                .iter()
+                .any(|error| error.starts_with("Failed to parse MIN_VOTES_COUNT")),
+            "{errors:?}"
+        );
+    }
+}
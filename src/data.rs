@@ -47,3 +47,205 @@ impl FromStr for CallbackQueryData {
         Ok(CallbackQueryData { command, args })
     }
 }
+
+/// Split bot command text into command name without bot username and its arguments
+pub fn parse_bot_command(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim();
+    if !text.starts_with('/') {
+        return None;
+    }
+
+    let (command, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let command = command.split('@').next().unwrap_or(command);
+    Some((command, args.trim()))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SettingsCommand {
+    Show,
+    HashTolerance(usize),
+    SearchDistanceDays(u64),
+    MinVotesCount(i64),
+    MatchThreshold(Option<f32>),
+    Blockhash(bool),
+    Siglip2(bool),
+    Stickers(bool),
+    // None means forever
+    MuteDays(Option<u64>),
+    // None means global retention
+    RetentionDays(Option<u64>),
+    HashOnly(bool),
+}
+
+fn parse_switch(s: &str) -> Result<bool, anyhow::Error> {
+    match s {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(anyhow::format_err!("Switch should be on or off")),
+    }
+}
+
+impl FromStr for SettingsCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(args: &str) -> Result<Self, Self::Err> {
+        let mut iter = args.split_ascii_whitespace();
+
+        let Some(name) = iter.next() else {
+            return Ok(SettingsCommand::Show);
+        };
+        let value = iter
+            .next()
+            .ok_or(anyhow::format_err!("Setting value is missing"))?;
+        if iter.next().is_some() {
+            return Err(anyhow::format_err!("Too many arguments"));
+        }
+
+        match name {
+            "tolerance" => {
+                let tolerance = usize::from_str(value)?;
+                if !(1..=64).contains(&tolerance) {
+                    return Err(anyhow::format_err!("Tolerance should be in 1..=64"));
+                }
+                Ok(SettingsCommand::HashTolerance(tolerance))
+            }
+            "days" => {
+                let days = u64::from_str(value)?;
+                if !(1..=365).contains(&days) {
+                    return Err(anyhow::format_err!("Days should be in 1..=365"));
+                }
+                Ok(SettingsCommand::SearchDistanceDays(days))
+            }
+            "quorum" => {
+                let quorum = i64::from_str(value)?;
+                if quorum < 1 {
+                    return Err(anyhow::format_err!("Quorum should be positive"));
+                }
+                Ok(SettingsCommand::MinVotesCount(quorum))
+            }
+            "threshold" => {
+                if value == "default" {
+                    return Ok(SettingsCommand::MatchThreshold(None));
+                }
+                let threshold = f32::from_str(value)?;
+                if !(0.0..=1.0).contains(&threshold) {
+                    return Err(anyhow::format_err!("Threshold should be in 0..=1"));
+                }
+                Ok(SettingsCommand::MatchThreshold(Some(threshold)))
+            }
+            "mute" => {
+                if value == "forever" {
+                    return Ok(SettingsCommand::MuteDays(None));
+                }
+                let days = u64::from_str(value)?;
+                if !(1..=365).contains(&days) {
+                    return Err(anyhow::format_err!("Mute days should be in 1..=365"));
+                }
+                Ok(SettingsCommand::MuteDays(Some(days)))
+            }
+            "retention" => {
+                if value == "default" {
+                    return Ok(SettingsCommand::RetentionDays(None));
+                }
+                let days = u64::from_str(value)?;
+                if !(1..=3650).contains(&days) {
+                    return Err(anyhow::format_err!("Retention days should be in 1..=3650"));
+                }
+                Ok(SettingsCommand::RetentionDays(Some(days)))
+            }
+            "blockhash" => Ok(SettingsCommand::Blockhash(parse_switch(value)?)),
+            "siglip2" => Ok(SettingsCommand::Siglip2(parse_switch(value)?)),
+            "stickers" => Ok(SettingsCommand::Stickers(parse_switch(value)?)),
+            "hashonly" => Ok(SettingsCommand::HashOnly(parse_switch(value)?)),
+            _ => Err(anyhow::format_err!("Unknown setting {name}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<SettingsCommand, anyhow::Error> {
+        args.parse()
+    }
+
+    #[test]
+    fn settings_values_are_parsed() {
+        assert_eq!(parse("").unwrap(), SettingsCommand::Show);
+        assert_eq!(
+            parse("tolerance 7").unwrap(),
+            SettingsCommand::HashTolerance(7)
+        );
+        assert_eq!(
+            parse("days 30").unwrap(),
+            SettingsCommand::SearchDistanceDays(30)
+        );
+        assert_eq!(
+            parse("quorum 3").unwrap(),
+            SettingsCommand::MinVotesCount(3)
+        );
+        assert_eq!(
+            parse("threshold 0.9").unwrap(),
+            SettingsCommand::MatchThreshold(Some(0.9))
+        );
+        assert_eq!(
+            parse("threshold default").unwrap(),
+            SettingsCommand::MatchThreshold(None)
+        );
+        assert_eq!(
+            parse("blockhash off").unwrap(),
+            SettingsCommand::Blockhash(false)
+        );
+        assert_eq!(parse("siglip2 on").unwrap(), SettingsCommand::Siglip2(true));
+        assert_eq!(
+            parse("stickers on").unwrap(),
+            SettingsCommand::Stickers(true)
+        );
+        assert_eq!(
+            parse("mute forever").unwrap(),
+            SettingsCommand::MuteDays(None)
+        );
+        assert_eq!(
+            parse("retention 90").unwrap(),
+            SettingsCommand::RetentionDays(Some(90))
+        );
+        assert_eq!(
+            parse("hashonly on").unwrap(),
+            SettingsCommand::HashOnly(true)
+        );
+    }
+
+    #[test]
+    fn settings_out_of_range_are_rejected() {
+        for args in [
+            "tolerance 0",
+            "tolerance 65",
+            "days 0",
+            "days 366",
+            "quorum 0",
+            "quorum -1",
+            "threshold 1.5",
+            "threshold -0.1",
+            "threshold NaN",
+            "mute 0",
+            "retention 3651",
+        ] {
+            assert!(parse(args).is_err(), "{args} should be rejected");
+        }
+    }
+
+    #[test]
+    fn malformed_settings_are_rejected() {
+        for args in [
+            "tolerance",
+            "tolerance 5 6",
+            "tolerance five",
+            "blockhash yes",
+            "siglip2 1",
+            "colour red",
+        ] {
+            assert!(parse(args).is_err(), "{args} should be rejected");
+        }
+    }
+}
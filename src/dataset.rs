@@ -0,0 +1,237 @@
+//! Finished votings on alerts exported as labelled pairs for threshold tuning and model evaluation.
+//!
+//! NOTDUPE voting won by community marks alert as false positive, every other finished
+//! voting confirms that flagged image is duplicate of original.
+
+use std::{collections::HashMap, path::Path};
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    hasher::{CalculatedHash, PHashIndexer},
+    storage::{is_stored_file, local_storage::fnv1a, FileStorage},
+};
+
+pub const MANIFEST_FILE: &str = "manifest.jsonl";
+/// Pairs with both images in `img_eval` format
+pub const PAIRS_FILE: &str = "pairs.tsv";
+const IMAGES_DIR: &str = "images";
+/// Longer file ids are shortened, Telegram ones fit
+const MAX_IMAGE_NAME_LEN: usize = 96;
+
+/// Image of alert pair with hashes saved for it
+#[derive(Debug)]
+pub struct DatasetImage {
+    /// Unknown for alerts saved before flagged message was recorded
+    pub message_id: Option<i64>,
+    pub file_id: String,
+    /// Storage URL, export path or Telegram file id of hash-only chats
+    pub filename: Option<String>,
+    pub hashes: Vec<CalculatedHash>,
+}
+
+#[derive(Debug)]
+pub struct VotedAlert {
+    pub voting_id: i64,
+    pub chat_id: i64,
+    pub alert_message_id: i64,
+    pub voting_type: String,
+    pub votes_count: i64,
+    /// Sum of votes, positive if community agreed with voting
+    pub score: i64,
+    /// Label of pair, false for rejected false positives
+    pub duplicate: bool,
+    pub original: DatasetImage,
+    pub flagged: DatasetImage,
+}
+
+#[derive(Debug, Default)]
+pub struct ExportReport {
+    pub alerts: u64,
+    pub duplicates: u64,
+    pub false_positives: u64,
+    pub images: u64,
+    pub missing_images: u64,
+}
+
+/// Write manifest, pairs list and images of voted alerts into `output_dir`
+#[tracing::instrument(name = "Export dataset", skip(indexer, storage))]
+pub async fn export_dataset<T: FileStorage>(
+    indexer: &PHashIndexer,
+    storage: &T,
+    output_dir: &Path,
+) -> Result<ExportReport, anyhow::Error> {
+    let images_dir = output_dir.join(IMAGES_DIR);
+    tokio::fs::create_dir_all(&images_dir)
+        .await
+        .map_err(|e| anyhow::format_err!("Failed to create {}: {e}", images_dir.display()))?;
+
+    let alerts = indexer.find_voted_alerts().await?;
+    let mut report = ExportReport::default();
+    let mut manifest = String::new();
+    let mut pairs = String::new();
+    // Same original is usually flagged many times, it's saved once
+    let mut saved_images: HashMap<(i64, String), Option<String>> = HashMap::new();
+
+    for alert in &alerts {
+        let mut image_paths = vec![];
+        for image in [&alert.original, &alert.flagged] {
+            let key = (alert.chat_id, image.file_id.clone());
+            let path = match saved_images.get(&key) {
+                Some(path) => path.clone(),
+                None => {
+                    let path = save_image(storage, alert.chat_id, image, output_dir).await;
+                    match &path {
+                        Some(_) => report.images += 1,
+                        None => report.missing_images += 1,
+                    }
+                    saved_images.insert(key, path.clone());
+                    path
+                }
+            };
+            image_paths.push(path);
+        }
+        let [original_path, flagged_path] = &image_paths[..] else {
+            unreachable!("Alert has two images");
+        };
+
+        let line = json!({
+            "voting_id": alert.voting_id,
+            "chat_id": alert.chat_id,
+            "alert_message_id": alert.alert_message_id,
+            "voting_type": alert.voting_type,
+            "votes_count": alert.votes_count,
+            "score": alert.score,
+            "label": if alert.duplicate { "duplicate" } else { "not_duplicate" },
+            "original": image_json(&alert.original, original_path.as_deref()),
+            "flagged": image_json(&alert.flagged, flagged_path.as_deref()),
+        });
+        manifest.push_str(&line.to_string());
+        manifest.push('\n');
+
+        if let (Some(original_path), Some(flagged_path)) = (original_path, flagged_path) {
+            let label = if alert.duplicate { "dup" } else { "diff" };
+            pairs.push_str(&format!("{label}\t{original_path}\t{flagged_path}\n"));
+        }
+
+        report.alerts += 1;
+        if alert.duplicate {
+            report.duplicates += 1;
+        } else {
+            report.false_positives += 1;
+        }
+    }
+
+    for (file, content) in [(MANIFEST_FILE, manifest), (PAIRS_FILE, pairs)] {
+        tokio::fs::write(output_dir.join(file), content)
+            .await
+            .map_err(|e| anyhow::format_err!("Failed to write {file}: {e}"))?;
+    }
+    Ok(report)
+}
+
+/// Copy image into dataset, returns its path relative to `output_dir`
+async fn save_image<T: FileStorage>(
+    storage: &T,
+    chat_id: i64,
+    image: &DatasetImage,
+    output_dir: &Path,
+) -> Option<String> {
+    let filename = image.filename.as_deref()?;
+    let data = if is_stored_file(filename) {
+        storage
+            .load_raw_file(filename)
+            .await
+            .map_err(|e| tracing::warn!("Failed to load {filename}: {e}"))
+            .ok()?
+    } else if Path::new(filename).is_file() {
+        // Imported from chat export
+        tokio::fs::read(filename)
+            .await
+            .map_err(|e| tracing::warn!("Failed to read {filename}: {e}"))
+            .ok()?
+    } else {
+        // Hash-only chats keep nothing
+        return None;
+    };
+
+    let extension = match image::guess_format(&data) {
+        Ok(format) => format.extensions_str().first().copied(),
+        // Video alerts keep thumbnail or video file
+        Err(_) => Path::new(filename)
+            .extension()
+            .and_then(|extension| extension.to_str()),
+    }
+    .unwrap_or("bin");
+    let path = format!(
+        "{IMAGES_DIR}/{chat_id}_{}.{extension}",
+        image_name(&image.file_id)
+    );
+    match tokio::fs::write(output_dir.join(&path), data).await {
+        Ok(()) => Some(path),
+        Err(e) => {
+            tracing::error!("Failed to write {path}: {e}");
+            None
+        }
+    }
+}
+
+/// Telegram file ids are kept as is, other ids like paths of old indexer rows are
+/// sanitized and suffixed with their hash, so they stay unique and inside `IMAGES_DIR`
+fn image_name(file_id: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if !file_id.is_empty() && file_id.len() <= MAX_IMAGE_NAME_LEN && file_id.chars().all(is_safe) {
+        return file_id.to_owned();
+    }
+
+    // End of path has file name, it's the most readable part
+    let tail_start = file_id
+        .char_indices()
+        .rev()
+        .nth(MAX_IMAGE_NAME_LEN - 1)
+        .map(|(index, _)| index)
+        .unwrap_or(0);
+    let sanitized: String = file_id[tail_start..]
+        .chars()
+        .map(|c| if is_safe(c) { c } else { '_' })
+        .collect();
+    let hash = fnv1a(file_id.as_bytes());
+    format!("{}_{hash:016x}", sanitized.trim_start_matches('_'))
+}
+
+fn image_json(image: &DatasetImage, path: Option<&str>) -> Value {
+    let hashes: Map<String, Value> = image
+        .hashes
+        .iter()
+        .map(|hash| (hash.hash_type.as_str().to_owned(), json!(hash.hash)))
+        .collect();
+    json!({
+        "message_id": image.message_id,
+        "file_id": image.file_id,
+        "image": path,
+        "hashes": hashes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn telegram_file_id_is_kept() {
+        assert_eq!(
+            image_name("AgACAgIAAxkBAAIB-2Zk_abc"),
+            "AgACAgIAAxkBAAIB-2Zk_abc"
+        );
+    }
+
+    #[test]
+    fn path_file_id_stays_inside_images_dir() {
+        let name = image_name("/home/user/../pics/cat 1.png");
+
+        assert!(name.starts_with("home_user____pics_cat_1_png_"), "{name}");
+        assert!(!name.contains('/') && !name.contains('.'));
+        assert_ne!(name, image_name("/home/user/../pics/cat_1.png"));
+        assert!(image_name(&"a/".repeat(200)).len() <= MAX_IMAGE_NAME_LEN + 17);
+    }
+}
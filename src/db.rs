@@ -1,4 +1,8 @@
 use image_hasher::ImageHash;
+use migration::sea_orm::{
+    sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    SqlxSqliteConnector,
+};
 use rusqlite::{
     functions::{Context, FunctionFlags},
     Connection,
@@ -6,6 +10,26 @@ use rusqlite::{
 
 use crate::siglip2;
 
+pub const DEFAULT_DB_PATH: &str = "./hashes.db";
+
+pub async fn apply_migrations(db_path: &str) {
+    use migration::{Migrator, MigratorTrait};
+    let opts = SqliteConnectOptions::new()
+        .filename(db_path)
+        .create_if_missing(true);
+
+    // Table rebuilding migrations must see their own schema changes
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(opts)
+        .await
+        .expect("Failed to connect to apply migrations");
+    let db = SqlxSqliteConnector::from_sqlx_sqlite_pool(pool);
+    Migrator::up(&db, None)
+        .await
+        .expect("Failed to apply transactions");
+}
+
 pub fn create_db(path: &str) -> Result<Connection, ()> {
     // Connect to the SQLite database
     let conn = Connection::open(path).map_err(|_| ())?;
@@ -49,25 +73,31 @@ fn hamming_sqlite_func(ctx: &Context) -> Result<i64, rusqlite::Error> {
 
 #[inline]
 fn cosine_similarity_normalized_func(ctx: &Context) -> Result<f32, rusqlite::Error> {
-    use base64::engine::general_purpose::STANDARD;
-    use base64::Engine;
     let embedding1_str: String = ctx.get(0)?;
     let embedding2_str: String = ctx.get(1)?;
 
-    let bytes_1 = STANDARD.decode(embedding1_str).map_err(|e| {
-        rusqlite::Error::UserFunctionError(format!("Invalid Base64 in arg 1: {}", e).into())
+    let embedding_1 = siglip2::embedding_from_base64(&embedding1_str).map_err(|e| {
+        rusqlite::Error::UserFunctionError(format!("Invalid embedding in arg 1: {}", e).into())
     })?;
 
-    let bytes_2 = STANDARD.decode(embedding2_str).map_err(|e| {
-        rusqlite::Error::UserFunctionError(format!("Invalid Base64 in arg 2: {}", e).into())
+    let embedding_2 = siglip2::embedding_from_base64(&embedding2_str).map_err(|e| {
+        rusqlite::Error::UserFunctionError(format!("Invalid embedding in arg 2: {}", e).into())
     })?;
 
-    let embedding_1: &[f32] = bytemuck::cast_slice(&bytes_1);
-    let embedding_2: &[f32] = bytemuck::cast_slice(&bytes_2);
+    if embedding_1.len() != embedding_2.len() {
+        return Err(rusqlite::Error::UserFunctionError(
+            format!(
+                "Embedding size mismatch: {} != {}",
+                embedding_1.len(),
+                embedding_2.len()
+            )
+            .into(),
+        ));
+    }
 
     Ok(siglip2::cosine_similarity_normalized(
-        embedding_1,
-        embedding_2,
+        &embedding_1,
+        &embedding_2,
     ))
 }
 
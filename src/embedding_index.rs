@@ -0,0 +1,919 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use rusqlite::Connection;
+
+use crate::siglip2;
+
+/// Max neighbours per node on upper layers, twice as many on layer 0
+const HNSW_M: usize = 16;
+const HNSW_EF_CONSTRUCTION: usize = 100;
+/// Unsaved inserts per shard before it is written to disk
+const SHARD_FLUSH_THRESHOLD: usize = 64;
+/// Every chat gets a separate graph per week of posts
+pub const SHARD_WINDOW_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+const SHARD_MAGIC: &[u8; 6] = b"HNSW01";
+/// Upper bound of stored embedding size, larger values mean broken file
+const MAX_DIMENSION: usize = 16384;
+/// Per-chat first kept window, older shards were evicted and aren't rebuilt from db
+const EVICTED_FILE: &str = "evicted";
+
+/// Search knobs: higher `ef_search` gives better recall for more latency
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddingSearchParams {
+    pub top_k: usize,
+    pub ef_search: usize,
+}
+
+impl Default for EmbeddingSearchParams {
+    fn default() -> Self {
+        Self {
+            top_k: 10,
+            ef_search: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: f32,
+    node: u32,
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.node.cmp(&other.node))
+    }
+}
+
+struct HnswNode {
+    id: i32,
+    created_at: u64,
+    vector: Vec<f32>,
+    // neighbours per layer, layer 0 first
+    neighbors: Vec<Vec<u32>>,
+    deleted: bool,
+}
+
+/// HNSW graph over L2-normalized embeddings with `1 - cosine` distance
+#[derive(Default)]
+struct Hnsw {
+    nodes: Vec<HnswNode>,
+    entry_point: Option<u32>,
+    dirty: usize,
+}
+
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    1.0 - siglip2::cosine_similarity_normalized(a, b)
+}
+
+/// Deterministic layer for the node so rebuilding from db yields the same graph
+fn random_level(id: i32) -> usize {
+    // splitmix64
+    let mut x = (id as u64).wrapping_add(0x9E3779B97F4A7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+    x ^= x >> 31;
+    let uniform = ((x >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+    (-uniform.ln() / (HNSW_M as f64).ln()).floor() as usize
+}
+
+impl Hnsw {
+    fn dimension(&self) -> Option<usize> {
+        self.nodes.first().map(|node| node.vector.len())
+    }
+
+    fn top_level(&self) -> usize {
+        self.entry_point
+            .map(|entry| self.nodes[entry as usize].neighbors.len() - 1)
+            .unwrap_or(0)
+    }
+
+    fn search_layer(&self, query: &[f32], entry: u32, ef: usize, layer: usize) -> Vec<Candidate> {
+        let mut visited = HashSet::from([entry]);
+        let entry = Candidate {
+            distance: distance(query, &self.nodes[entry as usize].vector),
+            node: entry,
+        };
+        let mut candidates = BinaryHeap::from([Reverse(entry)]);
+        let mut found = BinaryHeap::from([entry]);
+
+        while let Some(Reverse(current)) = candidates.pop() {
+            let furthest = found.peek().map(|c| c.distance).unwrap_or(f32::MAX);
+            if current.distance > furthest && found.len() >= ef {
+                break;
+            }
+
+            let Some(neighbors) = self.nodes[current.node as usize].neighbors.get(layer) else {
+                continue;
+            };
+            for &neighbor in neighbors {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+                let neighbor_distance = distance(query, &self.nodes[neighbor as usize].vector);
+                let furthest = found.peek().map(|c| c.distance).unwrap_or(f32::MAX);
+                if found.len() < ef || neighbor_distance < furthest {
+                    let candidate = Candidate {
+                        distance: neighbor_distance,
+                        node: neighbor,
+                    };
+                    candidates.push(Reverse(candidate));
+                    found.push(candidate);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        found.into_sorted_vec()
+    }
+
+    fn greedy_descend(&self, query: &[f32], from_layer: usize, to_layer: usize) -> Option<u32> {
+        let mut entry = self.entry_point?;
+        for layer in (to_layer + 1..=from_layer).rev() {
+            if let Some(nearest) = self.search_layer(query, entry, 1, layer).first() {
+                entry = nearest.node;
+            }
+        }
+        Some(entry)
+    }
+
+    fn insert(&mut self, id: i32, created_at: u64, vector: Vec<f32>) {
+        let level = random_level(id);
+        let node = self.nodes.len() as u32;
+        let top_level = self.top_level();
+        let entry = self.greedy_descend(&vector, top_level, level);
+
+        self.nodes.push(HnswNode {
+            id,
+            created_at,
+            vector,
+            neighbors: vec![vec![]; level + 1],
+            deleted: false,
+        });
+        self.dirty += 1;
+
+        let Some(mut entry) = entry else {
+            self.entry_point = Some(node);
+            return;
+        };
+
+        for layer in (0..=level.min(top_level)).rev() {
+            let query = &self.nodes[node as usize].vector;
+            let found = self.search_layer(query, entry, HNSW_EF_CONSTRUCTION, layer);
+            let max_neighbors = if layer == 0 { HNSW_M * 2 } else { HNSW_M };
+
+            let neighbors: Vec<u32> = found
+                .iter()
+                .take(max_neighbors)
+                .map(|candidate| candidate.node)
+                .collect();
+            for &neighbor in &neighbors {
+                self.link(neighbor, node, layer, max_neighbors);
+            }
+            self.nodes[node as usize].neighbors[layer] = neighbors;
+
+            if let Some(nearest) = found.first() {
+                entry = nearest.node;
+            }
+        }
+
+        if level > top_level {
+            self.entry_point = Some(node);
+        }
+    }
+
+    /// Add back link to `node`, keep only the closest neighbours on overflow
+    fn link(&mut self, from: u32, node: u32, layer: usize, max_neighbors: usize) {
+        self.nodes[from as usize].neighbors[layer].push(node);
+        if self.nodes[from as usize].neighbors[layer].len() <= max_neighbors {
+            return;
+        }
+
+        let from_vector = &self.nodes[from as usize].vector;
+        let mut neighbors: Vec<Candidate> = self.nodes[from as usize].neighbors[layer]
+            .iter()
+            .map(|&neighbor| Candidate {
+                distance: distance(from_vector, &self.nodes[neighbor as usize].vector),
+                node: neighbor,
+            })
+            .collect();
+        neighbors.sort();
+        neighbors.truncate(max_neighbors);
+        self.nodes[from as usize].neighbors[layer] =
+            neighbors.into_iter().map(|c| c.node).collect();
+    }
+
+    fn search(&self, query: &[f32], ef_search: usize) -> Vec<&HnswNode> {
+        let Some(entry) = self.greedy_descend(query, self.top_level(), 0) else {
+            return vec![];
+        };
+        self.search_layer(query, entry, ef_search, 0)
+            .into_iter()
+            .map(|candidate| &self.nodes[candidate.node as usize])
+            .filter(|node| !node.deleted)
+            .collect()
+    }
+
+    fn write(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(fs::File::create(&tmp_path)?);
+            writer.write_all(SHARD_MAGIC)?;
+            writer.write_all(&(self.dimension().unwrap_or(0) as u32).to_le_bytes())?;
+            writer.write_all(&(self.nodes.len() as u32).to_le_bytes())?;
+            writer.write_all(&self.entry_point.unwrap_or(u32::MAX).to_le_bytes())?;
+            for node in &self.nodes {
+                writer.write_all(&node.id.to_le_bytes())?;
+                writer.write_all(&node.created_at.to_le_bytes())?;
+                writer.write_all(&[node.deleted as u8, node.neighbors.len() as u8])?;
+                writer.write_all(bytemuck::cast_slice(&node.vector))?;
+                for neighbors in &node.neighbors {
+                    writer.write_all(&(neighbors.len() as u32).to_le_bytes())?;
+                    for neighbor in neighbors {
+                        writer.write_all(&neighbor.to_le_bytes())?;
+                    }
+                }
+            }
+            writer.flush()?;
+        }
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Shard file is validated, so broken or foreign file can't make search panic
+    fn read(path: &Path) -> Result<Self, anyhow::Error> {
+        let file = fs::File::open(path)?;
+        let file_size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0u8; 6];
+        reader.read_exact(&mut magic)?;
+        if &magic != SHARD_MAGIC {
+            return Err(anyhow::format_err!("Unknown shard format"));
+        }
+        let dimension = read_u32(&mut reader)? as usize;
+        let count = read_u32(&mut reader)? as usize;
+        let entry_point = Some(read_u32(&mut reader)?).filter(|entry| *entry != u32::MAX);
+
+        if count > 0 && !(1..=MAX_DIMENSION).contains(&dimension) {
+            return Err(anyhow::format_err!("Invalid dimension {dimension}"));
+        }
+        // id, created_at, flags, vector and at least one layer length per node
+        let min_node_size = (4 + 8 + 2 + 4 + dimension * std::mem::size_of::<f32>()) as u64;
+        if count as u64 * min_node_size > file_size {
+            return Err(anyhow::format_err!("Node count {count} exceeds file size"));
+        }
+        if entry_point.is_none() != (count == 0) {
+            return Err(anyhow::format_err!("Broken entry point"));
+        }
+
+        let mut nodes = Vec::with_capacity(count);
+        for _ in 0..count {
+            let id = read_u32(&mut reader)? as i32;
+            let mut created_at = [0u8; 8];
+            reader.read_exact(&mut created_at)?;
+            let mut flags = [0u8; 2];
+            reader.read_exact(&mut flags)?;
+            if flags[1] == 0 {
+                return Err(anyhow::format_err!("Node {id} has no layers"));
+            }
+            let mut vector = vec![0u8; dimension * std::mem::size_of::<f32>()];
+            reader.read_exact(&mut vector)?;
+
+            let mut neighbors = Vec::with_capacity(flags[1] as usize);
+            for _ in 0..flags[1] {
+                let neighbors_count = read_u32(&mut reader)? as usize;
+                if neighbors_count > count {
+                    return Err(anyhow::format_err!("Node {id} has too many neighbours"));
+                }
+                let mut layer = Vec::with_capacity(neighbors_count);
+                for _ in 0..neighbors_count {
+                    layer.push(read_u32(&mut reader)?);
+                }
+                neighbors.push(layer);
+            }
+
+            nodes.push(HnswNode {
+                id,
+                created_at: u64::from_le_bytes(created_at),
+                vector: bytemuck::pod_collect_to_vec(&vector),
+                neighbors,
+                deleted: flags[0] != 0,
+            });
+        }
+
+        if entry_point.is_some_and(|entry| entry as usize >= nodes.len()) {
+            return Err(anyhow::format_err!("Broken entry point"));
+        }
+        // Neighbour on a layer must exist and have that layer itself
+        for node in &nodes {
+            for (layer, neighbors) in node.neighbors.iter().enumerate() {
+                if neighbors.iter().any(|&neighbor| {
+                    nodes
+                        .get(neighbor as usize)
+                        .is_none_or(|neighbor| neighbor.neighbors.len() <= layer)
+                }) {
+                    return Err(anyhow::format_err!(
+                        "Node {} has broken neighbours",
+                        node.id
+                    ));
+                }
+            }
+        }
+
+        Ok(Self {
+            nodes,
+            entry_point,
+            dirty: 0,
+        })
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, std::io::Error> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Found embedding with exact cosine similarity
+#[derive(Debug)]
+pub struct EmbeddingMatch {
+    pub id: i32,
+    pub similarity: f32,
+}
+
+/// Approximate nearest neighbour index of SigLIP2 embeddings.
+///
+/// Graphs are sharded per chat and per `SHARD_WINDOW_SECONDS` and stored as
+/// `<chat_id>_<window>.hnsw` files in a directory next to the db. Shards which
+/// fell out of chat search window are evicted, see `set_search_window`.
+pub struct EmbeddingIndex {
+    dir: PathBuf,
+    shards: HashMap<(i64, u64), Hnsw>,
+    locations: HashMap<i32, (i64, u64, u32)>,
+    // First window kept per chat, only chats with evicted shards are present
+    evicted_before: HashMap<i64, u64>,
+}
+
+impl EmbeddingIndex {
+    /// Index without shards for indexer which never saves hashes
+    pub(crate) fn empty() -> Self {
+        Self {
+            dir: PathBuf::new(),
+            shards: HashMap::new(),
+            locations: HashMap::new(),
+            evicted_before: HashMap::new(),
+        }
+    }
+
+    /// Load saved shards and add embeddings from db which are not there yet
+    #[tracing::instrument(name = "Load embedding index", skip(conn))]
+    pub fn load(dir: &Path, conn: &Connection) -> Result<Self, anyhow::Error> {
+        let mut index = Self {
+            dir: dir.to_path_buf(),
+            shards: HashMap::new(),
+            locations: HashMap::new(),
+            evicted_before: read_evicted(&dir.join(EVICTED_FILE)),
+        };
+
+        if dir.is_dir() {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("hnsw") {
+                    continue;
+                }
+                let Some(key) = path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .and_then(parse_shard_name)
+                else {
+                    continue;
+                };
+                if index.is_evicted(key.0, key.1) {
+                    index.remove_shard_file(key.0, key.1);
+                    continue;
+                }
+                match Hnsw::read(&path) {
+                    Ok(shard) => {
+                        for (node_idx, node) in shard.nodes.iter().enumerate() {
+                            if !node.deleted {
+                                index
+                                    .locations
+                                    .insert(node.id, (key.0, key.1, node_idx as u32));
+                            }
+                        }
+                        index.shards.insert(key, shard);
+                    }
+                    Err(e) => {
+                        // Broken shard is rebuilt from db below
+                        tracing::warn!("Failed to read shard {}: {e}", path.display());
+                        index.remove_shard_file(key.0, key.1);
+                    }
+                }
+            }
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT id, chat_id, base64_hash, created_at FROM hashes WHERE orientation = 'siglip2' ORDER BY id",
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut stored_ids = HashSet::new();
+        let mut added = 0;
+        while let Some(row) = rows.next()? {
+            let id: i32 = row.get(0)?;
+            stored_ids.insert(id);
+            if index.locations.contains_key(&id) {
+                continue;
+            }
+            let embedding: String = row.get(2)?;
+            match siglip2::embedding_from_base64(&embedding) {
+                Ok(embedding) => {
+                    added += index.add(id, row.get(1)?, embedding, row.get(3)?) as usize;
+                }
+                Err(e) => tracing::warn!("Skip invalid embedding {id}: {e}"),
+            }
+        }
+
+        // Rows removed from db while index was offline
+        let removed_ids: Vec<i32> = index
+            .locations
+            .keys()
+            .filter(|id| !stored_ids.contains(id))
+            .copied()
+            .collect();
+        for id in removed_ids {
+            index.remove(id);
+        }
+
+        index.flush()?;
+        tracing::info!(
+            "Embedding index loaded {} embeddings, {added} added from db",
+            index.locations.len()
+        );
+        Ok(index)
+    }
+
+    fn is_evicted(&self, chat_id: i64, window: u64) -> bool {
+        self.evicted_before
+            .get(&chat_id)
+            .is_some_and(|first_window| window < *first_window)
+    }
+
+    fn remove_shard_file(&self, chat_id: i64, window: u64) {
+        let path = self.dir.join(format!("{chat_id}_{window}.hnsw"));
+        if let Err(e) = fs::remove_file(&path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("Failed to remove shard {}: {e}", path.display());
+            }
+        }
+    }
+
+    /// Keep only shards searchable from `from_timestamp`.
+    ///
+    /// Older shards are dropped from memory and disk, shards evicted before
+    /// are rebuilt from db if search window of chat grew since then.
+    #[tracing::instrument(name = "Set embedding search window", skip(self, conn))]
+    pub fn set_search_window(
+        &mut self,
+        conn: &Connection,
+        chat_id: i64,
+        from_timestamp: u64,
+    ) -> Result<(), anyhow::Error> {
+        let first_window = from_timestamp / SHARD_WINDOW_SECONDS;
+
+        let expired: Vec<(i64, u64)> = self
+            .shards
+            .keys()
+            .filter(|(shard_chat_id, window)| *shard_chat_id == chat_id && *window < first_window)
+            .copied()
+            .collect();
+        for (chat_id, window) in &expired {
+            if let Some(shard) = self.shards.remove(&(*chat_id, *window)) {
+                for node in shard.nodes {
+                    self.locations.remove(&node.id);
+                }
+            }
+            self.remove_shard_file(*chat_id, *window);
+        }
+
+        let evicted_before = self.evicted_before.get(&chat_id).copied().unwrap_or(0);
+        if evicted_before == first_window || (expired.is_empty() && evicted_before < first_window) {
+            return Ok(());
+        }
+        self.evicted_before.insert(chat_id, first_window);
+
+        let mut restored = 0;
+        if evicted_before > first_window {
+            let mut stmt = conn.prepare(
+                "SELECT id, base64_hash, created_at FROM hashes WHERE orientation = 'siglip2' AND chat_id = ? AND created_at >= ? AND created_at < ? ORDER BY id",
+            )?;
+            let mut rows = stmt.query(rusqlite::params![
+                chat_id,
+                first_window * SHARD_WINDOW_SECONDS,
+                evicted_before * SHARD_WINDOW_SECONDS
+            ])?;
+            while let Some(row) = rows.next()? {
+                let id: i32 = row.get(0)?;
+                if self.locations.contains_key(&id) {
+                    continue;
+                }
+                let embedding: String = row.get(1)?;
+                match siglip2::embedding_from_base64(&embedding) {
+                    Ok(embedding) => {
+                        restored += self.add(id, chat_id, embedding, row.get(2)?) as usize;
+                    }
+                    Err(e) => tracing::warn!("Skip invalid embedding {id}: {e}"),
+                }
+            }
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        write_evicted(&self.dir.join(EVICTED_FILE), &self.evicted_before)?;
+        tracing::info!(
+            "Evicted {} shards of chat {chat_id}, {restored} embeddings restored",
+            expired.len()
+        );
+        Ok(())
+    }
+
+    /// Returns false if embedding isn't indexed
+    fn add(&mut self, id: i32, chat_id: i64, embedding: Vec<f32>, created_at: u64) -> bool {
+        let window = created_at / SHARD_WINDOW_SECONDS;
+        // Too old to be searched, it's restored from db if search window grows
+        if self.is_evicted(chat_id, window) {
+            return false;
+        }
+        let shard = self.shards.entry((chat_id, window)).or_default();
+        if shard
+            .dimension()
+            .is_some_and(|dimension| dimension != embedding.len())
+        {
+            tracing::warn!("Skip embedding {id} with wrong dimension");
+            return false;
+        }
+        shard.insert(id, created_at, embedding);
+        self.locations
+            .insert(id, (chat_id, window, shard.nodes.len() as u32 - 1));
+        true
+    }
+
+    pub fn insert(&mut self, id: i32, chat_id: i64, embedding: &str, created_at: u64) {
+        let embedding = match siglip2::embedding_from_base64(embedding) {
+            Ok(embedding) => embedding,
+            Err(e) => {
+                tracing::warn!("Skip invalid embedding {id}: {e}");
+                return;
+            }
+        };
+        self.add(id, chat_id, embedding, created_at);
+
+        let window = created_at / SHARD_WINDOW_SECONDS;
+        if self
+            .shards
+            .get(&(chat_id, window))
+            .is_some_and(|shard| shard.dirty >= SHARD_FLUSH_THRESHOLD)
+        {
+            if let Err(e) = self.flush_shard(chat_id, window) {
+                tracing::error!("Failed to save embedding shard: {e}");
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: i32) {
+        if let Some((chat_id, window, node)) = self.locations.remove(&id) {
+            if let Some(shard) = self.shards.get_mut(&(chat_id, window)) {
+                shard.nodes[node as usize].deleted = true;
+                shard.dirty += 1;
+            }
+        }
+    }
+
+    /// Top `top_k` embeddings posted after `from_timestamp` with similarity above `min_similarity`
+    pub fn find(
+        &self,
+        chat_id: i64,
+        embedding: &str,
+        min_similarity: f32,
+        from_timestamp: u64,
+        params: EmbeddingSearchParams,
+    ) -> Vec<EmbeddingMatch> {
+        let query = match siglip2::embedding_from_base64(embedding) {
+            Ok(query) => query,
+            Err(e) => {
+                tracing::warn!("Invalid query embedding: {e}");
+                return vec![];
+            }
+        };
+
+        let first_window = from_timestamp / SHARD_WINDOW_SECONDS;
+        let mut found: Vec<EmbeddingMatch> = self
+            .shards
+            .iter()
+            .filter(|((shard_chat_id, window), _)| {
+                *shard_chat_id == chat_id && *window >= first_window
+            })
+            .filter(|(_, shard)| shard.dimension() == Some(query.len()))
+            .flat_map(|(_, shard)| shard.search(&query, params.ef_search.max(params.top_k)))
+            .filter(|node| node.created_at > from_timestamp)
+            // Graph distances are only used for candidates, rank by exact similarity
+            .map(|node| EmbeddingMatch {
+                id: node.id,
+                similarity: siglip2::cosine_similarity_normalized(&query, &node.vector),
+            })
+            .filter(|found| found.similarity > min_similarity)
+            .collect();
+
+        found.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        found.truncate(params.top_k);
+        found
+    }
+
+    fn flush_shard(&mut self, chat_id: i64, window: u64) -> Result<(), anyhow::Error> {
+        let Some(shard) = self.shards.get_mut(&(chat_id, window)) else {
+            return Ok(());
+        };
+        fs::create_dir_all(&self.dir)?;
+        shard.write(&self.dir.join(format!("{chat_id}_{window}.hnsw")))?;
+        shard.dirty = 0;
+        Ok(())
+    }
+
+    /// Save every shard with unsaved changes
+    pub fn flush(&mut self) -> Result<(), anyhow::Error> {
+        let dirty: Vec<(i64, u64)> = self
+            .shards
+            .iter()
+            .filter(|(_, shard)| shard.dirty > 0)
+            .map(|(key, _)| *key)
+            .collect();
+        for (chat_id, window) in dirty {
+            self.flush_shard(chat_id, window)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_shard_name(name: &str) -> Option<(i64, u64)> {
+    let (chat_id, window) = name.rsplit_once('_')?;
+    Some((chat_id.parse().ok()?, window.parse().ok()?))
+}
+
+/// `<chat_id> <first_window>` lines, missing or broken file means nothing was evicted
+fn read_evicted(path: &Path) -> HashMap<i64, u64> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return HashMap::new();
+    };
+    content
+        .lines()
+        .filter_map(|line| {
+            let (chat_id, window) = line.split_once(' ')?;
+            Some((chat_id.parse().ok()?, window.parse().ok()?))
+        })
+        .collect()
+}
+
+fn write_evicted(path: &Path, evicted_before: &HashMap<i64, u64>) -> Result<(), anyhow::Error> {
+    let content: String = evicted_before
+        .iter()
+        .map(|(chat_id, window)| format!("{chat_id} {window}\n"))
+        .collect();
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, content)?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::create_db, test_utils::Random};
+
+    const DIMENSION: usize = 16;
+    const CHAT_ID: i64 = -100;
+
+    /// Normalized vector with components in [-1, 1]
+    fn random_vector(random: &mut Random) -> Vec<f32> {
+        let mut vector: Vec<f32> = (0..DIMENSION)
+            .map(|_| random.below(2001) as f32 / 1000.0 - 1.0)
+            .collect();
+        siglip2::l2_normalize(&mut vector);
+        vector
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("img_hnsw_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn create_hashes_table(conn: &Connection) {
+        conn.execute_batch(
+            "CREATE TABLE hashes (
+                id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+                chat_id integer NOT NULL,
+                orientation varchar NOT NULL,
+                base64_hash varchar NOT NULL,
+                created_at integer NOT NULL
+            )",
+        )
+        .unwrap();
+    }
+
+    fn insert_embedding(conn: &Connection, embedding: &[f32], created_at: u64) -> i32 {
+        conn.execute(
+            "INSERT INTO hashes(chat_id, orientation, base64_hash, created_at) VALUES(?, 'siglip2', ?, ?)",
+            rusqlite::params![CHAT_ID, siglip2::embedding_to_base64(embedding), created_at],
+        )
+        .unwrap();
+        conn.last_insert_rowid() as i32
+    }
+
+    fn found_ids(index: &EmbeddingIndex, query: &[f32], from_timestamp: u64) -> Vec<i32> {
+        let mut ids: Vec<i32> = index
+            .find(
+                CHAT_ID,
+                &siglip2::embedding_to_base64(query),
+                -1.0,
+                from_timestamp,
+                EmbeddingSearchParams {
+                    top_k: 100,
+                    ef_search: 100,
+                },
+            )
+            .into_iter()
+            .map(|found| found.id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn search_recall_matches_brute_force() {
+        let mut random = Random(0x9e37_79b9_7f4a_7c15);
+        let mut graph = Hnsw::default();
+        let vectors: Vec<Vec<f32>> = (0..1500).map(|_| random_vector(&mut random)).collect();
+        for (id, vector) in vectors.iter().enumerate() {
+            graph.insert(id as i32, 0, vector.clone());
+        }
+
+        let top_k = 10;
+        let mut hits = 0;
+        for _ in 0..100 {
+            let query = random_vector(&mut random);
+            let mut expected: Vec<(f32, i32)> = vectors
+                .iter()
+                .enumerate()
+                .map(|(id, vector)| (distance(&query, vector), id as i32))
+                .collect();
+            expected.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let expected: HashSet<i32> = expected.iter().take(top_k).map(|(_, id)| *id).collect();
+
+            let found = graph.search(&query, EmbeddingSearchParams::default().ef_search);
+            hits += found
+                .iter()
+                .take(top_k)
+                .filter(|node| expected.contains(&node.id))
+                .count();
+        }
+
+        let recall = hits as f32 / (100 * top_k) as f32;
+        assert!(recall >= 0.95, "recall {recall}");
+    }
+
+    #[test]
+    fn written_shard_reads_back_same_graph() {
+        let dir = temp_dir("round_trip");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shard.hnsw");
+        let mut random = Random(42);
+        let mut graph = Hnsw::default();
+        for id in 0..300 {
+            graph.insert(id, id as u64 * 10, random_vector(&mut random));
+        }
+        graph.nodes[7].deleted = true;
+
+        graph.write(&path).unwrap();
+        let read = Hnsw::read(&path).unwrap();
+
+        assert_eq!(read.entry_point, graph.entry_point);
+        assert_eq!(read.nodes.len(), graph.nodes.len());
+        for (read, written) in read.nodes.iter().zip(&graph.nodes) {
+            assert_eq!(read.id, written.id);
+            assert_eq!(read.created_at, written.created_at);
+            assert_eq!(read.deleted, written.deleted);
+            assert_eq!(read.vector, written.vector);
+            assert_eq!(read.neighbors, written.neighbors);
+        }
+        let query = random_vector(&mut random);
+        let ids = |graph: &Hnsw| -> Vec<i32> {
+            graph
+                .search(&query, 64)
+                .iter()
+                .map(|node| node.id)
+                .collect()
+        };
+        assert_eq!(ids(&read), ids(&graph));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn broken_shard_is_rejected() {
+        let dir = temp_dir("broken");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shard.hnsw");
+        let mut random = Random(7);
+        let mut graph = Hnsw::default();
+        for id in 0..50 {
+            graph.insert(id, 0, random_vector(&mut random));
+        }
+
+        graph.nodes[3].neighbors[0].push(1000);
+        graph.write(&path).unwrap();
+        assert!(Hnsw::read(&path).is_err());
+
+        // Huge node count must fail before allocation
+        graph.nodes[3].neighbors[0].pop();
+        graph.write(&path).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[10..14].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert!(Hnsw::read(&path).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn broken_shard_is_rebuilt_from_db() {
+        let dir = temp_dir("rebuild");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(format!("{CHAT_ID}_0.hnsw")), b"HNSW01garbage").unwrap();
+        let conn = create_db(":memory:").unwrap();
+        create_hashes_table(&conn);
+        let mut random = Random(11);
+        let query = random_vector(&mut random);
+        let ids: Vec<i32> = (0..20)
+            .map(|created_at| insert_embedding(&conn, &random_vector(&mut random), created_at + 1))
+            .collect();
+
+        let index = EmbeddingIndex::load(&dir, &conn).unwrap();
+
+        assert_eq!(found_ids(&index, &query, 0), ids);
+        assert!(Hnsw::read(&dir.join(format!("{CHAT_ID}_0.hnsw"))).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expired_shards_are_evicted_and_restored() {
+        let dir = temp_dir("evict");
+        let conn = create_db(":memory:").unwrap();
+        create_hashes_table(&conn);
+        let mut random = Random(13);
+        let query = random_vector(&mut random);
+        let ids: Vec<i32> = (0..3)
+            .map(|window| {
+                insert_embedding(
+                    &conn,
+                    &random_vector(&mut random),
+                    window * SHARD_WINDOW_SECONDS + 1,
+                )
+            })
+            .collect();
+        let mut index = EmbeddingIndex::load(&dir, &conn).unwrap();
+        assert_eq!(found_ids(&index, &query, 0), ids);
+
+        index
+            .set_search_window(&conn, CHAT_ID, 2 * SHARD_WINDOW_SECONDS)
+            .unwrap();
+        assert_eq!(index.shards.len(), 1);
+        assert!(!dir.join(format!("{CHAT_ID}_0.hnsw")).exists());
+        assert_eq!(found_ids(&index, &query, 0), ids[2..]);
+
+        // Evicted rows aren't rebuilt from db on restart
+        let mut index = EmbeddingIndex::load(&dir, &conn).unwrap();
+        assert_eq!(found_ids(&index, &query, 0), ids[2..]);
+
+        index.set_search_window(&conn, CHAT_ID, 0).unwrap();
+        assert_eq!(found_ids(&index, &query, 0), ids);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
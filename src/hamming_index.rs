@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+
+use image_hasher::ImageHash;
+use rusqlite::Connection;
+
+use crate::hasher::HashType;
+
+/// Hash row reference stored in the index
+#[derive(Debug, Clone, Copy)]
+struct IndexEntry {
+    id: i32,
+    created_at: u64,
+}
+
+struct Node {
+    hash: ImageHash<Box<[u8]>>,
+    entries: Vec<IndexEntry>,
+    children: Vec<(u32, usize)>,
+}
+
+/// Tree is rebuilt when more than this fraction of nodes has no entries
+const MAX_DEAD_NODES_FRACTION: f32 = 0.5;
+
+/// BK-tree over blockhash hashes of one orientation.
+///
+/// Deleting a hash only drops its entry, the node stays in place to keep routing
+/// for its children. Tree is rebuilt from live nodes once dead ones pile up.
+#[derive(Default)]
+struct BkTree {
+    nodes: Vec<Node>,
+    /// Nodes without entries
+    dead_nodes: usize,
+}
+
+impl BkTree {
+    fn insert(&mut self, hash: ImageHash<Box<[u8]>>, entry: IndexEntry) -> usize {
+        if self.nodes.is_empty() {
+            self.nodes.push(Node {
+                hash,
+                entries: vec![entry],
+                children: vec![],
+            });
+            return 0;
+        }
+
+        let mut current = 0;
+        loop {
+            let dist = self.nodes[current].hash.dist(&hash);
+            if dist == 0 {
+                if self.nodes[current].entries.is_empty() {
+                    self.dead_nodes -= 1;
+                }
+                self.nodes[current].entries.push(entry);
+                return current;
+            }
+
+            match self.nodes[current]
+                .children
+                .iter()
+                .find(|(child_dist, _)| *child_dist == dist)
+            {
+                Some((_, child)) => current = *child,
+                None => {
+                    let new_node = self.nodes.len();
+                    self.nodes.push(Node {
+                        hash,
+                        entries: vec![entry],
+                        children: vec![],
+                    });
+                    self.nodes[current].children.push((dist, new_node));
+                    return new_node;
+                }
+            }
+        }
+    }
+
+    /// Visit every entry with distance strictly less than `max_distance`
+    fn find(
+        &self,
+        hash: &ImageHash<Box<[u8]>>,
+        max_distance: u32,
+        mut visit: impl FnMut(IndexEntry, u32),
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![0];
+        while let Some(current) = stack.pop() {
+            let node = &self.nodes[current];
+            let dist = node.hash.dist(hash);
+            if dist < max_distance {
+                for entry in &node.entries {
+                    visit(*entry, dist);
+                }
+            }
+
+            for (child_dist, child) in &node.children {
+                if child_dist.abs_diff(dist) < max_distance {
+                    stack.push(*child);
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, node: usize, id: i32) {
+        if let Some(node) = self.nodes.get_mut(node) {
+            let had_entries = !node.entries.is_empty();
+            node.entries.retain(|entry| entry.id != id);
+            if had_entries && node.entries.is_empty() {
+                self.dead_nodes += 1;
+            }
+        }
+    }
+
+    fn needs_rebuild(&self) -> bool {
+        self.dead_nodes as f32 > self.nodes.len() as f32 * MAX_DEAD_NODES_FRACTION
+    }
+
+    /// Insert entries of live nodes into fresh tree, returns new node of every entry
+    fn rebuild(&mut self) -> Vec<(i32, usize)> {
+        let nodes = std::mem::take(&mut self.nodes);
+        self.dead_nodes = 0;
+
+        let mut locations = vec![];
+        // Parents precede children, so the tree keeps similar shape
+        for node in nodes {
+            for entry in node.entries {
+                locations.push((entry.id, self.insert(node.hash.clone(), entry)));
+            }
+        }
+        locations
+    }
+}
+
+/// Per-chat in-memory index of blockhash rows from `hashes` table.
+///
+/// Mirrors SQL `hamming_distance` search without decoding every row on every query.
+#[derive(Default)]
+pub struct HammingIndex {
+    trees: HashMap<(i64, HashType), BkTree>,
+    locations: HashMap<i32, (i64, HashType, usize)>,
+}
+
+impl HammingIndex {
+    #[tracing::instrument(name = "Load hamming index", skip(conn))]
+    pub fn load(conn: &Connection) -> Result<Self, rusqlite::Error> {
+        let mut index = Self::default();
+
+        let mut stmt = conn.prepare(
+            "SELECT id, chat_id, orientation, base64_hash, created_at FROM hashes WHERE orientation NOT IN ('siglip2', 'video_keyframes')",
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let orientation: String = row.get(2)?;
+            let Ok(hash_type) = orientation.parse::<HashType>() else {
+                continue;
+            };
+            let hash: String = row.get(3)?;
+            index.insert(row.get(0)?, row.get(1)?, hash_type, &hash, row.get(4)?);
+        }
+
+        tracing::info!("Hamming index loaded {} hashes", index.len());
+        Ok(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    pub fn insert(
+        &mut self,
+        id: i32,
+        chat_id: i64,
+        hash_type: HashType,
+        base64_hash: &str,
+        created_at: u64,
+    ) {
+        if !hash_type.is_hamming() {
+            return;
+        }
+        let Ok(hash) = ImageHash::from_base64(base64_hash) else {
+            tracing::warn!("Skip invalid hash {id} in hamming index");
+            return;
+        };
+
+        let node = self
+            .trees
+            .entry((chat_id, hash_type))
+            .or_default()
+            .insert(hash, IndexEntry { id, created_at });
+        self.locations.insert(id, (chat_id, hash_type, node));
+    }
+
+    pub fn remove(&mut self, id: i32) {
+        let Some((chat_id, hash_type, node)) = self.locations.remove(&id) else {
+            return;
+        };
+        let Some(tree) = self.trees.get_mut(&(chat_id, hash_type)) else {
+            return;
+        };
+        tree.remove(node, id);
+
+        if tree.needs_rebuild() {
+            for (id, node) in tree.rebuild() {
+                self.locations.insert(id, (chat_id, hash_type, node));
+            }
+            if tree.nodes.is_empty() {
+                self.trees.remove(&(chat_id, hash_type));
+            }
+        }
+    }
+
+    /// Ids and distances of hashes closer than `max_distance`, nearest first
+    pub fn find(
+        &self,
+        chat_id: i64,
+        hash_type: HashType,
+        base64_hash: &str,
+        max_distance: usize,
+        from_timestamp: u64,
+    ) -> Vec<(i32, u32)> {
+        let Some(tree) = self.trees.get(&(chat_id, hash_type)) else {
+            return vec![];
+        };
+        let Ok(hash) = ImageHash::from_base64(base64_hash) else {
+            return vec![];
+        };
+
+        let mut found = vec![];
+        tree.find(
+            &hash,
+            max_distance.try_into().unwrap_or(u32::MAX),
+            |entry, dist| {
+                if entry.created_at > from_timestamp {
+                    found.push((entry.id, dist));
+                }
+            },
+        );
+        found.sort_by_key(|(id, dist)| (*dist, *id));
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::create_db, test_utils::Random};
+
+    const HASH_BYTES: usize = 19;
+    const CHATS: [i64; 2] = [-100, -200];
+    const HASH_TYPES: [HashType; 2] = [HashType::PHashLandscape, HashType::PHashSquare];
+
+    /// Copy of `base` with a few random bits flipped, so distances are close to tolerance
+    fn near_hash(random: &mut Random, base: &[u8; HASH_BYTES]) -> String {
+        let mut bytes = *base;
+        for _ in 0..random.below(12) {
+            let bit = random.below(HASH_BYTES as u64 * 8) as usize;
+            bytes[bit / 8] ^= 1 << (bit % 8);
+        }
+        ImageHash::<Box<[u8]>>::from_bytes(&bytes)
+            .unwrap()
+            .to_base64()
+    }
+
+    fn create_hashes_table(conn: &Connection) {
+        conn.execute_batch(
+            r#"CREATE TABLE hashes (
+                id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+                chat_id integer NOT NULL,
+                message_id integer NOT NULL,
+                filename varchar NOT NULL,
+                file_id varchar NOT NULL,
+                orientation varchar NOT NULL,
+                base64_hash varchar NOT NULL,
+                created_at integer NOT NULL,
+                media_group_id varchar NULL
+            )"#,
+        )
+        .unwrap();
+    }
+
+    /// SQL search which index replaced, kept as reference
+    fn sql_find(
+        conn: &Connection,
+        chat_id: i64,
+        hash_type: HashType,
+        base64_hash: &str,
+        max_distance: usize,
+        from_timestamp: u64,
+    ) -> Vec<i32> {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, hamming_distance(base64_hash, ?) as dist FROM hashes WHERE chat_id = ? AND orientation = ? AND dist < ? AND created_at > ?",
+            )
+            .unwrap();
+        let mut ids = stmt
+            .query_map(
+                rusqlite::params![
+                    base64_hash,
+                    chat_id,
+                    hash_type.as_str(),
+                    max_distance,
+                    from_timestamp
+                ],
+                |row| row.get(0),
+            )
+            .unwrap()
+            .collect::<Result<Vec<i32>, _>>()
+            .unwrap();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn index_returns_same_ids_as_sql_search() {
+        let conn = create_db(":memory:").unwrap();
+        create_hashes_table(&conn);
+        let mut random = Random(0x9e37_79b9_7f4a_7c15);
+        let base = [0b1010_0110u8; HASH_BYTES];
+
+        for message_id in 0..600 {
+            let chat_id = CHATS[random.below(CHATS.len() as u64) as usize];
+            let hash_type = HASH_TYPES[random.below(HASH_TYPES.len() as u64) as usize];
+            conn.execute(
+                "INSERT INTO hashes(chat_id, message_id, filename, file_id, orientation, base64_hash, created_at) VALUES(?, ?, '', '', ?, ?, ?)",
+                rusqlite::params![
+                    chat_id,
+                    message_id,
+                    hash_type.as_str(),
+                    near_hash(&mut random, &base),
+                    random.below(1000)
+                ],
+            )
+            .unwrap();
+        }
+        // Removed rows must disappear from index too
+        conn.execute("DELETE FROM hashes WHERE id % 7 = 0", [])
+            .unwrap();
+        let mut index = HammingIndex::load(&conn).unwrap();
+        for id in (0..=600).filter(|id| id % 7 == 0) {
+            index.remove(id);
+        }
+
+        let mut compared = 0;
+        for _ in 0..200 {
+            let chat_id = CHATS[random.below(CHATS.len() as u64) as usize];
+            let hash_type = HASH_TYPES[random.below(HASH_TYPES.len() as u64) as usize];
+            let query = near_hash(&mut random, &base);
+            let tolerance = random.below(10) as usize;
+            let from_timestamp = random.below(1000);
+
+            let mut found: Vec<i32> = index
+                .find(chat_id, hash_type, &query, tolerance, from_timestamp)
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect();
+            found.sort();
+            let expected = sql_find(&conn, chat_id, hash_type, &query, tolerance, from_timestamp);
+            assert_eq!(
+                found, expected,
+                "tolerance {tolerance}, from {from_timestamp}"
+            );
+            compared += expected.len();
+        }
+        assert!(compared > 0, "queries should find something");
+    }
+
+    #[test]
+    fn removed_hash_is_not_found() {
+        let mut index = HammingIndex::default();
+        let hash = ImageHash::<Box<[u8]>>::from_bytes(&[0u8; HASH_BYTES])
+            .unwrap()
+            .to_base64();
+        index.insert(1, CHATS[0], HashType::PHashSquare, &hash, 10);
+        index.insert(2, CHATS[0], HashType::PHashSquare, &hash, 10);
+        index.remove(1);
+
+        let found = index.find(CHATS[0], HashType::PHashSquare, &hash, 1, 0);
+
+        assert_eq!(found, vec![(2, 0)]);
+        assert_eq!(index.len(), 1);
+    }
+
+    #[test]
+    fn tree_is_rebuilt_when_dead_nodes_pile_up() {
+        let mut index = HammingIndex::default();
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        let base = [0u8; HASH_BYTES];
+        let hashes = (0..200)
+            .map(|_| near_hash(&mut random, &base))
+            .collect::<Vec<_>>();
+        for (id, hash) in hashes.iter().enumerate() {
+            index.insert(id as i32, CHATS[0], HashType::PHashSquare, hash, 10);
+        }
+        let key = (CHATS[0], HashType::PHashSquare);
+        let nodes_before = index.trees[&key].nodes.len();
+
+        for id in (0..200).filter(|id| id % 4 != 0) {
+            index.remove(id);
+        }
+
+        let tree = &index.trees[&key];
+        assert!(tree.nodes.len() < nodes_before);
+        assert!(!tree.needs_rebuild());
+        // Moved entries are still found and removed by their new location
+        for (id, hash) in hashes.iter().enumerate().filter(|(id, _)| id % 4 == 0) {
+            let found = index.find(CHATS[0], HashType::PHashSquare, hash, 1, 0);
+            assert!(found.contains(&(id as i32, 0)), "{id} not found");
+        }
+        for id in (0..200).filter(|id| id % 4 == 0) {
+            index.remove(id);
+        }
+        assert!(index.is_empty());
+        assert!(!index.trees.contains_key(&key));
+    }
+}
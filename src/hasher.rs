@@ -1,29 +1,73 @@
 use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    path::Path,
     str::FromStr,
     sync::Arc,
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use image::DynamicImage;
-use image_hasher::{HashAlg, Hasher, HasherConfig};
+use image_hasher::{HashAlg, Hasher, HasherConfig, ImageHash};
 
 use tokio::sync::Mutex;
 
 use crate::{
-    create_vote, create_voting, db, delete_old_hash, find_image_by_unique_file_id,
-    find_similar_hashes, get_voting_info, metrics, move_old_hash_to_new, HashRecord, VoteResult,
-    VoteType, VotingRecord, VotingType,
+    count_muted_match, create_vote, create_voting,
+    dataset::{DatasetImage, VotedAlert},
+    db, delete_expired_alerts, delete_expired_file_hashes, delete_old_hash,
+    embedding_index::{EmbeddingIndex, EmbeddingSearchParams},
+    exclude_alert_pair, find_alert_files, find_alert_hashes, find_expired_files,
+    find_filename_by_file_id, find_hashes_by_file_id, find_hashes_by_ids, find_hashes_by_type,
+    find_image_by_unique_file_id, find_indexed_chats, find_indexed_files, find_indexed_messages,
+    find_pair_exclusions, find_voting_scores, get_chat_settings, get_index_metadata,
+    get_voting_info,
+    hamming_index::HammingIndex,
+    is_voting_finished,
+    keyframes::{self, KEYFRAMES_MIN_SIMILARITY},
+    metrics,
+    models::{ChatDefaults, ChatSettings},
+    move_old_hash_to_new, mute_message_hashes,
+    normalize::{BorderTrimmer, Crop, NormalizedImage},
+    retention::ExpiredFile,
+    save_alert_hashes, save_chat_settings, set_index_metadata,
+    siglip2::{self, Siglip2Hasher},
+    HashRecord, VoteResult, VoteType, VotingRecord, VotingType,
 };
 
-const PERCEPTIVE_HASH_TOLERANCE: usize = 5;
-const SEARCH_DISTANCE_IN_SECONDS: u64 = 7 * 24 * 60 * 60;
+pub const PERCEPTIVE_HASH_TOLERANCE: usize = 5;
+pub const SIGLIP2_MIN_SIMILARITY: f32 = 0.92;
+pub const SEARCH_DISTANCE_IN_SECONDS: u64 = 7 * 24 * 60 * 60;
 pub const MIN_VOTES_COUNT: i64 = 5;
+pub const MUTE_DURATION_IN_SECONDS: u64 = 30 * 24 * 60 * 60;
+/// Blockhash sizes as width x height, hashes of other sizes can't be compared
+pub const LANDSCAPE_HASH_SIZE: (u32, u32) = (15, 10);
+pub const PORTRAIT_HASH_SIZE: (u32, u32) = (10, 15);
+pub const SQUARE_HASH_SIZE: (u32, u32) = (15, 15);
+const HASHER_SIGNATURE_KEY: &str = "hasher";
+
+/// Hashes of single message or file to save into index
+#[derive(Debug)]
+pub struct IndexEntry<'a> {
+    pub filename: &'a str,
+    pub chat_id: i64,
+    pub message_id: i64,
+    pub file_id: &'a str,
+    pub media_group_id: Option<&'a str>,
+    pub hashes: &'a [CalculatedHash],
+    pub crop: Option<&'a Crop>,
+    pub created_at: u64,
+}
 
 pub trait Indexer {
     async fn is_file_processed_info(&self, file_id: &str, chat_id: i64) -> Option<HashRecord>;
     fn hash_image(&self, img: &DynamicImage) -> Vec<CalculatedHash>;
-    async fn find_similar_hashes(&self, hashes: &[CalculatedHash], chat_id: i64)
-        -> Vec<HashRecord>; //TODO
+    async fn find_similar_hashes(
+        &self,
+        hashes: &[CalculatedHash],
+        chat_id: i64,
+    ) -> Vec<ScoredMatch>; //TODO
+    #[allow(clippy::too_many_arguments)]
     async fn save_to_index(
         &mut self,
         filename: &str,
@@ -32,6 +76,7 @@ pub trait Indexer {
         file_id: &str,
         media_group_id: Option<&str>,
         hashes: &[CalculatedHash], //TODO
+        crop: Option<&Crop>,
     ) -> Result<(), ()>;
     async fn delete_old_hash(&mut self, hash_id: i32); //TODO
     async fn update_old_hash(&mut self, hash_id: i32, chat_id: i64, message_id: i64); //TODO
@@ -49,6 +94,16 @@ pub struct PHashIndexer {
     hasher_landscape: Hasher,
     hasher_portrait: Hasher,
     hasher_square: Hasher,
+    siglip2: Option<Siglip2Indexer>,
+    scoring: MatchScoring,
+    // Extra query variants, only canonical orientation is stored
+    transforms: Vec<Transform>,
+    border_trimmer: Option<BorderTrimmer>,
+    // Fraction of image kept by center crop hash
+    center_crop: Option<f32>,
+    chat_defaults: ChatDefaults,
+    hamming_index: HammingIndex,
+    embedding_index: EmbeddingIndex,
     db: Arc<Mutex<rusqlite::Connection>>,
 }
 
@@ -60,42 +115,173 @@ impl Default for PHashIndexer {
 
 impl PHashIndexer {
     pub fn new(db_path: &str) -> Self {
+        let db = db::create_db(db_path).expect("Failed to open db");
+        let hamming_index = HammingIndex::load(&db).expect("Failed to load hamming index");
+        // Embedding graphs are stored next to db, e.g. hashes.db -> hashes.hnsw/
+        let embedding_index = EmbeddingIndex::load(&Path::new(db_path).with_extension("hnsw"), &db)
+            .expect("Failed to load embedding index");
+        Self::with_db(db, hamming_index, embedding_index)
+    }
+
+    /// Indexer for hashing and scoring only, its db is empty and in memory
+    pub fn hashing_only() -> Self {
+        let db = db::create_db(":memory:").expect("Failed to open in-memory db");
+        Self::with_db(db, HammingIndex::default(), EmbeddingIndex::empty())
+    }
+
+    fn with_db(
+        db: rusqlite::Connection,
+        hamming_index: HammingIndex,
+        embedding_index: EmbeddingIndex,
+    ) -> Self {
         let hash_landscape_config = HasherConfig::new()
-            .hash_size(15, 10)
+            .hash_size(LANDSCAPE_HASH_SIZE.0, LANDSCAPE_HASH_SIZE.1)
             .hash_alg(HashAlg::Blockhash);
         let hasher_landscape = hash_landscape_config.to_hasher();
 
         let hash_portrait_config = HasherConfig::new()
-            .hash_size(10, 15)
+            .hash_size(PORTRAIT_HASH_SIZE.0, PORTRAIT_HASH_SIZE.1)
             .hash_alg(HashAlg::Blockhash);
         let hasher_portrait = hash_portrait_config.to_hasher();
 
         let hash_square_config = HasherConfig::new()
-            .hash_size(15, 15)
+            .hash_size(SQUARE_HASH_SIZE.0, SQUARE_HASH_SIZE.1)
             .hash_alg(HashAlg::Blockhash);
         let hasher_square = hash_square_config.to_hasher();
 
-        let db = Arc::new(Mutex::new(
-            db::create_db(db_path).expect("Failed to open db"),
-        ));
+        let db = Arc::new(Mutex::new(db));
 
         Self {
             hasher_landscape,
             hasher_portrait,
             hasher_square,
+            siglip2: None,
+            scoring: MatchScoring::default(),
+            transforms: vec![],
+            border_trimmer: None,
+            center_crop: None,
+            chat_defaults: ChatDefaults::default(),
+            hamming_index,
+            embedding_index,
             db,
         }
     }
 
+    pub fn with_scoring(mut self, scoring: MatchScoring) -> Self {
+        self.scoring = scoring;
+        self
+    }
+
+    pub fn scoring(&self) -> &MatchScoring {
+        &self.scoring
+    }
+
+    /// Enable SigLIP2 embeddings in addition to blockhash
+    pub fn with_siglip2(mut self, siglip2: Siglip2Indexer) -> Self {
+        self.siglip2 = Some(siglip2);
+        self
+    }
+
+    /// Also search flipped and rotated copies of query images
+    pub fn with_transforms(mut self, transforms: Vec<Transform>) -> Self {
+        self.transforms = transforms;
+        self
+    }
+
+    /// Trim borders, letterboxing and caption strips before hashing
+    pub fn with_border_trimmer(mut self, border_trimmer: BorderTrimmer) -> Self {
+        self.border_trimmer = Some(border_trimmer);
+        self
+    }
+
+    /// Also hash central part of image, survives watermarks and crops near edges
+    pub fn with_center_crop(mut self, fraction: f32) -> Self {
+        self.center_crop = Some(fraction.clamp(0.1, 1.0));
+        self
+    }
+
+    /// Settings of chats without own ones
+    pub fn with_chat_defaults(mut self, chat_defaults: ChatDefaults) -> Self {
+        self.chat_defaults = chat_defaults;
+        self
+    }
+
+    /// Image without detected borders, hash it instead of original image
+    pub fn normalize_image<'a>(&self, img: &'a DynamicImage) -> NormalizedImage<'a> {
+        match &self.border_trimmer {
+            Some(border_trimmer) => border_trimmer.normalize(img),
+            None => NormalizedImage {
+                image: Cow::Borrowed(img),
+                crop: None,
+            },
+        }
+    }
+
+    /// Algorithms, sizes and preprocessing of hashes written by this indexer
+    pub fn hasher_signature(&self) -> String {
+        let size = |(width, height): (u32, u32)| format!("{width}x{height}");
+        let border_trim = if self.border_trimmer.is_some() {
+            "on"
+        } else {
+            "off"
+        };
+        let center_crop = self
+            .center_crop
+            .map(|fraction| fraction.to_string())
+            .unwrap_or("off".to_owned());
+        let siglip2 = self
+            .siglip2
+            .as_ref()
+            .map(|siglip2| format!("{}/{}", siglip2.model_name, siglip2.dimension))
+            .unwrap_or("off".to_owned());
+        format!(
+            "blockhash landscape={} portrait={} square={} border_trim={border_trim} center_crop={center_crop} siglip2={siglip2}",
+            size(LANDSCAPE_HASH_SIZE),
+            size(PORTRAIT_HASH_SIZE),
+            size(SQUARE_HASH_SIZE)
+        )
+    }
+
+    /// Fail if db has hashes of other hasher config, signature is saved on first run
+    pub async fn check_hasher_signature(&self) -> Result<(), anyhow::Error> {
+        let db = self.db.lock().await;
+        let signature = self.hasher_signature();
+        match get_index_metadata(&db, HASHER_SIGNATURE_KEY)? {
+            Some(saved) if saved != signature => Err(anyhow::format_err!(
+                "Db hashes are made by `{saved}`, current hasher is `{signature}`"
+            )),
+            Some(_) => Ok(()),
+            None => {
+                set_index_metadata(&db, HASHER_SIGNATURE_KEY, &signature)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Files of chat which already have hashes
+    pub async fn find_indexed_files(&self, chat_id: i64) -> Result<HashSet<String>, anyhow::Error> {
+        let db = self.db.lock().await;
+        find_indexed_files(&db, chat_id)
+            .map_err(|e| anyhow::format_err!("Failed to find indexed files: {e}"))
+    }
+
+    /// Messages of chat which already have hashes
+    pub async fn find_indexed_messages(&self, chat_id: i64) -> Result<HashSet<i64>, anyhow::Error> {
+        let db = self.db.lock().await;
+        find_indexed_messages(&db, chat_id)
+            .map_err(|e| anyhow::format_err!("Failed to find indexed messages: {e}"))
+    }
+
     pub async fn is_file_processed_info(&self, file_id: &str, chat_id: i64) -> Option<HashRecord> {
         let db = self.db.lock().await;
         let send_metric = metrics::mtr_is_file_processed_info_query_time();
+        let settings = load_chat_settings(&db, chat_id, &self.chat_defaults);
 
         let current_timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
-        let from_timestamp = current_timestamp - SEARCH_DISTANCE_IN_SECONDS;
+        let from_timestamp = current_timestamp.saturating_sub(settings.search_distance_seconds);
         let result = find_image_by_unique_file_id(&db, file_id, chat_id, from_timestamp);
         send_metric();
         result
@@ -114,7 +300,7 @@ impl PHashIndexer {
 
         send_metric();
 
-        vec![
+        let mut hashes = vec![
             CalculatedHash {
                 hash_type: HashType::PHashLandscape,
                 hash: hash_landscape,
@@ -127,40 +313,217 @@ impl PHashIndexer {
                 hash_type: HashType::PHashSquare,
                 hash: hash_square,
             },
-        ]
+        ];
+        hashes.extend(self.hash_center_crop(img));
+
+        if let Some(siglip2) = &self.siglip2 {
+            match siglip2.hash_image(img) {
+                Ok(hash) => hashes.push(hash),
+                Err(e) => tracing::error!("Failed to calculate siglip2 embedding: {e}"),
+            }
+        }
+
+        hashes
+    }
+
+    /// Blockhashes of every configured transform of image, used only for search.
+    ///
+    /// SigLIP2 embeddings are not recalculated, they are tolerant to flips already.
+    #[tracing::instrument("Calculate image variants hashes", skip(self, img))]
+    pub fn hash_image_variants(&self, img: &DynamicImage) -> Vec<HashVariant> {
+        if self.transforms.is_empty() {
+            return vec![];
+        }
+        let send_metric = metrics::mtr_variants_hashing_time();
+
+        let variants = self
+            .transforms
+            .iter()
+            .map(|transform| {
+                let img = transform.apply(img);
+                HashVariant {
+                    transform: *transform,
+                    hashes: vec![
+                        CalculatedHash {
+                            hash_type: HashType::PHashLandscape,
+                            hash: self.hasher_landscape.hash_image(&img).to_base64(),
+                        },
+                        CalculatedHash {
+                            hash_type: HashType::PHashPortrait,
+                            hash: self.hasher_portrait.hash_image(&img).to_base64(),
+                        },
+                        CalculatedHash {
+                            hash_type: HashType::PHashSquare,
+                            hash: self.hasher_square.hash_image(&img).to_base64(),
+                        },
+                    ]
+                    .into_iter()
+                    .chain(self.hash_center_crop(&img))
+                    .collect(),
+                }
+            })
+            .collect();
+
+        send_metric();
+        variants
+    }
+
+    fn hash_center_crop(&self, img: &DynamicImage) -> Option<CalculatedHash> {
+        let fraction = self.center_crop?;
+        let center = Crop::center(img, fraction).apply(img);
+        Some(CalculatedHash {
+            hash_type: HashType::CenterCrop,
+            hash: self.hasher_square.hash_image(&center).to_base64(),
+        })
+    }
+
+    /// Hashes of video thumbnail and sampled frames, both optional
+    #[tracing::instrument("Calculate video hashes", skip_all)]
+    pub fn hash_video(
+        &self,
+        thumbnail: Option<&DynamicImage>,
+        frames: &[DynamicImage],
+    ) -> Vec<CalculatedHash> {
+        let send_metric = metrics::mtr_message_hashing_time();
+        let mut hashes = vec![];
+
+        if let Some(thumbnail) = thumbnail {
+            hashes.push(CalculatedHash {
+                hash_type: HashType::VideoThumbnail,
+                hash: self.hasher_square.hash_image(thumbnail).to_base64(),
+            });
+        }
+
+        if !frames.is_empty() {
+            let frame_hashes = frames
+                .iter()
+                .map(|frame| self.hasher_square.hash_image(frame))
+                .collect::<Vec<_>>();
+            hashes.push(CalculatedHash {
+                hash_type: HashType::VideoKeyframes,
+                hash: keyframes::encode_sequence(&frame_hashes),
+            });
+        }
+
+        send_metric();
+        hashes
     }
 
+    /// Search candidates by every detector and rank them by hybrid confidence
     pub async fn find_similar_hashes(
         &self,
         hashes: &[CalculatedHash],
         chat_id: i64,
-    ) -> Vec<HashRecord> {
+    ) -> Vec<ScoredMatch> {
+        self.find_similar_variants(hashes, &[], chat_id).await
+    }
+
+    /// Same as `find_similar_hashes`, but transformed variants of query are searched too.
+    ///
+    /// Every image is reported once, with the transform that gave the best confidence.
+    pub async fn find_similar_variants(
+        &self,
+        hashes: &[CalculatedHash],
+        variants: &[HashVariant],
+        chat_id: i64,
+    ) -> Vec<ScoredMatch> {
         let db = self.db.lock().await;
         let send_mtr = metrics::mtr_find_similar_hashes_time();
+        let settings = load_chat_settings(&db, chat_id, &self.chat_defaults);
 
         let current_timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
-        let from_timestamp = current_timestamp - SEARCH_DISTANCE_IN_SECONDS;
+        let from_timestamp = current_timestamp.saturating_sub(settings.search_distance_seconds);
 
-        let results = hashes
-            .iter()
-            .filter_map(|hash| {
-                if hash.hash_type == HashType::Siglip2 {
-                    return None
+        // Hashes of disabled detectors are still stored, but not searched or scored
+        let enabled_hashes = |hashes: &[CalculatedHash]| {
+            hashes
+                .iter()
+                .filter(|hash| settings.is_detector_enabled(hash.hash_type))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let hashes = enabled_hashes(hashes);
+        let threshold = settings.match_threshold.unwrap_or(self.scoring.threshold);
+
+        let queries = std::iter::once((Transform::Identity, hashes.clone())).chain(
+            variants
+                .iter()
+                .map(|variant| (variant.transform, enabled_hashes(&variant.hashes))),
+        );
+
+        let mut results = vec![];
+        for (transform, query) in queries {
+            let candidates = self.find_candidates(
+                &db,
+                &query,
+                chat_id,
+                settings.hash_tolerance,
+                from_timestamp,
+            );
+
+            // Every detector may return the same image, score each image once
+            let mut scored_files = HashSet::new();
+            for candidate in candidates {
+                if !scored_files.insert(candidate.file_id.clone()) {
+                    continue;
                 }
-                let result = find_similar_hashes(
-                    &db,
-                    &hash.hash,
-                    PERCEPTIVE_HASH_TOLERANCE,
-                    chat_id,
-                    from_timestamp,
+
+                let stored_hashes = match find_hashes_by_file_id(&db, chat_id, &candidate.file_id) {
+                    Ok(stored_hashes) => stored_hashes
+                        .into_iter()
+                        .filter_map(|(orientation, hash)| {
+                            Some(CalculatedHash {
+                                hash_type: HashType::from_str(&orientation).ok()?,
+                                hash,
+                            })
+                        })
+                        .collect::<Vec<_>>(),
+                    Err(e) => {
+                        tracing::error!("Failed to load candidate hashes: {e}");
+                        continue;
+                    }
+                };
+
+                let score = self
+                    .scoring
+                    .score(&query, &stored_hashes, settings.hash_tolerance);
+                tracing::debug!(
+                    "Candidate {} confidence {} with {} ({:?})",
+                    candidate.message_id,
+                    score.confidence,
+                    transform.as_str(),
+                    score.similarities
                 );
-                result.ok()
-            })
-            .flatten()
-            .collect();
+                if score.confidence >= threshold {
+                    // Exclusions are saved from original query, not from its variants
+                    if self.is_pair_excluded(
+                        &db,
+                        chat_id,
+                        &candidate.file_id,
+                        &hashes,
+                        settings.hash_tolerance,
+                        threshold,
+                    ) {
+                        tracing::info!("Candidate {} is excluded by voting", candidate.message_id);
+                        continue;
+                    }
+                    results.push(ScoredMatch {
+                        record: candidate,
+                        confidence: score.confidence,
+                        similarities: score.similarities,
+                        transform,
+                    });
+                }
+            }
+        }
+        results.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+        // Keep best transform of every image
+        let mut reported_files = HashSet::new();
+        results.retain(|result| reported_files.insert(result.record.file_id.clone()));
 
         // Send metrics
         send_mtr();
@@ -168,7 +531,309 @@ impl PHashIndexer {
         results
     }
 
+    /// Records found by any detector for given hashes
+    fn find_candidates(
+        &self,
+        db: &rusqlite::Connection,
+        hashes: &[CalculatedHash],
+        chat_id: i64,
+        hash_tolerance: usize,
+        from_timestamp: u64,
+    ) -> Vec<HashRecord> {
+        hashes
+            .iter()
+            .filter_map(|hash| {
+                let result = if hash.hash_type == HashType::Siglip2 {
+                    let siglip2 = self.siglip2.as_ref()?;
+                    let ids = self
+                        .embedding_index
+                        .find(
+                            chat_id,
+                            &hash.hash,
+                            siglip2.min_similarity,
+                            from_timestamp,
+                            siglip2.search_params,
+                        )
+                        .into_iter()
+                        .map(|found| found.id)
+                        .collect::<Vec<_>>();
+                    find_hashes_by_ids(db, &ids)
+                } else if hash.hash_type == HashType::VideoKeyframes {
+                    // Clips are rare compared to images, so sequences are compared one by one
+                    let ids =
+                        find_hashes_by_type(db, chat_id, hash.hash_type.as_str(), from_timestamp)
+                            .map_err(|e| {
+                                tracing::error!("Failed to load video fingerprints: {e}");
+                            })
+                            .ok()?
+                            .into_iter()
+                            .filter_map(|(id, stored_hash)| {
+                                let stored_hash = CalculatedHash {
+                                    hash_type: HashType::VideoKeyframes,
+                                    hash: stored_hash,
+                                };
+                                let similarity = hash_similarity(hash, &stored_hash).ok()?;
+                                (similarity >= self.scoring.video_keyframes_min_similarity)
+                                    .then_some(id)
+                            })
+                            .collect::<Vec<_>>();
+                    find_hashes_by_ids(db, &ids)
+                } else {
+                    let ids = self
+                        .hamming_index
+                        .find(
+                            chat_id,
+                            hash.hash_type,
+                            &hash.hash,
+                            hash_tolerance,
+                            from_timestamp,
+                        )
+                        .into_iter()
+                        .map(|(id, _)| id)
+                        .collect::<Vec<_>>();
+                    find_hashes_by_ids(db, &ids)
+                };
+                result
+                    .map_err(|e| {
+                        tracing::error!("Failed to search {} hashes: {e}", hash.hash_type.as_str());
+                    })
+                    .ok()
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Check if community voted that image with `hashes` is not duplicate of `original_file_id`
+    fn is_pair_excluded(
+        &self,
+        db: &rusqlite::Connection,
+        chat_id: i64,
+        original_file_id: &str,
+        hashes: &[CalculatedHash],
+        hash_tolerance: usize,
+        threshold: f32,
+    ) -> bool {
+        let exclusions = match find_pair_exclusions(db, chat_id, original_file_id) {
+            Ok(exclusions) => exclusions,
+            Err(e) => {
+                tracing::error!("Failed to load pair exclusions: {e}");
+                return false;
+            }
+        };
+
+        let mut excluded_files: HashMap<String, Vec<CalculatedHash>> = HashMap::new();
+        for (file_id, orientation, hash) in exclusions {
+            let Ok(hash_type) = HashType::from_str(&orientation) else {
+                continue;
+            };
+            excluded_files
+                .entry(file_id)
+                .or_default()
+                .push(CalculatedHash { hash_type, hash });
+        }
+
+        excluded_files.values().any(|excluded_hashes| {
+            self.scoring
+                .score(hashes, excluded_hashes, hash_tolerance)
+                .confidence
+                >= threshold
+        })
+    }
+
+    /// Keep hashes of flagged message to exclude the pair if voting decides it's not duplicate
+    #[tracing::instrument("Save alert hashes", skip(self, hashes))]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn save_alert(
+        &mut self,
+        chat_id: i64,
+        alert_message_id: i64,
+        original_file_id: &str,
+        file_id: &str,
+        message_id: i64,
+        filename: Option<&str>,
+        hashes: &[CalculatedHash],
+    ) -> Result<(), anyhow::Error> {
+        let mut db = self.db.lock().await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        let hashes = hashes
+            .iter()
+            .map(|hash| (hash.hash_type.as_str(), hash.hash.as_str()))
+            .collect::<Vec<_>>();
+        save_alert_hashes(
+            &mut db,
+            chat_id,
+            alert_message_id,
+            original_file_id,
+            file_id,
+            message_id,
+            filename,
+            &hashes,
+            now,
+        )
+        .map_err(|e| anyhow::format_err!("Failed to save alert hashes: {e}"))
+    }
+
+    /// Finished votings on alerts with hashes and files of both images
+    #[tracing::instrument(name = "Find voted alerts", skip(self))]
+    pub async fn find_voted_alerts(&self) -> Result<Vec<VotedAlert>, anyhow::Error> {
+        let db = self.db.lock().await;
+        let to_hashes = |hashes: Vec<(String, String)>| {
+            hashes
+                .into_iter()
+                .filter_map(|(orientation, hash)| {
+                    Some(CalculatedHash {
+                        hash_type: HashType::from_str(&orientation).ok()?,
+                        hash,
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut alerts = vec![];
+        for (voting, votes_count, score) in find_voting_scores(&db)? {
+            let min_votes_count =
+                load_chat_settings(&db, voting.chat_id, &self.chat_defaults).min_votes_count;
+            if !is_voting_finished(votes_count, score, min_votes_count) {
+                continue;
+            }
+            // Alerts before alert hashes were saved can't be matched with images
+            let Some((original_file_id, file_id, message_id, filename)) =
+                find_alert_files(&db, voting.chat_id, voting.message_id)?
+            else {
+                tracing::warn!("Alert of voting {} not found", voting.id);
+                continue;
+            };
+
+            alerts.push(VotedAlert {
+                voting_id: voting.id.into(),
+                chat_id: voting.chat_id,
+                alert_message_id: voting.message_id,
+                voting_type: voting.voting_type.to_string(),
+                votes_count,
+                score,
+                // Only NOTDUPE voting won by community rejects the alert
+                duplicate: !(voting.voting_type == VotingType::NOTDUPE && score > 0),
+                original: DatasetImage {
+                    message_id: Some(voting.original_message_id),
+                    filename: find_filename_by_file_id(&db, voting.chat_id, &original_file_id)?,
+                    hashes: to_hashes(find_hashes_by_file_id(
+                        &db,
+                        voting.chat_id,
+                        &original_file_id,
+                    )?),
+                    file_id: original_file_id,
+                },
+                flagged: DatasetImage {
+                    message_id,
+                    file_id,
+                    filename,
+                    hashes: to_hashes(find_alert_hashes(&db, voting.chat_id, voting.message_id)?),
+                },
+            });
+        }
+        Ok(alerts)
+    }
+
+    /// Files indexed before retention period of their chats
+    #[tracing::instrument(name = "Find expired files", skip(self))]
+    pub async fn find_expired_files(
+        &self,
+        default_retention_seconds: u64,
+        limit: usize,
+    ) -> Result<Vec<ExpiredFile>, anyhow::Error> {
+        let db = self.db.lock().await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+
+        let mut expired_files = vec![];
+        for chat_id in find_indexed_chats(&db)? {
+            if expired_files.len() >= limit {
+                break;
+            }
+            let retention_seconds = load_chat_settings(&db, chat_id, &self.chat_defaults)
+                .retention_seconds
+                .unwrap_or(default_retention_seconds);
+            let before_timestamp = now.saturating_sub(retention_seconds);
+
+            let files =
+                find_expired_files(&db, chat_id, before_timestamp, limit - expired_files.len())?;
+            expired_files.extend(files.into_iter().map(|(filename, shared)| ExpiredFile {
+                chat_id,
+                filename,
+                before_timestamp,
+                shared,
+            }));
+        }
+        Ok(expired_files)
+    }
+
+    /// Delete expired rows of file from db and search indexes, returns deleted rows count
+    #[tracing::instrument(name = "Delete expired file", skip(self))]
+    pub async fn delete_expired_file(
+        &mut self,
+        file: &ExpiredFile,
+    ) -> Result<usize, anyhow::Error> {
+        let db = self.db.lock().await;
+        let ids =
+            delete_expired_file_hashes(&db, file.chat_id, &file.filename, file.before_timestamp)?;
+        for id in &ids {
+            self.hamming_index.remove(*id);
+            self.embedding_index.remove(*id);
+        }
+        Ok(ids.len())
+    }
+
+    /// Delete outdated alert hashes and pair exclusions, returns deleted rows count
+    #[tracing::instrument(name = "Delete expired alerts", skip(self))]
+    pub async fn delete_expired_alerts(
+        &self,
+        default_retention_seconds: u64,
+    ) -> Result<usize, anyhow::Error> {
+        let db = self.db.lock().await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+
+        let mut deleted = 0;
+        for chat_id in find_indexed_chats(&db)? {
+            let retention_seconds = load_chat_settings(&db, chat_id, &self.chat_defaults)
+                .retention_seconds
+                .unwrap_or(default_retention_seconds);
+            deleted += delete_expired_alerts(&db, chat_id, now.saturating_sub(retention_seconds))?;
+        }
+        Ok(deleted)
+    }
+
+    /// Db shared with indexer, for maintenance that shouldn't hold indexer lock
+    pub(crate) fn db(&self) -> Arc<Mutex<rusqlite::Connection>> {
+        self.db.clone()
+    }
+
+    /// Count match against muted original, returns true if alert should be skipped
+    #[tracing::instrument(name = "Check muted match", skip(self))]
+    pub async fn count_muted_match(&self, chat_id: i64, message_id: i64) -> bool {
+        let db = self.db.lock().await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs() as i64;
+        match count_muted_match(&db, chat_id, message_id, now) {
+            Ok(muted) => muted,
+            Err(e) => {
+                tracing::error!("Failed to count muted match: {e}");
+                false
+            }
+        }
+    }
+
     #[tracing::instrument("Save image hashes to db", skip(self))]
+    #[allow(clippy::too_many_arguments)]
     pub async fn save_to_index(
         &mut self,
         filename: &str,
@@ -178,43 +843,65 @@ impl PHashIndexer {
         media_group_id: Option<&str>,
         //(hash_landscape, hash_portrait, hash_square): (&str, &str, &str),
         hashes: &[CalculatedHash],
+        crop: Option<&Crop>,
     ) -> Result<(), ()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        self.save_entries_to_index(&[IndexEntry {
+            filename,
+            chat_id,
+            message_id,
+            file_id,
+            media_group_id,
+            hashes,
+            crop,
+            created_at: now,
+        }])
+        .await
+    }
+
+    /// Save hashes of several files in single transaction, nothing is saved on error
+    pub async fn save_entries_to_index(&mut self, entries: &[IndexEntry<'_>]) -> Result<(), ()> {
         let mut db = self.db.lock().await;
 
+        let mut inserted_ids = Vec::with_capacity(entries.len());
+
         let tx = db.transaction().map_err(|e| {
             tracing::error!("Transaction error {}", e);
         })?;
         {
             let mut prepared_st = tx
                 .prepare(
-                    r#"INSERT INTO hashes(filename, orientation, base64_hash, chat_id, message_id, file_id, created_at, media_group_id) VALUES(?, ?, ?, ?, ?, ?, ?, ?)"#,
+                    r#"INSERT INTO hashes(filename, orientation, base64_hash, chat_id, message_id, file_id, created_at, media_group_id, crop) VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
                 )
                 .map_err(|e| {
                     tracing::error!("Compile statement error {}", e);
                 })?;
 
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards")
-                .as_secs();
+            for entry in entries {
+                let media_group_id = entry.media_group_id.unwrap_or("");
+                let crop = entry.crop.map(|crop| crop.to_string());
 
-            let media_group_id = media_group_id.unwrap_or("");
-
-            for hash in hashes {
-                prepared_st
-                    .execute(rusqlite::params![
-                        filename,
-                        hash.hash_type.as_str(),
-                        hash.hash,
-                        chat_id,
-                        message_id,
-                        file_id,
-                        now,
-                        media_group_id,
-                    ])
-                    .map_err(|e| {
-                        tracing::error!("Insert {} error {}", hash.hash_type.as_str(), e);
-                    })?;
+                for hash in entry.hashes {
+                    let id = prepared_st
+                        .insert(rusqlite::params![
+                            entry.filename,
+                            hash.hash_type.as_str(),
+                            hash.hash,
+                            entry.chat_id,
+                            entry.message_id,
+                            entry.file_id,
+                            entry.created_at,
+                            media_group_id,
+                            crop,
+                        ])
+                        .map_err(|e| {
+                            tracing::error!("Insert {} error {}", hash.hash_type.as_str(), e);
+                        })?;
+                    inserted_ids.push((id, entry, hash));
+                }
             }
             /*
             prepared_st
@@ -267,12 +954,58 @@ impl PHashIndexer {
         tx.commit().map_err(|e| {
             tracing::error!("Transaction error {}", e);
         })?;
+
+        for (id, entry, hash) in inserted_ids {
+            let Ok(id) = i32::try_from(id) else {
+                tracing::error!("Hash id {id} is out of index range");
+                continue;
+            };
+            if hash.hash_type == HashType::Siglip2 {
+                self.embedding_index
+                    .insert(id, entry.chat_id, &hash.hash, entry.created_at);
+            } else {
+                self.hamming_index.insert(
+                    id,
+                    entry.chat_id,
+                    hash.hash_type,
+                    &hash.hash,
+                    entry.created_at,
+                );
+            }
+        }
+
+        // Graphs which fell out of search window are evicted as new ones appear
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        let chat_ids: HashSet<i64> = entries.iter().map(|entry| entry.chat_id).collect();
+        for chat_id in chat_ids {
+            let settings = load_chat_settings(&db, chat_id, &self.chat_defaults);
+            let from_timestamp = now.saturating_sub(settings.search_distance_seconds);
+            if let Err(e) = self
+                .embedding_index
+                .set_search_window(&db, chat_id, from_timestamp)
+            {
+                tracing::error!("Failed to evict embedding shards: {e}");
+            }
+        }
         Ok(())
     }
 
     pub async fn delete_old_hash(&mut self, hash_id: i32) {
         let db = self.db.lock().await;
-        let _ = delete_old_hash(&db, hash_id);
+        if delete_old_hash(&db, hash_id).is_ok() {
+            self.hamming_index.remove(hash_id);
+            self.embedding_index.remove(hash_id);
+        }
+    }
+
+    /// Save in-memory index changes to disk
+    pub fn flush(&mut self) {
+        if let Err(e) = self.embedding_index.flush() {
+            tracing::error!("Failed to save embedding index: {e}");
+        }
     }
 
     #[tracing::instrument(name = "Update existing hash", skip(self))]
@@ -312,6 +1045,34 @@ impl PHashIndexer {
         return get_voting_info(&db, voting_id);
     }
 
+    #[tracing::instrument(name = "Get chat settings", skip(self))]
+    pub async fn get_chat_settings(&self, chat_id: i64) -> Result<ChatSettings, anyhow::Error> {
+        let db = self.db.lock().await;
+        get_chat_settings(&db, chat_id, &self.chat_defaults)
+            .map_err(|e| anyhow::format_err!("Failed to load chat settings: {e}"))
+    }
+
+    #[tracing::instrument(name = "Save chat settings", skip(self))]
+    pub async fn save_chat_settings(
+        &mut self,
+        settings: &ChatSettings,
+    ) -> Result<(), anyhow::Error> {
+        let db = self.db.lock().await;
+        save_chat_settings(&db, settings)
+            .map_err(|e| anyhow::format_err!("Failed to save chat settings: {e}"))?;
+
+        // Longer search window needs evicted embedding shards back
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        self.embedding_index.set_search_window(
+            &db,
+            settings.chat_id,
+            now.saturating_sub(settings.search_distance_seconds),
+        )
+    }
+
     #[tracing::instrument(name = "Create new vote", skip(self))]
     pub async fn vote(
         &mut self,
@@ -321,30 +1082,430 @@ impl PHashIndexer {
         vote_type: VoteType,
     ) -> Result<VoteResult, anyhow::Error> {
         let mut db = self.db.lock().await;
-        return create_vote(&mut db, voting_id, user_id, username, vote_type);
+        let result = create_vote(
+            &mut db,
+            voting_id,
+            user_id,
+            username,
+            vote_type,
+            &self.chat_defaults,
+        )?;
+
+        if let VoteResult::Finished(_, VoteType::PRO) = &result {
+            let voting_info = get_voting_info(&db, voting_id)?;
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Time went backwards")
+                .as_secs();
+            match voting_info.voting_type {
+                // Community decided that flagged image is not duplicate, never flag this pair again
+                VotingType::NOTDUPE => {
+                    if let Err(e) =
+                        exclude_alert_pair(&db, voting_info.chat_id, voting_info.message_id, now)
+                    {
+                        tracing::error!("Failed to save pair exclusion: {e}");
+                    }
+                }
+                // Reposts of original are fine for community, stop alerting about them
+                VotingType::IGNORE => {
+                    let muted_until =
+                        get_chat_settings(&db, voting_info.chat_id, &self.chat_defaults)?
+                            .mute_duration_seconds
+                            .map(|duration| {
+                                i64::try_from(now.saturating_add(duration)).unwrap_or(i64::MAX)
+                            })
+                            .unwrap_or(i64::MAX);
+                    if let Err(e) = mute_message_hashes(
+                        &db,
+                        voting_info.chat_id,
+                        voting_info.original_message_id,
+                        muted_until,
+                    ) {
+                        tracing::error!("Failed to mute original hashes: {e}");
+                    }
+                }
+            }
+        }
+        Ok(result)
     }
 }
 
-struct Siglip2Indexer {}
+pub struct Siglip2Indexer {
+    // Session::run needs &mut, hashing is called from shared PHashIndexer
+    hasher: std::sync::Mutex<Siglip2Hasher>,
+    min_similarity: f32,
+    search_params: EmbeddingSearchParams,
+    // Model file name and embedding size are part of hasher signature
+    model_name: String,
+    dimension: usize,
+}
 
-#[derive(Debug)]
+impl Siglip2Indexer {
+    pub fn new(model_path: &Path, min_similarity: f32) -> Result<Self, anyhow::Error> {
+        let mut hasher = Siglip2Hasher::new(model_path)?;
+        // Model metadata may have dynamic output shape, so embedding size is measured
+        let dimension = hasher
+            .calculate_hash(&DynamicImage::new_rgb8(1, 1))
+            .map_err(|e| anyhow::format_err!("Failed to run siglip2 model: {e}"))?
+            .len();
+        let model_name = model_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(Self {
+            hasher: std::sync::Mutex::new(hasher),
+            min_similarity,
+            search_params: EmbeddingSearchParams::default(),
+            model_name,
+            dimension,
+        })
+    }
+
+    pub fn with_search_params(mut self, search_params: EmbeddingSearchParams) -> Self {
+        self.search_params = search_params;
+        self
+    }
+
+    #[tracing::instrument("Calculate siglip2 embedding", skip(self, img))]
+    pub fn hash_image(&self, img: &DynamicImage) -> Result<CalculatedHash, anyhow::Error> {
+        let send_metric = metrics::mtr_siglip2_hashing_time();
+
+        let embedding = self
+            .hasher
+            .lock()
+            .map_err(|_| anyhow::format_err!("Siglip2 hasher lock poisoned"))?
+            .calculate_hash(img)?;
+
+        send_metric();
+
+        Ok(CalculatedHash {
+            hash_type: HashType::Siglip2,
+            hash: siglip2::embedding_to_base64(&embedding),
+        })
+    }
+}
+
+/// Chat settings or defaults if they can't be loaded
+fn load_chat_settings(
+    db: &rusqlite::Connection,
+    chat_id: i64,
+    defaults: &ChatDefaults,
+) -> ChatSettings {
+    get_chat_settings(db, chat_id, defaults).unwrap_or_else(|e| {
+        tracing::error!("Failed to load chat settings, use defaults: {e}");
+        ChatSettings::new(chat_id, defaults)
+    })
+}
+
+#[derive(Debug, Clone)]
 pub struct CalculatedHash {
     pub hash_type: HashType,
     pub hash: String,
 }
 
-#[derive(Debug, PartialEq)]
+/// Weights of every detector in hybrid confidence and the decision threshold.
+///
+/// Every detector is calibrated against its own limit, so it reaches `threshold` exactly
+/// at the limit: blockhash at chat hash tolerance, SigLIP2 at `siglip2_min_similarity`,
+/// keyframes at `video_keyframes_min_similarity`. Confidence is weighted mean of calibrated detectors
+/// present on both sides, so detectors with more weight may outvote the others.
+/// Landscape, portrait and square grids are one detector, the best of them counts.
+/// Zero weight leaves detector out.
+#[derive(Debug, Clone)]
+pub struct MatchScoring {
+    pub landscape_weight: f32,
+    pub portrait_weight: f32,
+    pub square_weight: f32,
+    pub siglip2_weight: f32,
+    pub video_thumbnail_weight: f32,
+    pub video_keyframes_weight: f32,
+    pub center_crop_weight: f32,
+    pub threshold: f32,
+    pub siglip2_min_similarity: f32,
+    pub video_keyframes_min_similarity: f32,
+}
+
+impl Default for MatchScoring {
+    fn default() -> Self {
+        Self {
+            landscape_weight: 1.0,
+            portrait_weight: 1.0,
+            square_weight: 1.0,
+            siglip2_weight: 3.0,
+            video_thumbnail_weight: 1.0,
+            video_keyframes_weight: 3.0,
+            center_crop_weight: 1.0,
+            threshold: 0.95,
+            siglip2_min_similarity: SIGLIP2_MIN_SIMILARITY,
+            video_keyframes_min_similarity: KEYFRAMES_MIN_SIMILARITY,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MatchScore {
+    pub confidence: f32,
+    pub similarities: Vec<(HashType, f32)>,
+}
+
+impl MatchScoring {
+    pub fn weight(&self, hash_type: HashType) -> f32 {
+        match hash_type {
+            HashType::PHashLandscape => self.landscape_weight,
+            HashType::PHashPortrait => self.portrait_weight,
+            HashType::PHashSquare => self.square_weight,
+            HashType::Siglip2 => self.siglip2_weight,
+            HashType::VideoThumbnail => self.video_thumbnail_weight,
+            HashType::VideoKeyframes => self.video_keyframes_weight,
+            HashType::CenterCrop => self.center_crop_weight,
+        }
+    }
+
+    /// Compare detectors present in both query and stored hashes
+    pub fn score(
+        &self,
+        query: &[CalculatedHash],
+        stored: &[CalculatedHash],
+        hash_tolerance: usize,
+    ) -> MatchScore {
+        let mut similarities = vec![];
+        // Best blockhash grid as (weight, calibrated), other detectors are summed at once
+        let mut blockhash: Option<(f32, f32)> = None;
+        let mut weighted_sum: f32 = 0.0;
+        let mut total_weight: f32 = 0.0;
+
+        for query_hash in query {
+            let Some(stored_hash) = stored
+                .iter()
+                .find(|stored_hash| stored_hash.hash_type == query_hash.hash_type)
+            else {
+                continue;
+            };
+
+            let similarity = match hash_similarity(query_hash, stored_hash) {
+                Ok(similarity) => similarity,
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to compare {} hashes: {e}",
+                        query_hash.hash_type.as_str()
+                    );
+                    continue;
+                }
+            };
+            similarities.push((query_hash.hash_type, similarity));
+
+            let weight = self.weight(query_hash.hash_type);
+            if weight <= 0.0 {
+                continue;
+            }
+            let calibrated = match query_hash.hash_type {
+                HashType::Siglip2 => {
+                    self.calibrate(1.0 - similarity, 1.0 - self.siglip2_min_similarity)
+                }
+                HashType::VideoKeyframes => {
+                    self.calibrate(1.0 - similarity, 1.0 - self.video_keyframes_min_similarity)
+                }
+                _ => match blockhash_distance(query_hash, stored_hash) {
+                    Ok((distance, _)) => self.calibrate(distance as f32, hash_tolerance as f32),
+                    Err(_) => continue,
+                },
+            };
+
+            if matches!(
+                query_hash.hash_type,
+                HashType::PHashLandscape | HashType::PHashPortrait | HashType::PHashSquare
+            ) {
+                if blockhash.is_none_or(|(_, best)| calibrated > best) {
+                    blockhash = Some((weight, calibrated));
+                }
+                continue;
+            }
+            weighted_sum += weight * calibrated;
+            total_weight += weight;
+        }
+
+        if let Some((weight, calibrated)) = blockhash {
+            weighted_sum += weight * calibrated;
+            total_weight += weight;
+        }
+        let confidence = if total_weight > 0.0 {
+            weighted_sum / total_weight
+        } else {
+            0.0
+        };
+
+        MatchScore {
+            confidence,
+            similarities,
+        }
+    }
+
+    /// Map difference from identical to confidence, `allowed` difference gives `threshold`
+    fn calibrate(&self, difference: f32, allowed: f32) -> f32 {
+        if allowed <= 0.0 {
+            return if difference <= 0.0 { 1.0 } else { 0.0 };
+        }
+        (1.0 - (1.0 - self.threshold) * difference / allowed).clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct ScoredMatch {
+    pub record: HashRecord,
+    pub confidence: f32,
+    pub similarities: Vec<(HashType, f32)>,
+    /// Transform of query image that matched the record
+    pub transform: Transform,
+}
+
+impl ScoredMatch {
+    /// Transform and border crop of original for alert, None if image matched as is
+    pub fn details(&self) -> Option<String> {
+        let mut details = vec![];
+        if self.transform != Transform::Identity {
+            details.push(format!("преобразование {}", self.transform.as_str()));
+        }
+        if let Some(crop) = &self.record.crop {
+            details.push(format!(
+                "у оригинала обрезаны края до {}x{} от {},{}",
+                crop.width, crop.height, crop.x, crop.y
+            ));
+        }
+        (!details.is_empty()).then(|| details.join(", "))
+    }
+}
+
+/// Hashes of transformed query image
+#[derive(Debug, Clone)]
+pub struct HashVariant {
+    pub transform: Transform,
+    pub hashes: Vec<CalculatedHash>,
+}
+
+/// Transform applied to query image before hashing
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Transform {
+    Identity,
+    FlipHorizontal,
+    FlipVertical,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+const TRANSFORM_IDENTITY: &str = "identity";
+const TRANSFORM_FLIP_HORIZONTAL: &str = "flip_h";
+const TRANSFORM_FLIP_VERTICAL: &str = "flip_v";
+const TRANSFORM_ROTATE_90: &str = "rot90";
+const TRANSFORM_ROTATE_180: &str = "rot180";
+const TRANSFORM_ROTATE_270: &str = "rot270";
+
+impl Transform {
+    /// Every transform except identity
+    pub const ALL_VARIANTS: [Transform; 5] = [
+        Transform::FlipHorizontal,
+        Transform::FlipVertical,
+        Transform::Rotate90,
+        Transform::Rotate180,
+        Transform::Rotate270,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Transform::Identity => TRANSFORM_IDENTITY,
+            Transform::FlipHorizontal => TRANSFORM_FLIP_HORIZONTAL,
+            Transform::FlipVertical => TRANSFORM_FLIP_VERTICAL,
+            Transform::Rotate90 => TRANSFORM_ROTATE_90,
+            Transform::Rotate180 => TRANSFORM_ROTATE_180,
+            Transform::Rotate270 => TRANSFORM_ROTATE_270,
+        }
+    }
+
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        match self {
+            Transform::Identity => img.clone(),
+            Transform::FlipHorizontal => img.fliph(),
+            Transform::FlipVertical => img.flipv(),
+            Transform::Rotate90 => img.rotate90(),
+            Transform::Rotate180 => img.rotate180(),
+            Transform::Rotate270 => img.rotate270(),
+        }
+    }
+}
+
+impl FromStr for Transform {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            TRANSFORM_IDENTITY => Ok(Transform::Identity),
+            TRANSFORM_FLIP_HORIZONTAL => Ok(Transform::FlipHorizontal),
+            TRANSFORM_FLIP_VERTICAL => Ok(Transform::FlipVertical),
+            TRANSFORM_ROTATE_90 => Ok(Transform::Rotate90),
+            TRANSFORM_ROTATE_180 => Ok(Transform::Rotate180),
+            TRANSFORM_ROTATE_270 => Ok(Transform::Rotate270),
+            _ => Err(anyhow::format_err!("Unknown transform {s}")),
+        }
+    }
+}
+
+/// Similarity of two hashes of the same type in `[0, 1]`
+pub fn hash_similarity(a: &CalculatedHash, b: &CalculatedHash) -> Result<f32, anyhow::Error> {
+    if a.hash_type == HashType::Siglip2 {
+        let embedding_a = siglip2::embedding_from_base64(&a.hash)?;
+        let embedding_b = siglip2::embedding_from_base64(&b.hash)?;
+        if embedding_a.len() != embedding_b.len() {
+            return Err(anyhow::format_err!("Embedding size mismatch"));
+        }
+        return Ok(siglip2::cosine_similarity_normalized(&embedding_a, &embedding_b).max(0.0));
+    }
+    if a.hash_type == HashType::VideoKeyframes {
+        let frames_a = keyframes::decode_sequence(&a.hash)?;
+        let frames_b = keyframes::decode_sequence(&b.hash)?;
+        return Ok(keyframes::sequence_similarity(&frames_a, &frames_b));
+    }
+
+    let (distance, bits) = blockhash_distance(a, b)?;
+    Ok(1.0 - distance as f32 / bits as f32)
+}
+
+/// Hamming distance of two blockhashes and their size in bits
+fn blockhash_distance(
+    a: &CalculatedHash,
+    b: &CalculatedHash,
+) -> Result<(u32, usize), anyhow::Error> {
+    let hash_a: ImageHash<Box<[u8]>> =
+        ImageHash::from_base64(&a.hash).map_err(|e| anyhow::format_err!("Invalid hash: {e:?}"))?;
+    let hash_b: ImageHash<Box<[u8]>> =
+        ImageHash::from_base64(&b.hash).map_err(|e| anyhow::format_err!("Invalid hash: {e:?}"))?;
+    let bits = hash_a.as_bytes().len().max(hash_b.as_bytes().len()) * 8;
+    if bits == 0 {
+        return Err(anyhow::format_err!("Empty hash"));
+    }
+    Ok((hash_a.dist(&hash_b), bits))
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum HashType {
     PHashLandscape,
     PHashPortrait,
     PHashSquare,
     Siglip2,
+    /// Square blockhash of Telegram video thumbnail
+    VideoThumbnail,
+    /// Square blockhashes of sampled video frames
+    VideoKeyframes,
+    /// Square blockhash of central part of image
+    CenterCrop,
 }
 
 const PHASH_LANDSCAPE: &'static str = "landscape";
 const PHASH_PORTRAIT: &'static str = "portrait";
 const PHASH_SQUARE: &'static str = "square";
 const SIGLIP2: &'static str = "siglip2";
+const VIDEO_THUMBNAIL: &str = "video_thumbnail";
+const VIDEO_KEYFRAMES: &str = "video_keyframes";
+const CENTER_CROP: &str = "center_crop";
 
 impl HashType {
     pub fn as_str(&self) -> &'static str {
@@ -353,8 +1514,16 @@ impl HashType {
             HashType::PHashPortrait => PHASH_PORTRAIT,
             HashType::PHashSquare => PHASH_SQUARE,
             HashType::Siglip2 => SIGLIP2,
+            HashType::VideoThumbnail => VIDEO_THUMBNAIL,
+            HashType::VideoKeyframes => VIDEO_KEYFRAMES,
+            HashType::CenterCrop => CENTER_CROP,
         }
     }
+
+    /// Single blockhash searchable by hamming distance
+    pub fn is_hamming(&self) -> bool {
+        !matches!(self, HashType::Siglip2 | HashType::VideoKeyframes)
+    }
 }
 
 impl FromStr for HashType {
@@ -366,7 +1535,182 @@ impl FromStr for HashType {
             PHASH_PORTRAIT => Ok(HashType::PHashPortrait),
             PHASH_SQUARE => Ok(HashType::PHashSquare),
             SIGLIP2 => Ok(HashType::Siglip2),
+            VIDEO_THUMBNAIL => Ok(HashType::VideoThumbnail),
+            VIDEO_KEYFRAMES => Ok(HashType::VideoKeyframes),
+            CENTER_CROP => Ok(HashType::CenterCrop),
             _ => Err(()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH_BYTES: usize = 19;
+
+    /// Blockhash of 152 bits differing from zero hash in `distance` bits
+    fn blockhash(hash_type: HashType, distance: usize) -> CalculatedHash {
+        let mut bytes = [0u8; HASH_BYTES];
+        for bit in 0..distance {
+            bytes[bit / 8] |= 1 << (bit % 8);
+        }
+        let hash = ImageHash::<Box<[u8]>>::from_bytes(&bytes).unwrap();
+        CalculatedHash {
+            hash_type,
+            hash: hash.to_base64(),
+        }
+    }
+
+    fn embedding(similarity: f32) -> CalculatedHash {
+        let embedding = [similarity, (1.0 - similarity * similarity).sqrt()];
+        CalculatedHash {
+            hash_type: HashType::Siglip2,
+            hash: siglip2::embedding_to_base64(&embedding),
+        }
+    }
+
+    fn zero_hashes() -> Vec<CalculatedHash> {
+        vec![
+            blockhash(HashType::PHashLandscape, 0),
+            blockhash(HashType::PHashPortrait, 0),
+            blockhash(HashType::PHashSquare, 0),
+            embedding(1.0),
+        ]
+    }
+
+    #[test]
+    fn blockhash_hit_within_tolerance_matches() {
+        let scoring = MatchScoring::default();
+        let query = vec![
+            blockhash(HashType::PHashLandscape, 4),
+            blockhash(HashType::PHashPortrait, 10),
+            blockhash(HashType::PHashSquare, 12),
+        ];
+
+        let score = scoring.score(&query, &zero_hashes(), PERCEPTIVE_HASH_TOLERANCE);
+
+        assert!(score.confidence >= scoring.threshold, "{score:?}");
+    }
+
+    #[test]
+    fn siglip2_outweighs_blockhash_slightly_over_tolerance() {
+        let scoring = MatchScoring::default();
+        let query = vec![
+            blockhash(HashType::PHashLandscape, PERCEPTIVE_HASH_TOLERANCE + 2),
+            blockhash(HashType::PHashPortrait, 30),
+            blockhash(HashType::PHashSquare, 30),
+            embedding(0.99),
+        ];
+
+        let score = scoring.score(&query, &zero_hashes(), PERCEPTIVE_HASH_TOLERANCE);
+
+        assert!(score.confidence >= scoring.threshold, "{score:?}");
+    }
+
+    #[test]
+    fn blockhash_hit_with_low_siglip2_is_not_match() {
+        let scoring = MatchScoring::default();
+        // Same template with different content
+        let query = vec![
+            blockhash(HashType::PHashLandscape, 0),
+            blockhash(HashType::PHashPortrait, 0),
+            blockhash(HashType::PHashSquare, 0),
+            embedding(0.5),
+        ];
+
+        let score = scoring.score(&query, &zero_hashes(), PERCEPTIVE_HASH_TOLERANCE);
+
+        assert!(score.confidence < scoring.threshold, "{score:?}");
+    }
+
+    #[test]
+    fn every_detector_over_limit_is_not_match() {
+        let scoring = MatchScoring::default();
+        let query = vec![
+            blockhash(HashType::PHashLandscape, PERCEPTIVE_HASH_TOLERANCE + 1),
+            blockhash(HashType::PHashSquare, 30),
+            embedding(0.9),
+        ];
+
+        let score = scoring.score(&query, &zero_hashes(), PERCEPTIVE_HASH_TOLERANCE);
+
+        assert!(score.confidence < scoring.threshold, "{score:?}");
+    }
+
+    #[test]
+    fn zero_weight_detector_is_ignored() {
+        let scoring = MatchScoring {
+            siglip2_weight: 0.0,
+            ..MatchScoring::default()
+        };
+        let query = vec![blockhash(HashType::PHashSquare, 30), embedding(1.0)];
+
+        let score = scoring.score(&query, &zero_hashes(), PERCEPTIVE_HASH_TOLERANCE);
+
+        assert!(score.confidence < scoring.threshold, "{score:?}");
+    }
+
+    /// Clip of one frame differing from zero frame in `distance` bits
+    fn keyframes(distance: usize) -> CalculatedHash {
+        let frame =
+            ImageHash::<Box<[u8]>>::from_base64(&blockhash(HashType::PHashSquare, distance).hash)
+                .unwrap();
+        CalculatedHash {
+            hash_type: HashType::VideoKeyframes,
+            hash: keyframes::encode_sequence(&[frame]),
+        }
+    }
+
+    #[test]
+    fn keyframes_are_calibrated_against_own_limit() {
+        let scoring = MatchScoring {
+            video_keyframes_min_similarity: 0.9,
+            ..MatchScoring::default()
+        };
+        let stored = vec![keyframes(0)];
+
+        // Similarity about 0.92 is below threshold, but within keyframes limit
+        let close = scoring.score(&[keyframes(12)], &stored, PERCEPTIVE_HASH_TOLERANCE);
+        let far = scoring.score(&[keyframes(20)], &stored, PERCEPTIVE_HASH_TOLERANCE);
+
+        assert!(close.confidence >= scoring.threshold, "{close:?}");
+        assert!(far.confidence < scoring.threshold, "{far:?}");
+    }
+
+    fn scored_match(transform: Transform, crop: Option<Crop>) -> ScoredMatch {
+        ScoredMatch {
+            record: HashRecord {
+                id: 1,
+                filename: String::new(),
+                hash: String::new(),
+                file_id: String::new(),
+                chat_id: -100,
+                message_id: 1,
+                media_group_id: None,
+                crop,
+            },
+            confidence: 1.0,
+            similarities: vec![],
+            transform,
+        }
+    }
+
+    #[test]
+    fn match_details_show_transform_and_original_crop() {
+        let crop = Crop {
+            x: 0,
+            y: 40,
+            width: 640,
+            height: 400,
+        };
+
+        assert_eq!(scored_match(Transform::Identity, None).details(), None);
+        assert_eq!(
+            scored_match(Transform::Rotate90, Some(crop))
+                .details()
+                .unwrap(),
+            "преобразование rot90, у оригинала обрезаны края до 640x400 от 0,40"
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! Embedded HTTP server with health, readiness and status endpoints.
+//!
+//! - `/healthz` answers while process is alive
+//! - `/readyz` checks database, storage and, in polling mode, recent `getUpdates` success
+//! - `/status` reports update offset, running tasks and indexed images of every chat
+//!
+//! `/status` shows chat ids, so server should listen on private address only.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicI64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use serde_json::{json, Value};
+use tokio::{
+    net::TcpListener,
+    sync::{Mutex, Semaphore, SemaphorePermit},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    count_indexed_images,
+    db::create_db,
+    storage::{AnyFileStorage, FileStorage},
+};
+
+pub const DEFAULT_MAX_POLL_AGE_SECONDS: u64 = 120;
+/// Longer check is reported as failed, so probe doesn't hang on locked DB
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Clone)]
+pub struct HealthConfig {
+    pub listen_addr: SocketAddr,
+    /// Bot isn't ready if last successful `getUpdates` is older, not checked in webhook mode
+    pub max_poll_age: Option<Duration>,
+}
+
+/// Update processing state shared by update handlers and status endpoints
+#[derive(Debug)]
+pub struct BotStatus {
+    started_at: Instant,
+    update_offset: AtomicI64,
+    last_poll: std::sync::Mutex<Option<Instant>>,
+    in_flight: AtomicUsize,
+    /// Updates over limit wait for permit, so bursts don't load all images at once
+    limiter: Semaphore,
+    max_concurrent_updates: usize,
+}
+
+/// Task counted as in flight until dropped
+pub struct TaskGuard<'a> {
+    _in_flight: InFlight<'a>,
+    _permit: SemaphorePermit<'a>,
+}
+
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl BotStatus {
+    pub fn new(max_concurrent_updates: usize) -> Self {
+        Self {
+            started_at: Instant::now(),
+            update_offset: AtomicI64::new(0),
+            last_poll: std::sync::Mutex::new(None),
+            in_flight: AtomicUsize::new(0),
+            limiter: Semaphore::new(max_concurrent_updates),
+            max_concurrent_updates,
+        }
+    }
+
+    /// Wait for free slot, task waiting for it is in flight too
+    pub async fn start_task(&self) -> Option<TaskGuard<'_>> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let in_flight = InFlight(&self.in_flight);
+        let permit = self.limiter.acquire().await.ok()?;
+        Some(TaskGuard {
+            _in_flight: in_flight,
+            _permit: permit,
+        })
+    }
+
+    pub fn set_update_offset(&self, offset: i64) {
+        self.update_offset.fetch_max(offset, Ordering::Relaxed);
+    }
+
+    pub fn record_poll_success(&self) {
+        *self.last_poll.lock().unwrap() = Some(Instant::now());
+    }
+
+    fn last_poll_age(&self) -> Option<Duration> {
+        self.last_poll.lock().unwrap().map(|last| last.elapsed())
+    }
+}
+
+#[derive(Clone)]
+struct HealthState {
+    status: Arc<BotStatus>,
+    db_path: Arc<String>,
+    storage: Arc<Mutex<AnyFileStorage>>,
+    max_poll_age: Option<Duration>,
+}
+
+/// Serve endpoints until `shutdown` is cancelled
+#[tracing::instrument(name = "Health server", skip_all)]
+pub async fn serve(
+    config: &HealthConfig,
+    status: Arc<BotStatus>,
+    db_path: &str,
+    storage: Arc<Mutex<AnyFileStorage>>,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let state = HealthState {
+        status,
+        db_path: Arc::new(db_path.to_owned()),
+        storage,
+        max_poll_age: config.max_poll_age,
+    };
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/status", get(report_status))
+        .with_state(state);
+
+    let listener = TcpListener::bind(config.listen_addr)
+        .await
+        .map_err(|e| anyhow::format_err!("Failed to bind {}: {e}", config.listen_addr))?;
+    tracing::info!("Health server listening on {}", config.listen_addr);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+        .map_err(|e| anyhow::format_err!("Health server failed: {e}"))
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+async fn readyz(State(state): State<HealthState>) -> Response {
+    let (database, storage) = tokio::join!(check_database(&state), check_storage(&state));
+    let polling = check_polling(&state);
+
+    let ready = database.is_ok() && storage.is_ok() && polling.is_ok();
+    let code = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let check_result = |result: Result<(), anyhow::Error>| match result {
+        Ok(()) => "ok".to_owned(),
+        Err(e) => {
+            tracing::warn!("Readiness check failed: {e}");
+            e.to_string()
+        }
+    };
+    let body = json!({
+        "ready": ready,
+        "checks": {
+            "database": check_result(database),
+            "storage": check_result(storage),
+            "polling": check_result(polling),
+        },
+    });
+    json_response(code, &body)
+}
+
+async fn report_status(State(state): State<HealthState>) -> Response {
+    let db_path = state.db_path.clone();
+    let chats = tokio::task::spawn_blocking(move || {
+        let conn = open_db(&db_path)?;
+        count_indexed_images(&conn).map_err(|e| anyhow::format_err!("Failed to count images: {e}"))
+    })
+    .await
+    .map_err(|e| anyhow::format_err!("Failed to count images: {e}"))
+    .and_then(|result| result);
+    let chats = match chats {
+        Ok(chats) => chats,
+        Err(e) => {
+            tracing::error!("{e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &json!({ "error": e.to_string() }),
+            );
+        }
+    };
+
+    let status = &state.status;
+    let update_offset = status.update_offset.load(Ordering::Relaxed);
+    let body = json!({
+        "version": VERSION,
+        "uptime_seconds": status.started_at.elapsed().as_secs(),
+        "update_offset": Some(update_offset).filter(|offset| *offset > 0),
+        "last_poll_seconds_ago": status.last_poll_age().map(|age| age.as_secs()),
+        "in_flight_tasks": status.in_flight.load(Ordering::Relaxed),
+        "max_concurrent_updates": status.max_concurrent_updates,
+        "chats": chats
+            .iter()
+            .map(|(chat_id, images)| json!({ "chat_id": chat_id, "images": images }))
+            .collect::<Vec<_>>(),
+    });
+    json_response(StatusCode::OK, &body)
+}
+
+/// Own connection, so locked DB is found even if bot connection is busy
+fn open_db(db_path: &str) -> Result<rusqlite::Connection, anyhow::Error> {
+    let conn = create_db(db_path).map_err(|_| anyhow::format_err!("Failed to open database"))?;
+    conn.busy_timeout(CHECK_TIMEOUT)
+        .map_err(|e| anyhow::format_err!("Failed to set busy timeout: {e}"))?;
+    Ok(conn)
+}
+
+async fn check_database(state: &HealthState) -> Result<(), anyhow::Error> {
+    let db_path = state.db_path.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = open_db(&db_path)?;
+        conn.prepare("SELECT 1 FROM hashes LIMIT 1")
+            .and_then(|mut stmt| stmt.exists([]))
+            .map_err(|e| anyhow::format_err!("Database query failed: {e}"))?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| anyhow::format_err!("Database check failed: {e}"))?
+}
+
+async fn check_storage(state: &HealthState) -> Result<(), anyhow::Error> {
+    let check = async { state.storage.lock().await.check().await };
+    tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .map_err(|_| anyhow::format_err!("Storage check timed out"))?
+}
+
+fn check_polling(state: &HealthState) -> Result<(), anyhow::Error> {
+    let Some(max_poll_age) = state.max_poll_age else {
+        return Ok(());
+    };
+    match state.status.last_poll_age() {
+        Some(age) if age <= max_poll_age => Ok(()),
+        Some(age) => Err(anyhow::format_err!(
+            "Last getUpdates success {} seconds ago",
+            age.as_secs()
+        )),
+        None => Err(anyhow::format_err!("No successful getUpdates yet")),
+    }
+}
+
+fn json_response(code: StatusCode, body: &Value) -> Response {
+    (
+        code,
+        [(header::CONTENT_TYPE, "application/json")],
+        body.to_string(),
+    )
+        .into_response()
+}
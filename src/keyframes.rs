@@ -0,0 +1,201 @@
+use std::{
+    ffi::OsStr,
+    path::Path,
+    process::{Output, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use image::{DynamicImage, RgbImage};
+use image_hasher::ImageHash;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+pub const DEFAULT_KEYFRAMES_COUNT: usize = 8;
+/// Sequence similarity at which clips are still considered same
+pub const KEYFRAMES_MIN_SIMILARITY: f32 = 0.95;
+/// Frames are scaled to the same square, hashes don't depend on source resolution
+const FRAME_SIZE: u32 = 128;
+const FRAME_SEPARATOR: char = '.';
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Samples evenly spaced frames from video or animation with ffmpeg
+#[derive(Debug, Clone)]
+pub struct KeyframeExtractor {
+    ffmpeg_path: String,
+    frames_count: usize,
+}
+
+impl KeyframeExtractor {
+    pub fn new(ffmpeg_path: &str, frames_count: usize) -> Self {
+        Self {
+            ffmpeg_path: ffmpeg_path.to_owned(),
+            frames_count: frames_count.max(1),
+        }
+    }
+
+    #[tracing::instrument(name = "Extract keyframes", skip(self, video))]
+    pub async fn extract(
+        &self,
+        video: &[u8],
+        duration_seconds: u32,
+    ) -> Result<Vec<DynamicImage>, anyhow::Error> {
+        // Most containers need seeking, so video is passed to ffmpeg as file instead of stdin
+        let video_path = std::env::temp_dir().join(format!(
+            "img_dupes_{}_{}",
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::write(&video_path, video)
+            .await
+            .map_err(|e| anyhow::format_err!("Failed to write video to temp file: {e}"))?;
+
+        let result = self.extract_from_file(&video_path, duration_seconds).await;
+
+        if let Err(e) = tokio::fs::remove_file(&video_path).await {
+            tracing::warn!("Failed to remove temp video file: {e}");
+        }
+        result
+    }
+
+    /// Same as `extract`, but video is piped to ffmpeg and never written to disk.
+    ///
+    /// Used in hash-only mode. Containers needing seeking, like MP4 with index at the end,
+    /// can't be decoded from pipe, such clips are matched by thumbnail only.
+    #[tracing::instrument(name = "Extract keyframes from pipe", skip(self, video))]
+    pub async fn extract_piped(
+        &self,
+        video: &[u8],
+        duration_seconds: u32,
+    ) -> Result<Vec<DynamicImage>, anyhow::Error> {
+        let mut child = self
+            .ffmpeg_command(OsStr::new("pipe:0"), duration_seconds)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow::format_err!("Failed to run ffmpeg: {e}"))?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow::format_err!("ffmpeg stdin isn't piped"))?;
+
+        // Input is written while output is read, ffmpeg may quit before reading everything
+        let write = async move {
+            if let Err(e) = stdin.write_all(video).await {
+                tracing::debug!("ffmpeg stopped reading video: {e}");
+            }
+        };
+        let (_, output) = tokio::join!(write, child.wait_with_output());
+        let output = output.map_err(|e| anyhow::format_err!("Failed to run ffmpeg: {e}"))?;
+
+        frames_from_output(output)
+    }
+
+    async fn extract_from_file(
+        &self,
+        video_path: &Path,
+        duration_seconds: u32,
+    ) -> Result<Vec<DynamicImage>, anyhow::Error> {
+        let output = self
+            .ffmpeg_command(video_path.as_os_str(), duration_seconds)
+            .output()
+            .await
+            .map_err(|e| anyhow::format_err!("Failed to run ffmpeg: {e}"))?;
+
+        frames_from_output(output)
+    }
+
+    fn ffmpeg_command(&self, input: &OsStr, duration_seconds: u32) -> Command {
+        let fps = self.frames_count as f64 / f64::from(duration_seconds.max(1));
+        let mut command = Command::new(&self.ffmpeg_path);
+        command
+            .arg("-v")
+            .arg("error")
+            .arg("-i")
+            .arg(input)
+            .arg("-vf")
+            .arg(format!("fps={fps},scale={FRAME_SIZE}:{FRAME_SIZE}"))
+            .arg("-frames:v")
+            .arg(self.frames_count.to_string())
+            .arg("-f")
+            .arg("rawvideo")
+            .arg("-pix_fmt")
+            .arg("rgb24")
+            .arg("pipe:1");
+        command
+    }
+}
+
+/// Raw RGB frames printed by ffmpeg to stdout
+fn frames_from_output(output: Output) -> Result<Vec<DynamicImage>, anyhow::Error> {
+    if !output.status.success() {
+        return Err(anyhow::format_err!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let frame_len = (FRAME_SIZE * FRAME_SIZE * 3) as usize;
+    let frames: Vec<DynamicImage> = output
+        .stdout
+        .chunks_exact(frame_len)
+        .filter_map(|frame| RgbImage::from_raw(FRAME_SIZE, FRAME_SIZE, frame.to_vec()))
+        .map(DynamicImage::ImageRgb8)
+        .collect();
+
+    if frames.is_empty() {
+        return Err(anyhow::format_err!("No frames extracted"));
+    }
+    Ok(frames)
+}
+
+/// Join frame hashes into single fingerprint string
+pub fn encode_sequence(frames: &[ImageHash<Box<[u8]>>]) -> String {
+    frames
+        .iter()
+        .map(|frame| frame.to_base64())
+        .collect::<Vec<_>>()
+        .join(&FRAME_SEPARATOR.to_string())
+}
+
+pub fn decode_sequence(fingerprint: &str) -> Result<Vec<ImageHash<Box<[u8]>>>, anyhow::Error> {
+    fingerprint
+        .split(FRAME_SEPARATOR)
+        .map(|frame| {
+            ImageHash::from_base64(frame)
+                .map_err(|e| anyhow::format_err!("Invalid frame hash: {e:?}"))
+        })
+        .collect()
+}
+
+/// Similarity of two frame sequences in `[0, 1]`.
+///
+/// Every frame is matched to the closest frame of other clip regardless of position,
+/// and the better covered direction wins, so trimmed clip still matches the original.
+pub fn sequence_similarity(a: &[ImageHash<Box<[u8]>>], b: &[ImageHash<Box<[u8]>>]) -> f32 {
+    coverage(a, b).max(coverage(b, a))
+}
+
+fn coverage(query: &[ImageHash<Box<[u8]>>], stored: &[ImageHash<Box<[u8]>>]) -> f32 {
+    if query.is_empty() || stored.is_empty() {
+        return 0.0;
+    }
+
+    let total: f32 = query
+        .iter()
+        .map(|query_frame| {
+            stored
+                .iter()
+                .map(|stored_frame| {
+                    let bits = query_frame
+                        .as_bytes()
+                        .len()
+                        .max(stored_frame.as_bytes().len())
+                        * 8;
+                    1.0 - query_frame.dist(stored_frame) as f32 / bits.max(1) as f32
+                })
+                .fold(0.0, f32::max)
+        })
+        .sum();
+    total / query.len() as f32
+}
@@ -1,17 +1,34 @@
-use hasher::MIN_VOTES_COUNT;
-use models::{HashRecord, VoteResult, VoteType, VoterName, VotingRecord, VotingType};
-use rusqlite::{Connection, Result};
+use std::collections::HashSet;
 
+use models::{
+    ChatDefaults, ChatSettings, HashRecord, VoteResult, VoteType, VoterName, VotingRecord,
+    VotingType,
+};
+use rusqlite::{Connection, OptionalExtension, Result};
+
+pub mod config;
 pub mod data;
+pub mod dataset;
 pub mod db;
+pub mod embedding_index;
+pub mod hamming_index;
 pub mod hasher;
+pub mod health;
 pub mod keyboards;
+pub mod keyframes;
 pub mod metrics;
 mod models;
+pub mod normalize;
+pub mod prometheus;
+pub mod retention;
 pub mod siglip2;
 pub mod storage;
+#[cfg(test)]
+mod test_utils;
 pub mod tg_callbacks;
+pub mod tg_commands;
 pub mod tracing_setup;
+pub mod webhook;
 
 pub fn find_image_by_unique_file_id(
     conn: &Connection,
@@ -43,49 +60,80 @@ pub fn find_image_by_unique_file_id(
         chat_id: row.get(4).unwrap_or_default(),
         message_id: row.get(5).unwrap_or_default(),
         media_group_id: None,
+        crop: None,
     })
 }
 
-pub fn find_similar_hashes(
+/// Load hash records by ids keeping the order of `ids`
+pub fn find_hashes_by_ids(conn: &Connection, ids: &[i32]) -> Result<Vec<HashRecord>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, filename, base64_hash, file_id, chat_id, message_id, media_group_id, crop FROM hashes WHERE id = ?",
+    )?;
+
+    let mut records = Vec::with_capacity(ids.len());
+    for id in ids {
+        let mut rows = stmt.query(rusqlite::params![id])?;
+        if let Some(row) = rows.next()? {
+            let media_group_id: Option<String> = row.get(6).unwrap_or(None);
+            let media_group_id =
+                media_group_id.filter(|media_group_id| !media_group_id.trim().is_empty());
+
+            records.push(HashRecord {
+                id: row.get(0).unwrap_or_default(),
+                filename: row.get(1).unwrap_or_default(),
+                hash: row.get(2).unwrap_or_default(),
+                file_id: row.get(3).unwrap_or_default(),
+                chat_id: row.get(4).unwrap_or_default(),
+                message_id: row.get(5).unwrap_or_default(),
+                media_group_id,
+                crop: row
+                    .get::<_, Option<String>>(7)
+                    .unwrap_or(None)
+                    .and_then(|crop| crop.parse().ok()),
+            });
+        }
+    }
+
+    Ok(records)
+}
+
+pub fn find_hashes_by_file_id(
+    conn: &Connection,
+    chat_id: i64,
+    file_id: &str,
+) -> Result<Vec<(String, String)>> {
+    let mut stmt = conn
+        .prepare("SELECT orientation, base64_hash FROM hashes WHERE chat_id = ? AND file_id = ?")?;
+
+    let mut rows = stmt.query(rusqlite::params![chat_id, file_id])?;
+
+    let mut hashes = Vec::new();
+    while let Some(row) = rows.next()? {
+        hashes.push((row.get(0)?, row.get(1)?));
+    }
+
+    Ok(hashes)
+}
+
+/// Ids and hashes of one type posted in chat after `from_timestamp`
+pub fn find_hashes_by_type(
     conn: &Connection,
-    input_hash: &str,
-    max_distance: usize,
     chat_id: i64,
+    orientation: &str,
     from_timestamp: u64,
-) -> Result<Vec<HashRecord>> {
+) -> Result<Vec<(i32, String)>> {
     let mut stmt = conn.prepare(
-        "SELECT id, filename, base64_hash, file_id, chat_id, message_id, media_group_id, hamming_distance(base64_hash, ?) as dist FROM hashes WHERE chat_id  = ? AND dist < ? AND created_at > ? ORDER by dist ASC",
-    ).map_err(|e|{
-        eprint!("Failed to execute query to search similar {e}");
-        e
-    })?;
+        "SELECT id, base64_hash FROM hashes WHERE chat_id = ? AND orientation = ? AND created_at > ?",
+    )?;
 
-    let mut rows = stmt.query(rusqlite::params![
-        input_hash,
-        chat_id,
-        max_distance,
-        from_timestamp
-    ])?;
+    let mut rows = stmt.query(rusqlite::params![chat_id, orientation, from_timestamp])?;
 
-    // Collect results
-    let mut similar_hashes = Vec::new();
+    let mut hashes = Vec::new();
     while let Some(row) = rows.next()? {
-        let media_group_id: Option<String> = row.get(6).unwrap_or(None);
-        let media_group_id =
-            media_group_id.filter(|media_group_id| !media_group_id.trim().is_empty());
-
-        similar_hashes.push(HashRecord {
-            id: row.get(0).unwrap_or_default(),
-            filename: row.get(1).unwrap_or_default(),
-            hash: row.get(2).unwrap_or_default(),
-            file_id: row.get(3).unwrap_or_default(),
-            chat_id: row.get(4).unwrap_or_default(),
-            message_id: row.get(5).unwrap_or_default(),
-            media_group_id,
-        });
+        hashes.push((row.get(0)?, row.get(1)?));
     }
 
-    Ok(similar_hashes)
+    Ok(hashes)
 }
 
 pub fn delete_old_hash(conn: &Connection, hash_id: i32) -> Result<(), rusqlite::Error> {
@@ -147,6 +195,7 @@ pub fn create_vote(
     user_id: u64,
     username: &str,
     vote_type: VoteType,
+    chat_defaults: &ChatDefaults,
 ) -> Result<VoteResult, anyhow::Error> {
     if is_already_voted(&db, voting_id, user_id)? {
         return Ok(VoteResult::AlreadyVoted);
@@ -175,9 +224,13 @@ pub fn create_vote(
     }
 
     let votes_count = get_votes_count(voting_id, &db)?;
+    let voting_info = get_voting_info(db, voting_id)?;
+    let min_votes_count = get_chat_settings(db, voting_info.chat_id, chat_defaults)
+        .map_err(|e| anyhow::format_err!("Chat settings query error {e}"))?
+        .min_votes_count;
 
     let (voting_result, score) = get_voting_result(&db, voting_id)?;
-    if votes_count >= MIN_VOTES_COUNT || score.abs() >= MIN_VOTES_COUNT / 2 {
+    if is_voting_finished(votes_count, score, min_votes_count) {
         let voters = get_voting_names(&db, voting_id)?;
 
         return Ok(VoteResult::Finished(voters, voting_result));
@@ -187,6 +240,45 @@ pub fn create_vote(
     Ok(VoteResult::InProgress(voters))
 }
 
+/// Quorums up to this size are not finished by a single vote
+const SMALL_QUORUM: i64 = 3;
+
+/// Voting is finished by enough votes or by clear majority
+pub fn is_voting_finished(votes_count: i64, score: i64, min_votes_count: i64) -> bool {
+    if votes_count >= min_votes_count {
+        return true;
+    }
+    // Half of small quorum is one vote, so it takes two agreeing votes
+    if min_votes_count <= SMALL_QUORUM {
+        return score.abs() >= 2;
+    }
+    score.abs() >= min_votes_count / 2
+}
+
+/// Votings having any votes with votes count and sum of votes
+pub fn find_voting_scores(conn: &Connection) -> Result<Vec<(VotingRecord, i64, i64)>> {
+    let mut stmt = conn.prepare(
+        r"SELECT vti.id, vti.chat_id, vti.message_id, vti.original_message_id, vti.voting_type, COUNT(vot.id), SUM(vot.vote_type)
+        FROM votings vti JOIN votes vot ON vti.id = vot.voting_id GROUP BY vti.id ORDER BY vti.id",
+    )?;
+    let votings = stmt
+        .query_map([], |row| {
+            Ok((
+                VotingRecord {
+                    id: row.get(0)?,
+                    chat_id: row.get(1)?,
+                    message_id: row.get(2)?,
+                    original_message_id: row.get(3)?,
+                    voting_type: row.get(4)?,
+                },
+                row.get(5)?,
+                row.get(6)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(votings)
+}
+
 fn get_votes_count(voting_id: i64, tx: &Connection) -> Result<i64, anyhow::Error> {
     let mut vote_count_query = tx.prepare(
         r"SELECT COUNT(vot.id) FROM votings vti JOIN votes vot ON vti.id = vot.voting_id WHERE vti.id = ?"
@@ -255,7 +347,7 @@ fn is_already_voted(
 
 fn get_voting_info(conn: &Connection, voting_id: i64) -> Result<VotingRecord, anyhow::Error> {
     let mut voting_query = conn
-        .prepare(r"SELECT id, chat_id, message_id, voting_type FROM votings WHERE id = ?")
+        .prepare(r"SELECT id, chat_id, message_id, original_message_id, voting_type FROM votings WHERE id = ?")
         .map_err(|e| {
             tracing::error!("Compile statement error {}", e);
             anyhow::format_err!("Compile statement error {e}")
@@ -270,11 +362,13 @@ fn get_voting_info(conn: &Connection, voting_id: i64) -> Result<VotingRecord, an
         let id = row.get(0)?;
         let chat_id = row.get(1)?;
         let message_id = row.get(2)?;
-        let voting_type: VotingType = row.get(3)?;
+        let original_message_id = row.get(3)?;
+        let voting_type: VotingType = row.get(4)?;
         Ok(VotingRecord {
             id,
             chat_id,
             message_id,
+            original_message_id,
             voting_type,
         })
     } else {
@@ -311,3 +405,392 @@ fn get_voting_result(conn: &Connection, voting_id: i64) -> Result<(VoteType, i64
 
     Err(anyhow::format_err!("Failed to query final vote result"))
 }
+
+/// Saved settings of chat, or `defaults` if chat never changed them
+pub fn get_chat_settings(
+    conn: &Connection,
+    chat_id: i64,
+    defaults: &ChatDefaults,
+) -> Result<ChatSettings> {
+    let settings = conn
+        .query_row(
+            "SELECT hash_tolerance, search_distance_seconds, min_votes_count, match_threshold, blockhash_enabled, siglip2_enabled, stickers_enabled, mute_duration_seconds, retention_seconds, hash_only FROM chat_settings WHERE chat_id = ?",
+            rusqlite::params![chat_id],
+            |row| {
+                Ok(ChatSettings {
+                    chat_id,
+                    hash_tolerance: row.get(0)?,
+                    search_distance_seconds: row.get(1)?,
+                    min_votes_count: row.get(2)?,
+                    match_threshold: row.get(3)?,
+                    blockhash_enabled: row.get(4)?,
+                    siglip2_enabled: row.get(5)?,
+                    stickers_enabled: row.get(6)?,
+                    mute_duration_seconds: row.get(7)?,
+                    retention_seconds: row.get(8)?,
+                    hash_only: row.get(9)?,
+                })
+            },
+        )
+        .optional()?;
+
+    Ok(settings.unwrap_or_else(|| ChatSettings::new(chat_id, defaults)))
+}
+
+pub fn save_chat_settings(conn: &Connection, settings: &ChatSettings) -> Result<()> {
+    conn.execute(
+        r"INSERT INTO chat_settings(chat_id, hash_tolerance, search_distance_seconds, min_votes_count, match_threshold, blockhash_enabled, siglip2_enabled, stickers_enabled, mute_duration_seconds, retention_seconds, hash_only) VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT(chat_id) DO UPDATE SET hash_tolerance = excluded.hash_tolerance, search_distance_seconds = excluded.search_distance_seconds, min_votes_count = excluded.min_votes_count, match_threshold = excluded.match_threshold, blockhash_enabled = excluded.blockhash_enabled, siglip2_enabled = excluded.siglip2_enabled, stickers_enabled = excluded.stickers_enabled, mute_duration_seconds = excluded.mute_duration_seconds, retention_seconds = excluded.retention_seconds, hash_only = excluded.hash_only",
+        rusqlite::params![
+            settings.chat_id,
+            settings.hash_tolerance,
+            settings.search_distance_seconds,
+            settings.min_votes_count,
+            settings.match_threshold,
+            settings.blockhash_enabled,
+            settings.siglip2_enabled,
+            settings.stickers_enabled,
+            settings.mute_duration_seconds,
+            settings.retention_seconds,
+            settings.hash_only,
+        ],
+    )
+    .map_err(|e| {
+        tracing::error!("Save chat settings error {e}");
+        e
+    })?;
+    Ok(())
+}
+
+/// Keep hashes of flagged message until voting on alert is finished
+#[allow(clippy::too_many_arguments)]
+pub fn save_alert_hashes(
+    conn: &mut Connection,
+    chat_id: i64,
+    alert_message_id: i64,
+    original_file_id: &str,
+    file_id: &str,
+    message_id: i64,
+    filename: Option<&str>,
+    hashes: &[(&str, &str)],
+    created_at: u64,
+) -> Result<()> {
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare(
+            r"INSERT INTO alert_hashes(chat_id, alert_message_id, original_file_id, file_id, message_id, filename, orientation, base64_hash, created_at) VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )?;
+        for (orientation, hash) in hashes {
+            stmt.execute(rusqlite::params![
+                chat_id,
+                alert_message_id,
+                original_file_id,
+                file_id,
+                message_id,
+                filename,
+                orientation,
+                hash,
+                created_at,
+            ])
+            .map_err(|e| {
+                tracing::error!("Alert hash insert error {e}");
+                e
+            })?;
+        }
+    }
+    tx.commit()
+}
+
+/// Original file id, flagged file id, flagged message id and filename of alert
+pub type AlertFiles = (String, String, Option<i64>, Option<String>);
+
+pub fn find_alert_files(
+    conn: &Connection,
+    chat_id: i64,
+    alert_message_id: i64,
+) -> Result<Option<AlertFiles>> {
+    conn.query_row(
+        "SELECT original_file_id, file_id, message_id, filename FROM alert_hashes WHERE chat_id = ? AND alert_message_id = ? LIMIT 1",
+        rusqlite::params![chat_id, alert_message_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    )
+    .optional()
+}
+
+/// Hashes of flagged file saved for alert, as (orientation, hash)
+pub fn find_alert_hashes(
+    conn: &Connection,
+    chat_id: i64,
+    alert_message_id: i64,
+) -> Result<Vec<(String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT orientation, base64_hash FROM alert_hashes WHERE chat_id = ? AND alert_message_id = ?",
+    )?;
+    let hashes = stmt
+        .query_map(rusqlite::params![chat_id, alert_message_id], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(hashes)
+}
+
+/// Filename of indexed file, it's missing if file was removed by retention
+pub fn find_filename_by_file_id(
+    conn: &Connection,
+    chat_id: i64,
+    file_id: &str,
+) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT filename FROM hashes WHERE chat_id = ? AND file_id = ? LIMIT 1",
+        rusqlite::params![chat_id, file_id],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Turn hashes saved for alert into exclusion of flagged message and original pair
+pub fn exclude_alert_pair(
+    conn: &Connection,
+    chat_id: i64,
+    alert_message_id: i64,
+    created_at: u64,
+) -> Result<usize> {
+    let result = conn
+        .execute(
+            r"INSERT INTO pair_exclusions(chat_id, original_file_id, file_id, orientation, base64_hash, created_at)
+            SELECT chat_id, original_file_id, file_id, orientation, base64_hash, ? FROM alert_hashes WHERE chat_id = ? AND alert_message_id = ?",
+            rusqlite::params![created_at, chat_id, alert_message_id],
+        )
+        .map_err(|e| {
+            tracing::error!("Pair exclusion insert error {e}");
+            e
+        })?;
+    tracing::info!("Pair exclusion hashes saved {}", result);
+    Ok(result)
+}
+
+/// Hashes of files excluded from matching with original, as (file_id, orientation, hash)
+pub fn find_pair_exclusions(
+    conn: &Connection,
+    chat_id: i64,
+    original_file_id: &str,
+) -> Result<Vec<(String, String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT file_id, orientation, base64_hash FROM pair_exclusions WHERE chat_id = ? AND original_file_id = ?",
+    )?;
+
+    let mut rows = stmt.query(rusqlite::params![chat_id, original_file_id])?;
+
+    let mut hashes = Vec::new();
+    while let Some(row) = rows.next()? {
+        hashes.push((row.get(0)?, row.get(1)?, row.get(2)?));
+    }
+
+    Ok(hashes)
+}
+
+/// Silence alerts about original message until given timestamp
+pub fn mute_message_hashes(
+    conn: &Connection,
+    chat_id: i64,
+    message_id: i64,
+    muted_until: i64,
+) -> Result<usize> {
+    let result = conn
+        .execute(
+            r"UPDATE hashes SET muted_until = ? WHERE chat_id = ? AND message_id = ?",
+            rusqlite::params![muted_until, chat_id, message_id],
+        )
+        .map_err(|e| {
+            tracing::error!("Mute hashes error {e}");
+            e
+        })?;
+    tracing::info!("Muted hashes {}", result);
+    Ok(result)
+}
+
+/// Count match against muted message, returns false if message is not muted
+pub fn count_muted_match(
+    conn: &Connection,
+    chat_id: i64,
+    message_id: i64,
+    timestamp: i64,
+) -> Result<bool> {
+    let result = conn.execute(
+        r"UPDATE hashes SET muted_matches_count = muted_matches_count + 1 WHERE chat_id = ? AND message_id = ? AND muted_until > ?",
+        rusqlite::params![chat_id, message_id, timestamp],
+    )?;
+    Ok(result > 0)
+}
+
+pub fn get_index_metadata(conn: &Connection, key: &str) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT value FROM index_metadata WHERE key = ?",
+        rusqlite::params![key],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+pub fn set_index_metadata(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO index_metadata(key, value) VALUES(?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        rusqlite::params![key, value],
+    )?;
+    Ok(())
+}
+
+/// Filenames of chat having any hashes
+pub fn find_indexed_files(conn: &Connection, chat_id: i64) -> Result<HashSet<String>> {
+    let mut stmt = conn.prepare("SELECT DISTINCT filename FROM hashes WHERE chat_id = ?")?;
+    let files = stmt
+        .query_map(rusqlite::params![chat_id], |row| row.get(0))?
+        .collect::<Result<HashSet<String>>>()?;
+    Ok(files)
+}
+
+/// Message ids of chat having any hashes
+pub fn find_indexed_messages(conn: &Connection, chat_id: i64) -> Result<HashSet<i64>> {
+    let mut stmt = conn.prepare("SELECT DISTINCT message_id FROM hashes WHERE chat_id = ?")?;
+    let messages = stmt
+        .query_map(rusqlite::params![chat_id], |row| row.get(0))?
+        .collect::<Result<HashSet<i64>>>()?;
+    Ok(messages)
+}
+
+/// Chats having any indexed files
+pub fn find_indexed_chats(conn: &Connection) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare("SELECT DISTINCT chat_id FROM hashes")?;
+    let chats = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<i64>>>()?;
+    Ok(chats)
+}
+
+/// Indexed images of every chat
+pub fn count_indexed_images(conn: &Connection) -> Result<Vec<(i64, i64)>> {
+    let mut stmt =
+        conn.prepare("SELECT chat_id, COUNT(DISTINCT file_id) FROM hashes GROUP BY chat_id")?;
+    let counts = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(i64, i64)>>>()?;
+    Ok(counts)
+}
+
+/// Stored files of chat indexed before timestamp, with flag if file is still used by other rows
+pub fn find_expired_files(
+    conn: &Connection,
+    chat_id: i64,
+    before_timestamp: u64,
+    limit: usize,
+) -> Result<Vec<(String, bool)>> {
+    let mut stmt = conn.prepare(
+        r"SELECT DISTINCT expired.filename, EXISTS(
+            SELECT 1 FROM hashes used WHERE used.filename = expired.filename AND NOT (used.chat_id = ? AND used.created_at < ?)
+        ) FROM hashes expired WHERE expired.chat_id = ? AND expired.created_at < ? LIMIT ?",
+    )?;
+    let files = stmt
+        .query_map(
+            rusqlite::params![chat_id, before_timestamp, chat_id, before_timestamp, limit],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(files)
+}
+
+/// Delete expired hash rows of file, returns ids of deleted rows
+pub fn delete_expired_file_hashes(
+    conn: &Connection,
+    chat_id: i64,
+    filename: &str,
+    before_timestamp: u64,
+) -> Result<Vec<i32>> {
+    let mut stmt = conn.prepare(
+        r"DELETE FROM hashes WHERE chat_id = ? AND filename = ? AND created_at < ? RETURNING id",
+    )?;
+    let ids = stmt
+        .query_map(
+            rusqlite::params![chat_id, filename, before_timestamp],
+            |row| row.get(0),
+        )?
+        .collect::<Result<Vec<i32>>>()
+        .map_err(|e| {
+            tracing::error!("Delete expired hashes error {e}");
+            e
+        })?;
+    Ok(ids)
+}
+
+/// Delete alert hashes and exclusions which can't be used anymore, returns deleted rows count
+pub fn delete_expired_alerts(
+    conn: &Connection,
+    chat_id: i64,
+    before_timestamp: u64,
+) -> Result<usize> {
+    let alerts = conn.execute(
+        r"DELETE FROM alert_hashes WHERE chat_id = ? AND created_at < ?",
+        rusqlite::params![chat_id, before_timestamp],
+    )?;
+    // Exclusion is useless when original is gone
+    let exclusions = conn.execute(
+        r"DELETE FROM pair_exclusions WHERE chat_id = ? AND original_file_id NOT IN (SELECT file_id FROM hashes WHERE chat_id = ?)",
+        rusqlite::params![chat_id, chat_id],
+    )?;
+    Ok(alerts + exclusions)
+}
+
+/// Return free pages to filesystem, returns count of reclaimed bytes
+pub fn vacuum(conn: &Connection) -> Result<u64> {
+    let database_size = |conn: &Connection| -> Result<u64> {
+        let page_count: u64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+        let page_size: u64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        Ok(page_count * page_size)
+    };
+
+    let size_before = database_size(conn)?;
+    // Db is switched to INCREMENTAL by migration, so only free pages are released
+    conn.execute_batch("PRAGMA incremental_vacuum")?;
+    let size_after = database_size(conn)?;
+
+    Ok(size_before.saturating_sub(size_after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_vote_does_not_finish_small_quorum() {
+        assert!(!is_voting_finished(1, 1, 2));
+        assert!(!is_voting_finished(1, -1, 3));
+        assert!(is_voting_finished(1, 1, 1));
+    }
+
+    #[test]
+    fn voting_finishes_by_quorum_or_majority() {
+        // Tie still finishes once quorum is reached
+        assert!(is_voting_finished(2, 0, 2));
+        assert!(is_voting_finished(2, 2, 3));
+        assert!(is_voting_finished(2, -2, 3));
+        assert!(!is_voting_finished(1, 1, 5));
+        assert!(is_voting_finished(2, 2, 5));
+        assert!(!is_voting_finished(4, 0, 5));
+        assert!(is_voting_finished(5, 1, 5));
+        assert!(!is_voting_finished(3, 3, 10));
+    }
+
+    #[test]
+    fn incremental_vacuum_releases_free_pages() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL; CREATE TABLE blobs (data BLOB)")
+            .unwrap();
+        for _ in 0..100 {
+            conn.execute("INSERT INTO blobs VALUES (zeroblob(4096))", [])
+                .unwrap();
+        }
+
+        assert_eq!(vacuum(&conn).unwrap(), 0);
+        conn.execute("DELETE FROM blobs", []).unwrap();
+        assert!(vacuum(&conn).unwrap() > 0);
+    }
+}
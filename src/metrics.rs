@@ -53,6 +53,32 @@ pub fn mtr_message_hashing_time() -> impl Fn() {
     mtr_exec_time("message_hashing_time")
 }
 
+pub fn mtr_siglip2_hashing_time() -> impl Fn() {
+    mtr_exec_time("siglip2_hashing_time")
+}
+
+pub fn mtr_keyframes_extraction_time() -> impl Fn() {
+    mtr_exec_time("keyframes_extraction_time")
+}
+
+pub fn mtr_video_thumbnail_matches_count(count: u64) {
+    mtr_count("video_thumbnail_matches_count", count);
+}
+
+pub fn mtr_variants_hashing_time() -> impl Fn() {
+    mtr_exec_time("variants_hashing_time")
+}
+
+pub fn mtr_transformed_matches_count(count: u64, transform: &'static str) {
+    let count_metric = meter().u64_counter("transformed_matches_count").build();
+    count_metric.add(count, &[KeyValue::new("transform", transform)]);
+}
+
+pub fn mtr_muted_matches_count(count: u64, chat_id: i64) {
+    let count_metric = meter().u64_counter("muted_matches_count").build();
+    count_metric.add(count, &[KeyValue::new("chat_id", chat_id)]);
+}
+
 pub fn mtr_is_file_processed_info_query_time() -> impl Fn() {
     mtr_exec_time("is_file_processed_info_query_time")
 }
@@ -67,3 +93,21 @@ pub fn mtr_duplicate_count(count: u64, chat_id: i64, user_id: i64) {
         ],
     );
 }
+
+pub fn mtr_retention_time() -> impl Fn() {
+    mtr_exec_time("retention_time")
+}
+
+pub fn mtr_retention_removed_files_count(count: u64) {
+    mtr_count("retention_removed_files_count", count);
+}
+
+pub fn mtr_retention_removed_rows_count(count: u64) {
+    mtr_count("retention_removed_rows_count", count);
+}
+
+/// `source` is `files` for storage or `db` for database
+pub fn mtr_retention_reclaimed_bytes(bytes: u64, source: &'static str) {
+    let count_metric = meter().u64_counter("retention_reclaimed_bytes").build();
+    count_metric.add(bytes, &[KeyValue::new("source", source)]);
+}
@@ -1,5 +1,13 @@
 use rusqlite::types::{FromSql, FromSqlResult, ValueRef};
 
+use crate::{
+    hasher::{
+        HashType, MIN_VOTES_COUNT, MUTE_DURATION_IN_SECONDS, PERCEPTIVE_HASH_TOLERANCE,
+        SEARCH_DISTANCE_IN_SECONDS,
+    },
+    normalize::Crop,
+};
+
 #[derive(Debug)]
 pub struct HashRecord {
     pub id: i32,
@@ -9,6 +17,77 @@ pub struct HashRecord {
     pub chat_id: i64,    // group chat id
     pub message_id: i64, // single message id
     pub media_group_id: Option<String>,
+    // Part of image left after border trimming
+    pub crop: Option<Crop>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatSettings {
+    pub chat_id: i64,
+    pub hash_tolerance: usize,
+    pub search_distance_seconds: u64,
+    pub min_votes_count: i64,
+    // None means global match threshold
+    pub match_threshold: Option<f32>,
+    pub blockhash_enabled: bool,
+    pub siglip2_enabled: bool,
+    pub stickers_enabled: bool,
+    // None means ignored images are muted forever
+    pub mute_duration_seconds: Option<u64>,
+    // None means global retention period
+    pub retention_seconds: Option<u64>,
+    // Images are hashed in memory and never saved to storage
+    pub hash_only: bool,
+}
+
+/// Settings of chats which didn't change them with /settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatDefaults {
+    pub hash_tolerance: usize,
+    pub search_distance_seconds: u64,
+    pub min_votes_count: i64,
+    pub mute_duration_seconds: Option<u64>,
+}
+
+impl Default for ChatDefaults {
+    fn default() -> Self {
+        Self {
+            hash_tolerance: PERCEPTIVE_HASH_TOLERANCE,
+            search_distance_seconds: SEARCH_DISTANCE_IN_SECONDS,
+            min_votes_count: MIN_VOTES_COUNT,
+            mute_duration_seconds: Some(MUTE_DURATION_IN_SECONDS),
+        }
+    }
+}
+
+impl ChatSettings {
+    pub fn new(chat_id: i64, defaults: &ChatDefaults) -> Self {
+        Self {
+            chat_id,
+            hash_tolerance: defaults.hash_tolerance,
+            search_distance_seconds: defaults.search_distance_seconds,
+            min_votes_count: defaults.min_votes_count,
+            match_threshold: None,
+            blockhash_enabled: true,
+            siglip2_enabled: true,
+            stickers_enabled: false,
+            mute_duration_seconds: defaults.mute_duration_seconds,
+            retention_seconds: None,
+            hash_only: false,
+        }
+    }
+
+    pub fn is_detector_enabled(&self, hash_type: HashType) -> bool {
+        match hash_type {
+            HashType::PHashLandscape
+            | HashType::PHashPortrait
+            | HashType::PHashSquare
+            | HashType::VideoThumbnail
+            | HashType::VideoKeyframes
+            | HashType::CenterCrop => self.blockhash_enabled,
+            HashType::Siglip2 => self.siglip2_enabled,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -16,6 +95,7 @@ pub struct VotingRecord {
     pub id: i32,
     pub chat_id: i64,
     pub message_id: i64,
+    pub original_message_id: i64,
     pub voting_type: VotingType,
 }
 
@@ -0,0 +1,294 @@
+use std::{borrow::Cow, fmt::Display, str::FromStr};
+
+use image::{DynamicImage, Rgb, RgbImage};
+
+pub const DEFAULT_BORDER_COLOR_TOLERANCE: u8 = 24;
+pub const DEFAULT_BORDER_MIN_UNIFORM_FRACTION: f32 = 0.8;
+pub const DEFAULT_BORDER_MAX_TRIM_FRACTION: f32 = 0.4;
+/// Images smaller than this after trimming are hashed as is
+const MIN_TRIMMED_SIZE: u32 = 16;
+const MAX_BORDER_LAYERS: usize = 4;
+
+/// Part of image that was kept by normalization
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Crop {
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        img.crop_imm(self.x, self.y, self.width, self.height)
+    }
+
+    /// Central part of image with `fraction` of its width and height
+    pub fn center(img: &DynamicImage, fraction: f32) -> Self {
+        let width = ((img.width() as f32 * fraction) as u32).clamp(1, img.width().max(1));
+        let height = ((img.height() as f32 * fraction) as u32).clamp(1, img.height().max(1));
+        Self {
+            x: (img.width() - width) / 2,
+            y: (img.height() - height) / 2,
+            width,
+            height,
+        }
+    }
+}
+
+/// Stored as `x,y,width,height`
+impl Display for Crop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+impl FromStr for Crop {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| u32::from_str(value.trim()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow::format_err!("Invalid crop {s}: {e}"))?;
+        let [x, y, width, height] = values[..] else {
+            return Err(anyhow::format_err!("Crop should have 4 values"));
+        };
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+/// Image prepared for hashing and the crop applied to it
+pub struct NormalizedImage<'a> {
+    pub image: Cow<'a, DynamicImage>,
+    pub crop: Option<Crop>,
+}
+
+/// Detects uniform borders, letterboxing and caption or watermark strips on image edges.
+///
+/// Line of pixels is part of border if most of its pixels have the color of the outermost
+/// line, so thin text on a strip doesn't stop trimming.
+#[derive(Debug, Clone)]
+pub struct BorderTrimmer {
+    color_tolerance: u8,
+    min_uniform_fraction: f32,
+    max_trim_fraction: f32,
+}
+
+impl Default for BorderTrimmer {
+    fn default() -> Self {
+        Self {
+            color_tolerance: DEFAULT_BORDER_COLOR_TOLERANCE,
+            min_uniform_fraction: DEFAULT_BORDER_MIN_UNIFORM_FRACTION,
+            max_trim_fraction: DEFAULT_BORDER_MAX_TRIM_FRACTION,
+        }
+    }
+}
+
+impl BorderTrimmer {
+    pub fn new(color_tolerance: u8, min_uniform_fraction: f32, max_trim_fraction: f32) -> Self {
+        Self {
+            color_tolerance,
+            min_uniform_fraction: min_uniform_fraction.clamp(0.0, 1.0),
+            max_trim_fraction: max_trim_fraction.clamp(0.0, 0.5),
+        }
+    }
+
+    pub fn normalize<'a>(&self, img: &'a DynamicImage) -> NormalizedImage<'a> {
+        match self.find_crop(img) {
+            Some(crop) => NormalizedImage {
+                image: Cow::Owned(crop.apply(img)),
+                crop: Some(crop),
+            },
+            None => NormalizedImage {
+                image: Cow::Borrowed(img),
+                crop: None,
+            },
+        }
+    }
+
+    /// Crop without borders, None if there is nothing to trim
+    pub fn find_crop(&self, img: &DynamicImage) -> Option<Crop> {
+        let rgb = img.to_rgb8();
+        let (width, height) = rgb.dimensions();
+        if width < MIN_TRIMMED_SIZE || height < MIN_TRIMMED_SIZE {
+            return None;
+        }
+
+        let max_rows = (height as f32 * self.max_trim_fraction) as u32;
+        let max_columns = (width as f32 * self.max_trim_fraction) as u32;
+        let (mut top, mut bottom, mut left, mut right) = (0, 0, 0, 0);
+
+        // Borders may be stacked, e.g. caption strip below letterbox, so trim layer by layer
+        for _ in 0..MAX_BORDER_LAYERS {
+            let row = |y: u32| (left..width - right).map(move |x| (x, y));
+            let new_top = self.border_size(&rgb, max_rows - top, |i| row(top + i));
+            let new_bottom =
+                self.border_size(&rgb, max_rows - bottom, |i| row(height - 1 - bottom - i));
+            top += new_top;
+            bottom += new_bottom;
+
+            // Side borders are checked only between top and bottom ones
+            let column = |x: u32| (top..height - bottom).map(move |y| (x, y));
+            let new_left = self.border_size(&rgb, max_columns - left, |i| column(left + i));
+            let new_right =
+                self.border_size(&rgb, max_columns - right, |i| column(width - 1 - right - i));
+            left += new_left;
+            right += new_right;
+
+            if new_top + new_bottom + new_left + new_right == 0 {
+                break;
+            }
+        }
+
+        if top + bottom + left + right == 0 {
+            return None;
+        }
+
+        let crop = Crop {
+            x: left,
+            y: top,
+            width: width - left - right,
+            height: height - top - bottom,
+        };
+        if crop.width < MIN_TRIMMED_SIZE || crop.height < MIN_TRIMMED_SIZE {
+            return None;
+        }
+        Some(crop)
+    }
+
+    /// Count of uniform lines from the edge, `line(i)` gives pixels of i-th line from the edge
+    fn border_size<I: Iterator<Item = (u32, u32)>>(
+        &self,
+        img: &RgbImage,
+        max_lines: u32,
+        line: impl Fn(u32) -> I,
+    ) -> u32 {
+        if max_lines == 0 {
+            return 0;
+        }
+        let edge = line(0)
+            .map(|(x, y)| *img.get_pixel(x, y))
+            .collect::<Vec<_>>();
+        let border_color = median_color(&edge);
+
+        (0..max_lines)
+            .take_while(|i| {
+                let mut total = 0;
+                let mut uniform = 0;
+                for (x, y) in line(*i) {
+                    total += 1;
+                    if self.is_same_color(img.get_pixel(x, y), &border_color) {
+                        uniform += 1;
+                    }
+                }
+                total > 0 && uniform as f32 / total as f32 >= self.min_uniform_fraction
+            })
+            .count() as u32
+    }
+
+    fn is_same_color(&self, a: &Rgb<u8>, b: &Rgb<u8>) -> bool {
+        a.0.iter()
+            .zip(b.0.iter())
+            .all(|(a, b)| a.abs_diff(*b) <= self.color_tolerance)
+    }
+}
+
+/// Per channel median, text on strip doesn't shift it like mean does
+fn median_color(pixels: &[Rgb<u8>]) -> Rgb<u8> {
+    let mut color = [0; 3];
+    for (channel, value) in color.iter_mut().enumerate() {
+        let mut values = pixels.iter().map(|p| p.0[channel]).collect::<Vec<_>>();
+        values.sort_unstable();
+        *value = values.get(values.len() / 2).copied().unwrap_or_default();
+    }
+    Rgb(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Busy picture without uniform lines, so nothing of it is trimmed
+    fn content(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            Rgb([
+                (x * 7 + y * 3) as u8,
+                ((x * 5) ^ (y * 11)) as u8,
+                (x * y) as u8,
+            ])
+        })
+    }
+
+    /// Content placed at `x,y` of black canvas
+    fn framed(content: &RgbImage, width: u32, height: u32, x: u32, y: u32) -> RgbImage {
+        let mut canvas = RgbImage::from_pixel(width, height, Rgb([0, 0, 0]));
+        image::imageops::replace(&mut canvas, content, x as i64, y as i64);
+        canvas
+    }
+
+    #[test]
+    fn letterbox_is_trimmed() {
+        let img = DynamicImage::ImageRgb8(framed(&content(160, 90), 160, 130, 0, 20));
+
+        let crop = BorderTrimmer::default().find_crop(&img);
+
+        assert_eq!(
+            crop,
+            Some(Crop {
+                x: 0,
+                y: 20,
+                width: 160,
+                height: 90,
+            })
+        );
+    }
+
+    #[test]
+    fn caption_strip_with_text_is_trimmed() {
+        let mut img = RgbImage::from_pixel(120, 100, Rgb([255, 255, 255]));
+        image::imageops::replace(&mut img, &content(120, 80), 0, 0);
+        // Few dark letter pixels on caption lines
+        for y in 85..95 {
+            for x in (10..110).step_by(12) {
+                img.put_pixel(x, y, Rgb([0, 0, 0]));
+            }
+        }
+
+        let crop = BorderTrimmer::default().find_crop(&DynamicImage::ImageRgb8(img));
+
+        assert_eq!(
+            crop,
+            Some(Crop {
+                x: 0,
+                y: 0,
+                width: 120,
+                height: 80,
+            })
+        );
+    }
+
+    #[test]
+    fn image_without_border_is_kept() {
+        let img = DynamicImage::ImageRgb8(content(100, 80));
+
+        assert_eq!(BorderTrimmer::default().find_crop(&img), None);
+    }
+
+    #[test]
+    fn tiny_image_is_kept() {
+        let trimmer = BorderTrimmer::default();
+        let tiny = DynamicImage::ImageRgb8(framed(&content(8, 4), 12, 12, 2, 4));
+        // Content left after trimming is smaller than hash input
+        let thin = DynamicImage::ImageRgb8(framed(&content(100, 8), 100, 40, 0, 16));
+
+        assert_eq!(trimmer.find_crop(&tiny), None);
+        assert_eq!(trimmer.find_crop(&thin), None);
+    }
+}
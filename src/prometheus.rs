@@ -0,0 +1,223 @@
+//! Prometheus pull endpoint for metrics from `metrics` module.
+//!
+//! Metrics are collected from meter provider on every scrape and rendered in text exposition format.
+
+use std::{
+    fmt::{Display, Write},
+    net::SocketAddr,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::{
+    error::OTelSdkResult,
+    metrics::{
+        data::{AggregatedMetrics, Metric, MetricData, ResourceMetrics},
+        reader::MetricReader,
+        InstrumentKind, ManualReader, Pipeline, Temporality,
+    },
+};
+use tokio::net::TcpListener;
+
+pub const DEFAULT_PROMETHEUS_PATH: &str = "/metrics";
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Reader registered in meter provider, clones share collected state with scrape handler
+#[derive(Debug, Clone, Default)]
+pub struct PrometheusReader {
+    reader: Arc<ManualReader>,
+}
+
+impl MetricReader for PrometheusReader {
+    fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+        self.reader.register_pipeline(pipeline);
+    }
+
+    fn collect(&self, rm: &mut ResourceMetrics) -> OTelSdkResult {
+        self.reader.collect(rm)
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.reader.force_flush()
+    }
+
+    fn shutdown_with_timeout(&self, timeout: Duration) -> OTelSdkResult {
+        self.reader.shutdown_with_timeout(timeout)
+    }
+
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.reader.temporality(kind)
+    }
+}
+
+impl PrometheusReader {
+    /// All metrics in text exposition format
+    pub fn render(&self) -> Result<String, anyhow::Error> {
+        let mut metrics = ResourceMetrics::default();
+        self.collect(&mut metrics)
+            .map_err(|e| anyhow::format_err!("Failed to collect metrics: {e}"))?;
+
+        let mut output = String::new();
+        for scope in metrics.scope_metrics() {
+            for metric in scope.metrics() {
+                match metric.data() {
+                    AggregatedMetrics::U64(data) => write_metric(&mut output, metric, data),
+                    AggregatedMetrics::I64(data) => write_metric(&mut output, metric, data),
+                    AggregatedMetrics::F64(data) => write_metric(&mut output, metric, data),
+                }
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// Serve scrape endpoint in background task
+pub async fn serve(
+    reader: PrometheusReader,
+    listen_addr: SocketAddr,
+    path: &str,
+) -> Result<(), anyhow::Error> {
+    let listener = TcpListener::bind(listen_addr)
+        .await
+        .map_err(|e| anyhow::format_err!("Failed to listen on {listen_addr}: {e}"))?;
+    let app = Router::new().route(path, get(scrape)).with_state(reader);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("Prometheus endpoint failed: {e}");
+        }
+    });
+    Ok(())
+}
+
+async fn scrape(State(reader): State<PrometheusReader>) -> Response {
+    match reader.render() {
+        Ok(output) => ([(header::CONTENT_TYPE, CONTENT_TYPE)], output).into_response(),
+        Err(e) => {
+            tracing::error!("{e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+fn write_metric<T: Display + Copy>(output: &mut String, metric: &Metric, data: &MetricData<T>) {
+    let mut name = sanitize_name(metric.name());
+    let kind = match data {
+        MetricData::Gauge(_) => "gauge",
+        MetricData::Sum(sum) if sum.is_monotonic() => {
+            name.push_str("_total");
+            "counter"
+        }
+        MetricData::Sum(_) => "gauge",
+        MetricData::Histogram(_) => "histogram",
+        // Not used by bot metrics
+        MetricData::ExponentialHistogram(_) => return,
+    };
+
+    if !metric.description().is_empty() {
+        let _ = writeln!(
+            output,
+            "# HELP {name} {}",
+            escape_help(metric.description())
+        );
+    }
+    let _ = writeln!(output, "# TYPE {name} {kind}");
+    match data {
+        MetricData::Gauge(gauge) => {
+            for point in gauge.data_points() {
+                write_sample(output, &name, point.attributes(), None, point.value());
+            }
+        }
+        MetricData::Sum(sum) => {
+            for point in sum.data_points() {
+                write_sample(output, &name, point.attributes(), None, point.value());
+            }
+        }
+        MetricData::Histogram(histogram) => {
+            for point in histogram.data_points() {
+                let bucket_name = format!("{name}_bucket");
+                let mut cumulative = 0;
+                let bounds = point.bounds().map(|bound| bound.to_string());
+                let bounds = bounds.chain(std::iter::once("+Inf".to_owned()));
+                for (bound, count) in bounds.zip(point.bucket_counts()) {
+                    cumulative += count;
+                    let le = ("le", bound.as_str());
+                    write_sample(
+                        output,
+                        &bucket_name,
+                        point.attributes(),
+                        Some(le),
+                        cumulative,
+                    );
+                }
+                let sum_name = format!("{name}_sum");
+                write_sample(output, &sum_name, point.attributes(), None, point.sum());
+                let count_name = format!("{name}_count");
+                write_sample(output, &count_name, point.attributes(), None, point.count());
+            }
+        }
+        MetricData::ExponentialHistogram(_) => {}
+    }
+}
+
+fn write_sample<'a>(
+    output: &mut String,
+    name: &str,
+    attributes: impl Iterator<Item = &'a KeyValue>,
+    extra_label: Option<(&str, &str)>,
+    value: impl Display,
+) {
+    let mut labels: Vec<String> = attributes
+        .map(|attribute| {
+            format!(
+                "{}=\"{}\"",
+                sanitize_name(attribute.key.as_str()),
+                escape_label(&attribute.value.to_string())
+            )
+        })
+        .collect();
+    if let Some((key, value)) = extra_label {
+        labels.push(format!("{key}=\"{value}\""));
+    }
+    if labels.is_empty() {
+        let _ = writeln!(output, "{name} {value}");
+    } else {
+        let _ = writeln!(output, "{name}{{{}}} {value}", labels.join(","));
+    }
+}
+
+/// Prometheus names allow only `[a-zA-Z_:][a-zA-Z0-9_:]*`
+fn sanitize_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == ':' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn escape_help(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
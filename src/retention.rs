@@ -0,0 +1,156 @@
+//! Background garbage collection of stored files and hash rows older than retention period.
+//!
+//! Every chat may have own retention period in settings, it's independent from search window.
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    hasher::PHashIndexer,
+    metrics,
+    storage::{is_stored_file, FileStorage},
+    vacuum,
+};
+
+pub const DEFAULT_RETENTION_INTERVAL_SECONDS: u64 = 6 * 60 * 60;
+pub const DEFAULT_RETENTION_BATCH_SIZE: usize = 500;
+
+#[derive(Debug, Clone)]
+pub struct RetentionConfig {
+    /// Used for chats without own retention setting
+    pub retention_seconds: u64,
+    pub interval: Duration,
+    /// Files removed between db lock releases
+    pub batch_size: usize,
+}
+
+impl RetentionConfig {
+    pub fn new(retention_seconds: u64) -> Self {
+        Self {
+            retention_seconds,
+            interval: Duration::from_secs(DEFAULT_RETENTION_INTERVAL_SECONDS),
+            batch_size: DEFAULT_RETENTION_BATCH_SIZE,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+}
+
+/// Stored file with hash rows of single chat indexed before `before_timestamp`
+#[derive(Debug, Clone)]
+pub struct ExpiredFile {
+    pub chat_id: i64,
+    pub filename: String,
+    pub before_timestamp: u64,
+    /// Same file is used by other chat or newer rows, only rows are deleted
+    pub shared: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct RetentionReport {
+    pub removed_files: u64,
+    pub removed_rows: u64,
+    pub file_bytes: u64,
+    pub db_bytes: u64,
+}
+
+/// Run garbage collection every `config.interval` until cancelled
+pub async fn run_retention<T: FileStorage>(
+    indexer: Arc<Mutex<PHashIndexer>>,
+    storage: Arc<Mutex<T>>,
+    config: RetentionConfig,
+    shutdown: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(config.interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => break,
+        }
+
+        match collect_garbage(&indexer, &storage, &config).await {
+            Ok(report) => tracing::info!("Retention finished {report:?}"),
+            Err(e) => tracing::error!("Retention failed: {e}"),
+        }
+    }
+}
+
+#[tracing::instrument(name = "Collect garbage", skip(indexer, storage))]
+pub async fn collect_garbage<T: FileStorage>(
+    indexer: &Arc<Mutex<PHashIndexer>>,
+    storage: &Arc<Mutex<T>>,
+    config: &RetentionConfig,
+) -> Result<RetentionReport, anyhow::Error> {
+    let send_metric = metrics::mtr_retention_time();
+    let mut report = RetentionReport::default();
+
+    loop {
+        let expired_files = indexer
+            .lock()
+            .await
+            .find_expired_files(config.retention_seconds, config.batch_size)
+            .await?;
+        if expired_files.is_empty() {
+            break;
+        }
+
+        let mut progress = false;
+        for file in &expired_files {
+            // File is removed before rows, so failed removal is retried on next run
+            if !file.shared && is_stored_file(&file.filename) {
+                let storage = storage.lock().await;
+                let size = storage.file_size(&file.filename).await.unwrap_or_else(|e| {
+                    tracing::warn!("Failed to get size of {}: {e}", file.filename);
+                    0
+                });
+                if let Err(e) = storage.remove_file(&file.filename).await {
+                    tracing::error!("Failed to remove {}: {e}", file.filename);
+                    continue;
+                }
+                report.removed_files += 1;
+                report.file_bytes += size;
+            }
+
+            let removed_rows = indexer.lock().await.delete_expired_file(file).await?;
+            report.removed_rows += removed_rows as u64;
+            progress |= removed_rows > 0;
+        }
+
+        // Every file of batch failed, don't spin on them until next run
+        if !progress || expired_files.len() < config.batch_size {
+            break;
+        }
+    }
+
+    let db = {
+        let indexer = indexer.lock().await;
+        report.removed_rows += indexer
+            .delete_expired_alerts(config.retention_seconds)
+            .await? as u64;
+        indexer.db()
+    };
+    // Nothing deleted means no free pages, and images are indexed meanwhile without waiting
+    if report.removed_rows > 0 {
+        let db = db.lock().await;
+        report.db_bytes =
+            vacuum(&db).map_err(|e| anyhow::format_err!("Failed to vacuum db: {e}"))?;
+    }
+
+    metrics::mtr_retention_removed_files_count(report.removed_files);
+    metrics::mtr_retention_removed_rows_count(report.removed_rows);
+    metrics::mtr_retention_reclaimed_bytes(report.file_bytes, "files");
+    metrics::mtr_retention_reclaimed_bytes(report.db_bytes, "db");
+    send_metric();
+
+    Ok(report)
+}
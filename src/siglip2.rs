@@ -1,3 +1,4 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
 use image::DynamicImage;
 use ndarray::Array;
 use ort::{
@@ -24,7 +25,7 @@ impl Siglip2Hasher {
         let session = SessionBuilder::new()?
             .with_optimization_level(GraphOptimizationLevel::Level3).map_err(|_|anyhow::anyhow!("Failed to set GraphOptimizationLevel"))?
             .with_intra_threads(4).map_err(|_|anyhow::anyhow!("Failed to set intra with_intra_threads"))?
-            .commit_from_file(model_path /*"./16vision_model_q4.onnx"*/).map_err(|_|anyhow::anyhow!("Failed to set intra with_intra_threads"))?;
+            .commit_from_file(model_path /*"./16vision_model_q4.onnx"*/).map_err(|e|anyhow::anyhow!("Failed to load model {}: {e}", model_path.display()))?;
         Ok(Self { session })
     }
 
@@ -32,18 +33,15 @@ impl Siglip2Hasher {
         let height: u32 = 224;
         let width: u32 = 224;
         let resized =
-            image::imageops::resize(image, height, width, image::imageops::FilterType::Nearest);
-        let input_tensor: Vec<f32> = resized
-            .pixels()
-            .flat_map(|p| {
-                let [r, g, b, _] = p.0;
-                // нормализация в float 0.0–1.0
-                vec![r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0]
-            })
-            .collect();
-
-        let input_array =
-            Array::from_shape_vec((1, 3, height as usize, width as usize), input_tensor)?;
+            image::imageops::resize(image, width, height, image::imageops::FilterType::Nearest);
+        // Model expects NCHW layout normalized with mean 0.5 and std 0.5
+        let input_array = Array::from_shape_fn(
+            (1, 3, height as usize, width as usize),
+            |(_, channel, y, x)| {
+                let pixel = resized.get_pixel(x as u32, y as u32);
+                (pixel.0[channel] as f32 / 255.0 - 0.5) / 0.5
+            },
+        );
         let input_value = Value::from_array(input_array)?;
         let outputs = self.session.run(vec![("pixel_values", &input_value)])?;
         let mut embedding: Vec<f32> = outputs[0]
@@ -137,4 +135,20 @@ pub fn l2_normalize(v: &mut [f32]) {
     for x in v {
         *x /= norm;
     }
-}
\ No newline at end of file
+}
+
+pub fn embedding_to_base64(embedding: &[f32]) -> String {
+    STANDARD.encode(bytemuck::cast_slice::<f32, u8>(embedding))
+}
+
+pub fn embedding_from_base64(encoded: &str) -> Result<Vec<f32>, anyhow::Error> {
+    let bytes = STANDARD.decode(encoded)?;
+    if bytes.len() % std::mem::size_of::<f32>() != 0 {
+        return Err(anyhow::format_err!(
+            "Embedding length {} is not a multiple of f32 size",
+            bytes.len()
+        ));
+    }
+    // Decoded buffer is not guaranteed to be f32 aligned, so copy instead of casting
+    Ok(bytemuck::pod_collect_to_vec(&bytes))
+}
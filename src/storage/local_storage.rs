@@ -1,30 +1,185 @@
-use std::{ffi::OsStr, io::Cursor, path::PathBuf};
-
-use frankenstein::{response::MethodResponse, types::File};
-use reqwest::Response;
-
-#[tracing::instrument(name = "Save file", skip(file_response))]
-async fn save_file(
-    file_path: &str,
-    response: &MethodResponse<File>,
-    file_response: Response,
-) -> Result<PathBuf, ()> {
-    let original_path = std::path::Path::new(file_path);
-    let extension = original_path
-        .extension()
-        .and_then(OsStr::to_str)
-        .unwrap_or("");
-
-    let destination_path_str = format!(
-        "./files/{path}.{extension}",
-        path = response.result.file_unique_id,
-        extension = extension
-    );
-    let destination_path = std::path::Path::new(&destination_path_str);
-    let prefix = destination_path.parent().unwrap();
-    std::fs::create_dir_all(prefix).unwrap();
-    let mut file = std::fs::File::create(destination_path).map_err(|_| ())?;
-    let mut content = Cursor::new(file_response.bytes().await.map_err(|_| ())?);
-    std::io::copy(&mut content, &mut file).map_err(|_| ())?;
-    Ok(destination_path.to_path_buf())
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use image::DynamicImage;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use super::{local_source_path, FileStorage};
+
+pub const DEFAULT_LOCAL_STORAGE_PATH: &str = "./files";
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Stores files in local directory, files are addressed by `file://` URIs.
+///
+/// Files are spread over two levels of subdirectories by hash of filename,
+/// e.g. `files/3f/a2/AQADx.jpg`, so directories stay small.
+pub struct LocalFileStorage {
+    root: PathBuf,
+}
+
+impl LocalFileStorage {
+    /// Create root directory if missing, root is made absolute for `file://` URIs
+    pub fn new(root: &Path) -> Result<Self, anyhow::Error> {
+        std::fs::create_dir_all(root)
+            .map_err(|e| anyhow::format_err!("Failed to create storage directory: {e}"))?;
+        let root = root
+            .canonicalize()
+            .map_err(|e| anyhow::format_err!("Failed to resolve storage directory: {e}"))?;
+        Ok(Self { root })
+    }
+
+    fn file_path(&self, filename: &str) -> PathBuf {
+        let hash = fnv1a(filename.as_bytes());
+        self.root
+            .join(format!("{:02x}", hash & 0xff))
+            .join(format!("{:02x}", (hash >> 8) & 0xff))
+            .join(filename)
+    }
+
+    /// Path of file from URI, only files inside storage root are accepted
+    fn path_from_url(&self, url: &str) -> Result<PathBuf, anyhow::Error> {
+        let file_url = Url::parse(url)?;
+
+        if file_url.scheme() != "file" {
+            return Err(anyhow::format_err!("This is not file url"));
+        }
+
+        let path = file_url
+            .to_file_path()
+            .map_err(|_| anyhow::format_err!("Failed to parse file path"))?;
+        if !path.starts_with(&self.root) || path.components().any(|c| c.as_os_str() == "..") {
+            return Err(anyhow::format_err!("File is outside of storage"));
+        }
+        Ok(path)
+    }
+}
+
+impl FileStorage for LocalFileStorage {
+    #[tracing::instrument("Save file to local storage", skip(self))]
+    async fn save_file(&self, url: &str, filename: &str) -> Result<String, anyhow::Error> {
+        if filename.contains(['/', '\\']) || filename.starts_with('.') {
+            return Err(anyhow::format_err!("Invalid filename `{filename}`"));
+        }
+
+        let destination_path = self.file_path(filename);
+        let directory = destination_path
+            .parent()
+            .ok_or(anyhow::format_err!("Failed to get file directory"))?;
+        tokio::fs::create_dir_all(directory)
+            .await
+            .map_err(|e| anyhow::format_err!("Failed to create directory: {e}"))?;
+
+        // Written to temp file first and renamed, so readers never see partial file
+        let temp_path = directory.join(format!(
+            ".{filename}.{}.{}.tmp",
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = download_to_file(url, &temp_path).await;
+        let result = match result {
+            Ok(()) => tokio::fs::rename(&temp_path, &destination_path)
+                .await
+                .map_err(|e| anyhow::format_err!("Failed to move file to storage: {e}")),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            if let Err(e) = tokio::fs::remove_file(&temp_path).await {
+                tracing::warn!("Failed to remove temp file: {e}");
+            }
+            return Err(e);
+        }
+
+        let file_url = Url::from_file_path(&destination_path)
+            .map_err(|_| anyhow::format_err!("Failed to build file url"))?;
+        Ok(file_url.to_string())
+    }
+
+    #[tracing::instrument("Load file from local storage", skip(self))]
+    async fn load_file(&self, url: &str) -> Result<DynamicImage, anyhow::Error> {
+        let bytes = self.load_raw_file(url).await?;
+
+        let image_result = image::load_from_memory(&bytes)
+            .map_err(|e| anyhow::format_err!("Failed to load image: {}", e))?;
+        Ok(image_result)
+    }
+
+    #[tracing::instrument("Load raw file from local storage", skip(self))]
+    async fn load_raw_file(&self, url: &str) -> Result<Vec<u8>, anyhow::Error> {
+        let path = self.path_from_url(url)?;
+        tokio::fs::read(&path)
+            .await
+            .map_err(|e| anyhow::format_err!("Failed to read file: {e}"))
+    }
+
+    #[tracing::instrument("Remove file from local storage", skip(self))]
+    async fn remove_file(&self, url: &str) -> Result<(), anyhow::Error> {
+        let path = self.path_from_url(url)?;
+        match tokio::fs::remove_file(&path).await {
+            // Already removed file is fine, same as S3 delete
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.map_err(|e| anyhow::format_err!("Failed to remove file: {e}")),
+        }
+    }
+
+    #[tracing::instrument("Get file size in local storage", skip(self))]
+    async fn file_size(&self, url: &str) -> Result<u64, anyhow::Error> {
+        let path = self.path_from_url(url)?;
+        let metadata = tokio::fs::metadata(&path)
+            .await
+            .map_err(|e| anyhow::format_err!("Failed to read file metadata: {e}"))?;
+        Ok(metadata.len())
+    }
+
+    async fn check(&self) -> Result<(), anyhow::Error> {
+        let metadata = tokio::fs::metadata(&self.root)
+            .await
+            .map_err(|e| anyhow::format_err!("Storage directory is unavailable: {e}"))?;
+        if !metadata.is_dir() || metadata.permissions().readonly() {
+            return Err(anyhow::format_err!("Storage directory is not writable"));
+        }
+        Ok(())
+    }
+}
+
+async fn download_to_file(url: &str, path: &Path) -> Result<(), anyhow::Error> {
+    if let Some(source_path) = local_source_path(url) {
+        tokio::fs::copy(&source_path, path)
+            .await
+            .map_err(|e| anyhow::format_err!("Failed to copy file: {e}"))?;
+        return Ok(());
+    }
+
+    let mut response = reqwest::get(url)
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| anyhow::format_err!("Failed to download url: {}", e))?;
+
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .map_err(|e| anyhow::format_err!("Failed to create file: {e}"))?;
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| anyhow::format_err!("Failed to download url: {}", e))?
+    {
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| anyhow::format_err!("Failed to write file: {e}"))?;
+    }
+    file.sync_all()
+        .await
+        .map_err(|e| anyhow::format_err!("Failed to flush file: {e}"))?;
+    Ok(())
+}
+
+/// FNV-1a, stable across runs and Rust versions unlike std hasher, so files are found
+/// after restart and generated ids don't change
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
 }
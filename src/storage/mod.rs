@@ -1,8 +1,19 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
 use image::DynamicImage;
+use local_storage::LocalFileStorage;
+use s3_storage::S3FileStorage;
+use url::Url;
 
 pub mod local_storage;
 pub mod s3_storage;
 
+/// Bot API doesn't allow to download bigger files anyway
+pub const DEFAULT_MAX_MEMORY_FILE_SIZE: u64 = 20 * 1024 * 1024;
+
 pub trait FileStorage {
     fn save_file(
         &self,
@@ -13,8 +24,156 @@ pub trait FileStorage {
         &self,
         url: &str,
     ) -> impl std::future::Future<Output = Result<DynamicImage, anyhow::Error>>;
+    fn load_raw_file(
+        &self,
+        url: &str,
+    ) -> impl std::future::Future<Output = Result<Vec<u8>, anyhow::Error>>;
     fn remove_file(
         &self,
         url: &str,
     ) -> impl std::future::Future<Output = Result<(), anyhow::Error>>;
+    /// Size of stored file in bytes
+    fn file_size(&self, url: &str)
+        -> impl std::future::Future<Output = Result<u64, anyhow::Error>>;
+    /// Storage is reachable, used by readiness probe
+    fn check(&self) -> impl std::future::Future<Output = Result<(), anyhow::Error>>;
+}
+
+/// Where downloaded files are kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageBackend {
+    Local,
+    S3,
+}
+
+impl FromStr for StorageBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(StorageBackend::Local),
+            "s3" => Ok(StorageBackend::S3),
+            _ => Err(anyhow::format_err!("Unknown storage backend {s}")),
+        }
+    }
+}
+
+/// Storage chosen at startup, `FileStorage` can't be used as trait object
+pub enum AnyFileStorage {
+    Local(LocalFileStorage),
+    S3(S3FileStorage),
+}
+
+impl FileStorage for AnyFileStorage {
+    async fn save_file(&self, url: &str, filename: &str) -> Result<String, anyhow::Error> {
+        match self {
+            AnyFileStorage::Local(storage) => storage.save_file(url, filename).await,
+            AnyFileStorage::S3(storage) => storage.save_file(url, filename).await,
+        }
+    }
+
+    async fn load_file(&self, url: &str) -> Result<DynamicImage, anyhow::Error> {
+        match self {
+            AnyFileStorage::Local(storage) => storage.load_file(url).await,
+            AnyFileStorage::S3(storage) => storage.load_file(url).await,
+        }
+    }
+
+    async fn load_raw_file(&self, url: &str) -> Result<Vec<u8>, anyhow::Error> {
+        match self {
+            AnyFileStorage::Local(storage) => storage.load_raw_file(url).await,
+            AnyFileStorage::S3(storage) => storage.load_raw_file(url).await,
+        }
+    }
+
+    async fn remove_file(&self, url: &str) -> Result<(), anyhow::Error> {
+        match self {
+            AnyFileStorage::Local(storage) => storage.remove_file(url).await,
+            AnyFileStorage::S3(storage) => storage.remove_file(url).await,
+        }
+    }
+
+    async fn file_size(&self, url: &str) -> Result<u64, anyhow::Error> {
+        match self {
+            AnyFileStorage::Local(storage) => storage.file_size(url).await,
+            AnyFileStorage::S3(storage) => storage.file_size(url).await,
+        }
+    }
+
+    async fn check(&self) -> Result<(), anyhow::Error> {
+        match self {
+            AnyFileStorage::Local(storage) => storage.check().await,
+            AnyFileStorage::S3(storage) => storage.check().await,
+        }
+    }
+}
+
+/// Hash-only and imported records keep Telegram file_id or export path instead of storage URL
+pub fn is_stored_file(filename: &str) -> bool {
+    Url::parse(filename).is_ok()
+}
+
+/// URL of file returned by `getFile`.
+///
+/// Local Bot API server returns absolute path on its filesystem, such files are read directly.
+pub fn telegram_file_url(files_endpoint: &str, file_path: &str) -> Result<String, anyhow::Error> {
+    if Path::new(file_path).is_absolute() {
+        let file_url = Url::from_file_path(file_path)
+            .map_err(|_| anyhow::format_err!("Failed to build file url for `{file_path}`"))?;
+        return Ok(file_url.to_string());
+    }
+    Ok(format!("{files_endpoint}/{file_path}"))
+}
+
+/// Path of `file://` URL, other URLs are downloaded over HTTP
+pub(crate) fn local_source_path(url: &str) -> Option<PathBuf> {
+    let url = Url::parse(url).ok()?;
+    if url.scheme() != "file" {
+        return None;
+    }
+    url.to_file_path().ok()
+}
+
+/// Download file without saving it anywhere, files over `max_size` bytes are rejected
+#[tracing::instrument("Download file to memory")]
+pub async fn download_to_memory(url: &str, max_size: u64) -> Result<Vec<u8>, anyhow::Error> {
+    if let Some(path) = local_source_path(url) {
+        let metadata = tokio::fs::metadata(&path)
+            .await
+            .map_err(|e| anyhow::format_err!("Failed to read file metadata: {e}"))?;
+        if metadata.len() > max_size {
+            return Err(anyhow::format_err!(
+                "File is too big: {} bytes",
+                metadata.len()
+            ));
+        }
+        return tokio::fs::read(&path)
+            .await
+            .map_err(|e| anyhow::format_err!("Failed to read file: {e}"));
+    }
+
+    let mut response = reqwest::get(url)
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| anyhow::format_err!("Failed to download url: {}", e))?;
+
+    if let Some(size) = response.content_length() {
+        if size > max_size {
+            return Err(anyhow::format_err!("File is too big: {size} bytes"));
+        }
+    }
+
+    // Content length may be missing, so limit is checked while downloading too
+    let mut data = Vec::with_capacity(response.content_length().unwrap_or(0) as usize);
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| anyhow::format_err!("Failed to download url: {}", e))?
+    {
+        if (data.len() + chunk.len()) as u64 > max_size {
+            return Err(anyhow::format_err!("File is bigger than {max_size} bytes"));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
 }
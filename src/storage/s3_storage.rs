@@ -3,7 +3,7 @@ use image::DynamicImage;
 use s3::{creds::Credentials, error::S3Error, Bucket, Region};
 use url::Url;
 
-use super::FileStorage;
+use super::{local_source_path, FileStorage};
 
 pub struct S3FileStorage {
     endpoint: String,
@@ -64,6 +64,18 @@ impl FileStorage for S3FileStorage {
         skip(self)
     )]
     async fn load_file(&self, url: &str) -> Result<DynamicImage, anyhow::Error> {
+        let bytes = self.load_raw_file(url).await?;
+
+        let image_result = image::load_from_memory(&bytes)
+            .map_err(|e| anyhow::format_err!("Failed to load image: {}", e))?;
+        Ok(image_result)
+    }
+
+    #[tracing::instrument(
+        "Load raw file from S3 storage"
+        skip(self)
+    )]
+    async fn load_raw_file(&self, url: &str) -> Result<Vec<u8>, anyhow::Error> {
         let s3_url = Url::parse(url)?;
 
         if s3_url.scheme() != "s3" {
@@ -88,9 +100,7 @@ impl FileStorage for S3FileStorage {
             .await
             .map_err(|e| anyhow::format_err!("Failed to read file from bucket: {}", e))?;
 
-        let image_result = image::load_from_memory(bucket_result.bytes())
-            .map_err(|e| anyhow::format_err!("Failed to load image: {}", e))?;
-        Ok(image_result)
+        Ok(bucket_result.to_vec())
     }
 
     #[tracing::instrument(
@@ -124,6 +134,60 @@ impl FileStorage for S3FileStorage {
 
         Ok(())
     }
+
+    #[tracing::instrument(
+        "Get file size in S3 storage"
+        skip(self)
+    )]
+    async fn file_size(&self, url: &str) -> Result<u64, anyhow::Error> {
+        let s3_url = Url::parse(url)?;
+
+        if s3_url.scheme() != "s3" {
+            return Err(anyhow::format_err!("This is not s3 url"));
+        }
+
+        let bucket_name = s3_url
+            .host_str()
+            .ok_or(anyhow::format_err!("Failed to parse bucket"))?;
+        let filename = s3_url.path();
+
+        let bucket = get_bucket(
+            &self.endpoint,
+            bucket_name,
+            &self.access_key,
+            &self.secret_key,
+        )
+        .map_err(|e| anyhow::format_err!("Failed to open bucket: {}", e))?;
+
+        let (head, _) = bucket
+            .head_object(filename)
+            .await
+            .map_err(|e| anyhow::format_err!("Failed to get file info: {}", e))?;
+
+        head.content_length
+            .and_then(|size| u64::try_from(size).ok())
+            .ok_or(anyhow::format_err!("File size is unknown"))
+    }
+
+    #[tracing::instrument(
+        "Check S3 storage"
+        skip(self)
+    )]
+    async fn check(&self) -> Result<(), anyhow::Error> {
+        let bucket = get_bucket(
+            &self.endpoint,
+            &self.bucket_name,
+            &self.access_key,
+            &self.secret_key,
+        )
+        .map_err(|e| anyhow::format_err!("Failed to open bucket: {}", e))?;
+
+        bucket
+            .list_page(String::new(), None, None, None, Some(1))
+            .await
+            .map_err(|e| anyhow::format_err!("Failed to list bucket: {}", e))?;
+        Ok(())
+    }
 }
 
 async fn upload_url_to_bucket(
@@ -131,6 +195,17 @@ async fn upload_url_to_bucket(
     url: &str,
     filename: &str,
 ) -> Result<s3::utils::PutStreamResponse, anyhow::Error> {
+    if let Some(source_path) = local_source_path(url) {
+        let mut file = tokio::fs::File::open(&source_path)
+            .await
+            .map_err(|e| anyhow::format_err!("Failed to open file: {e}"))?;
+        let result = bucket
+            .put_object_stream(&mut file, filename)
+            .await
+            .map_err(|e| anyhow::format_err!("Failed to upload file: {}", e))?;
+        return Ok(result);
+    }
+
     let parsed_url =
         Url::parse(url).map_err(|e| anyhow::format_err!("Failed to parse url `{}`: {}", url, e));
     let parsed_url = parsed_url?.clone();
@@ -0,0 +1,17 @@
+//! Helpers shared by unit tests.
+
+/// Small deterministic xorshift, so failures are reproducible
+pub struct Random(pub u64);
+
+impl Random {
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, max: u64) -> u64 {
+        self.next() % max
+    }
+}
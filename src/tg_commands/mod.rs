@@ -0,0 +1,2 @@
+mod settings;
+pub use settings::process_settings_command;
@@ -0,0 +1,160 @@
+use std::{str::FromStr, sync::Arc};
+
+use frankenstein::{
+    client_reqwest::Bot,
+    methods::{GetChatMemberParams, SendMessageParams},
+    types::{ChatMember, ChatType, Message, ReplyParameters},
+    AsyncTelegramApi,
+};
+use tokio::sync::Mutex;
+
+use crate::{data::SettingsCommand, hasher::PHashIndexer, models::ChatSettings};
+
+const SETTINGS_USAGE: &str = "Использование:
+/settings — показать настройки
+/settings tolerance <1-64> — допуск расстояния хэшей
+/settings days <1-365> — сколько дней искать дубли
+/settings quorum <N> — голосов для решения
+/settings threshold <0-1|default> — порог похожести, не ниже общего
+/settings blockhash <on|off> — поиск по blockhash
+/settings siglip2 <on|off> — поиск по SigLIP2
+/settings stickers <on|off> — искать дубли стикеров
+/settings mute <1-365|forever> — на сколько дней игнор глушит оригинал
+/settings retention <1-3650|default> — сколько дней хранить картинки
+/settings hashonly <on|off> — не сохранять картинки, только хэши";
+
+#[tracing::instrument(name = "Process settings command", skip(api, message, indexer))]
+pub async fn process_settings_command(
+    api: &Bot,
+    message: &Message,
+    args: &str,
+    indexer: Arc<Mutex<PHashIndexer>>,
+) -> Result<(), anyhow::Error> {
+    let chat_id = message.chat.id;
+
+    if !is_chat_admin(api, message).await? {
+        return reply(api, message, "Настройки доступны только админам чата").await;
+    }
+
+    let command = match SettingsCommand::from_str(args) {
+        Ok(command) => command,
+        Err(e) => {
+            tracing::warn!("Wrong settings command: {e}");
+            return reply(api, message, SETTINGS_USAGE).await;
+        }
+    };
+
+    let mut indexer = indexer.lock().await;
+    let mut settings = indexer.get_chat_settings(chat_id).await?;
+
+    match command {
+        SettingsCommand::Show => {
+            return reply(api, message, &format_settings(&settings)).await;
+        }
+        SettingsCommand::HashTolerance(tolerance) => settings.hash_tolerance = tolerance,
+        SettingsCommand::SearchDistanceDays(days) => {
+            settings.search_distance_seconds = days * 24 * 60 * 60
+        }
+        SettingsCommand::MinVotesCount(quorum) => settings.min_votes_count = quorum,
+        // Candidates are searched within global limits, lower threshold can't find more
+        SettingsCommand::MatchThreshold(Some(threshold))
+            if threshold < indexer.scoring().threshold =>
+        {
+            let text = format!(
+                "Порог похожести не может быть ниже общего {}",
+                indexer.scoring().threshold
+            );
+            return reply(api, message, &text).await;
+        }
+        SettingsCommand::MatchThreshold(threshold) => settings.match_threshold = threshold,
+        SettingsCommand::Blockhash(enabled) => settings.blockhash_enabled = enabled,
+        SettingsCommand::Siglip2(enabled) => settings.siglip2_enabled = enabled,
+        SettingsCommand::Stickers(enabled) => settings.stickers_enabled = enabled,
+        SettingsCommand::MuteDays(days) => {
+            settings.mute_duration_seconds = days.map(|days| days * 24 * 60 * 60)
+        }
+        SettingsCommand::RetentionDays(days) => {
+            settings.retention_seconds = days.map(|days| days * 24 * 60 * 60)
+        }
+        SettingsCommand::HashOnly(enabled) => settings.hash_only = enabled,
+    }
+
+    indexer.save_chat_settings(&settings).await?;
+    reply(
+        api,
+        message,
+        &format!("Настройки обновлены\n\n{}", format_settings(&settings)),
+    )
+    .await
+}
+
+async fn is_chat_admin(api: &Bot, message: &Message) -> Result<bool, anyhow::Error> {
+    if message.chat.type_field == ChatType::Private {
+        return Ok(true);
+    }
+    let Some(user) = &message.from else {
+        return Ok(false);
+    };
+
+    let member = api
+        .get_chat_member(
+            &GetChatMemberParams::builder()
+                .chat_id(message.chat.id)
+                .user_id(user.id)
+                .build(),
+        )
+        .await
+        .map_err(|e| anyhow::format_err!("Failed to get chat member: {e}"))?;
+
+    Ok(matches!(
+        member.result,
+        ChatMember::Creator(_) | ChatMember::Administrator(_)
+    ))
+}
+
+fn format_settings(settings: &ChatSettings) -> String {
+    let switch = |enabled| if enabled { "вкл" } else { "выкл" };
+    let threshold = settings
+        .match_threshold
+        .map(|threshold| threshold.to_string())
+        .unwrap_or("по умолчанию".to_owned());
+    let mute = settings
+        .mute_duration_seconds
+        .map(|duration| format!("{} дн.", duration / (24 * 60 * 60)))
+        .unwrap_or("навсегда".to_owned());
+    let retention = settings
+        .retention_seconds
+        .map(|retention| format!("{} дн.", retention / (24 * 60 * 60)))
+        .unwrap_or("по умолчанию".to_owned());
+
+    format!(
+        "Допуск хэшей: {}\nИскать дубли за дней: {}\nГолосов для решения: {}\nПорог похожести: {}\nBlockhash: {}\nSigLIP2: {}\nСтикеры: {}\nИгнор глушит оригинал: {}\nХранить картинки: {}\nТолько хэши: {}",
+        settings.hash_tolerance,
+        settings.search_distance_seconds / (24 * 60 * 60),
+        settings.min_votes_count,
+        threshold,
+        switch(settings.blockhash_enabled),
+        switch(settings.siglip2_enabled),
+        switch(settings.stickers_enabled),
+        mute,
+        retention,
+        switch(settings.hash_only),
+    )
+}
+
+async fn reply(api: &Bot, message: &Message, text: &str) -> Result<(), anyhow::Error> {
+    api.send_message(
+        &SendMessageParams::builder()
+            .chat_id(message.chat.id)
+            .text(text)
+            .reply_parameters(
+                ReplyParameters::builder()
+                    .message_id(message.message_id)
+                    .build(),
+            )
+            .build(),
+    )
+    .await
+    .map_err(|e| anyhow::format_err!("Failed to send settings message: {e}"))?;
+    Ok(())
+}
@@ -1,3 +1,5 @@
+use std::{collections::BTreeMap, net::SocketAddr, str::FromStr};
+
 use opentelemetry::{global, trace::TracerProvider};
 use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig, WithTonicConfig};
 use opentelemetry_sdk::{
@@ -6,21 +8,63 @@ use opentelemetry_sdk::{
     trace::{SdkTracerProvider, Tracer},
     Resource,
 };
-use tonic::metadata::MetadataMap;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
 use tracing::Level;
 use tracing_opentelemetry::OpenTelemetryLayer;
 use tracing_subscriber::{filter, fmt, layer::SubscriberExt, EnvFilter, Layer, Registry};
 
-fn metadata(auth_token: &str) -> Result<MetadataMap, anyhow::Error> {
-    let mut metadata = MetadataMap::with_capacity(3);
-    metadata.insert(
-        "authorization",
-        format!("Basic {auth_token}").parse().unwrap(),
-    );
-    let organization = "default".parse()?;
-    metadata.insert("organization", organization);
-    let stream_name = "default".parse()?;
-    metadata.insert("stream-name", stream_name);
+use crate::prometheus::{self, PrometheusReader};
+
+pub const DEFAULT_PROMETHEUS_LISTEN_ADDR: &str = "0.0.0.0:9464";
+
+/// Where traces and metrics are sent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TelemetryMode {
+    /// No logs, traces or metrics
+    Off,
+    /// Logs to stdout only
+    Stdout,
+    /// Logs to stdout, traces and metrics pushed to OTLP collector
+    Otlp,
+    /// Logs to stdout, metrics scraped from HTTP endpoint
+    Prometheus,
+}
+
+impl FromStr for TelemetryMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(TelemetryMode::Off),
+            "stdout" => Ok(TelemetryMode::Stdout),
+            "otlp" => Ok(TelemetryMode::Otlp),
+            "prometheus" => Ok(TelemetryMode::Prometheus),
+            _ => Err(anyhow::format_err!("Unknown telemetry mode {s}")),
+        }
+    }
+}
+
+/// Exporter settings, only ones of selected mode are used
+#[derive(Debug, Clone)]
+pub struct TelemetrySettings {
+    pub mode: TelemetryMode,
+    pub otlp_endpoint: Option<String>,
+    /// gRPC metadata sent with every export, e.g. `authorization`
+    pub otlp_headers: BTreeMap<String, String>,
+    pub prometheus_listen_addr: SocketAddr,
+    pub prometheus_path: String,
+}
+
+/// Headers as gRPC metadata, invalid names and values are rejected
+pub fn otlp_metadata(headers: &BTreeMap<String, String>) -> Result<MetadataMap, anyhow::Error> {
+    let mut metadata = MetadataMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        let key = MetadataKey::from_bytes(name.to_lowercase().as_bytes())
+            .map_err(|e| anyhow::format_err!("Invalid OTLP header name `{name}`: {e}"))?;
+        let value = MetadataValue::try_from(value.as_str())
+            .map_err(|e| anyhow::format_err!("Invalid OTLP header `{name}` value: {e}"))?;
+        metadata.insert(key, value);
+    }
     Ok(metadata)
 }
 
@@ -30,7 +74,7 @@ fn resource() -> Resource {
         .build()
 }
 
-fn init_tracing_subscriber(tracer: Tracer) {
+fn init_tracing_subscriber(tracer: Option<Tracer>) {
     let filter_otel = EnvFilter::new("info")
         .add_directive("hyper=off".parse().unwrap())
         .add_directive("opentelemetry=off".parse().unwrap())
@@ -38,7 +82,7 @@ fn init_tracing_subscriber(tracer: Tracer) {
         .add_directive("h2=off".parse().unwrap())
         .add_directive("reqwest=off".parse().unwrap());
 
-    let opentelemetry_layer = OpenTelemetryLayer::new(tracer);
+    let opentelemetry_layer = tracer.map(OpenTelemetryLayer::new);
 
     let stdout_layer = fmt::layer()
         .with_level(true)
@@ -51,47 +95,96 @@ fn init_tracing_subscriber(tracer: Tracer) {
     tracing::subscriber::set_global_default(subscriber).expect("Setting tracing subscriber failed");
 }
 
-pub fn init_tracing(otlp_endpoint: &str, token: &str) -> Result<impl Fn(), anyhow::Error> {
-    // To send traces to jaeger
-    let metadata = metadata(token)?;
+/// Exporters are connected lazily, so bot keeps working while collector is down
+fn build_otlp_providers(
+    settings: &TelemetrySettings,
+) -> Result<(SdkTracerProvider, SdkMeterProvider), anyhow::Error> {
+    let otlp_endpoint = settings
+        .otlp_endpoint
+        .as_deref()
+        .ok_or(anyhow::format_err!("OTLP endpoint is not set"))?;
+    let metadata = otlp_metadata(&settings.otlp_headers)?;
+
     let trace_exporter = SpanExporter::builder()
         .with_tonic()
         .with_endpoint(otlp_endpoint)
         .with_metadata(metadata.clone())
         .build()
-        .expect("Failed to init otlp tracers exporter");
-
-    global::set_text_map_propagator(TraceContextPropagator::new());
-
-    // To connect traces to exporter
-    let tracer_provider = SdkTracerProvider::builder()
-        .with_batch_exporter(trace_exporter)
-        .with_resource(resource())
-        .build();
-    init_tracing_subscriber(tracer_provider.tracer("img dupes bot"));
-
-    global::set_tracer_provider(tracer_provider.clone());
-
+        .map_err(|e| anyhow::format_err!("Failed to init otlp tracers exporter: {e}"))?;
     let metrics_exporter = MetricExporter::builder()
         .with_tonic()
         .with_endpoint(otlp_endpoint)
         .with_metadata(metadata)
         .build()
-        .expect("Failed to init otlp metrics exporter");
+        .map_err(|e| anyhow::format_err!("Failed to init otlp metrics exporter: {e}"))?;
 
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_batch_exporter(trace_exporter)
+        .with_resource(resource())
+        .build();
     let metrics_provider = SdkMeterProvider::builder()
         .with_periodic_exporter(metrics_exporter)
         .with_resource(resource())
         .build();
+    Ok((tracer_provider, metrics_provider))
+}
+
+/// Set up logs, traces and metrics, returned function flushes them on exit
+pub async fn init_tracing(settings: &TelemetrySettings) -> Result<impl Fn(), anyhow::Error> {
+    let mut tracer_provider = None;
+    let mut metrics_provider = None;
 
-    global::set_meter_provider(metrics_provider.clone());
+    match settings.mode {
+        TelemetryMode::Off => {}
+        TelemetryMode::Stdout => init_tracing_subscriber(None),
+        TelemetryMode::Otlp => match build_otlp_providers(settings) {
+            Ok((tracer, metrics)) => {
+                global::set_text_map_propagator(TraceContextPropagator::new());
+                init_tracing_subscriber(Some(tracer.tracer("img dupes bot")));
+                global::set_tracer_provider(tracer.clone());
+                global::set_meter_provider(metrics.clone());
+                tracer_provider = Some(tracer);
+                metrics_provider = Some(metrics);
+            }
+            Err(e) => {
+                init_tracing_subscriber(None);
+                tracing::error!("OTLP export disabled, logs go to stdout only: {e}");
+            }
+        },
+        TelemetryMode::Prometheus => {
+            init_tracing_subscriber(None);
+            let reader = PrometheusReader::default();
+            let metrics = SdkMeterProvider::builder()
+                .with_reader(reader.clone())
+                .with_resource(resource())
+                .build();
+            global::set_meter_provider(metrics.clone());
+            metrics_provider = Some(metrics);
+            prometheus::serve(
+                reader,
+                settings.prometheus_listen_addr,
+                &settings.prometheus_path,
+            )
+            .await?;
+            tracing::info!(
+                "Prometheus metrics served on {}{}",
+                settings.prometheus_listen_addr,
+                settings.prometheus_path
+            );
+        }
+    }
 
     Ok(Box::new(move || {
-        tracer_provider
-            .shutdown()
-            .expect("Failed to shutdown tracer");
-        metrics_provider
-            .shutdown()
-            .expect("Failed to shutdown metrics");
+        // Unsent data is lost if collector is down, it shouldn't fail shutdown
+        if let Some(tracer_provider) = &tracer_provider {
+            if let Err(e) = tracer_provider.shutdown() {
+                tracing::warn!("Failed to shutdown tracer: {e}");
+            }
+        }
+        if let Some(metrics_provider) = &metrics_provider {
+            if let Err(e) = metrics_provider.shutdown() {
+                tracing::warn!("Failed to shutdown metrics: {e}");
+            }
+        }
     }))
 }
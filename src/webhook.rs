@@ -0,0 +1,200 @@
+//! Embedded HTTP server receiving Telegram updates in webhook mode.
+//!
+//! Received updates are sent to channel and processed by the same handlers as in polling mode.
+//! Without `WEBHOOK_URL` webhook is not registered in Telegram, so server can be tested locally:
+//!
+//! ```sh
+//! curl -H 'X-Telegram-Bot-Api-Secret-Token: <secret>' -H 'Content-Type: application/json' \
+//!     -d @update.json http://127.0.0.1:8080/telegram
+//! ```
+
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Router,
+};
+use frankenstein::{
+    client_reqwest::Bot,
+    methods::{DeleteWebhookParams, SetWebhookParams},
+    updates::Update,
+    AsyncTelegramApi,
+};
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_util::sync::CancellationToken;
+
+pub const SECRET_TOKEN_HEADER: &str = "x-telegram-bot-api-secret-token";
+pub const DEFAULT_WEBHOOK_PATH: &str = "/telegram";
+/// First retry delay of failed registration, doubled up to the max one
+const REGISTER_RETRY_DELAY: Duration = Duration::from_secs(1);
+const REGISTER_MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// How bot receives updates from Telegram
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeliveryMode {
+    Polling,
+    Webhook,
+}
+
+impl FromStr for DeliveryMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "polling" => Ok(DeliveryMode::Polling),
+            "webhook" => Ok(DeliveryMode::Webhook),
+            _ => Err(anyhow::format_err!("Unknown delivery mode {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub listen_addr: SocketAddr,
+    pub path: String,
+    /// Public URL registered with `setWebhook`, skipped if not set
+    pub url: Option<String>,
+    pub secret_token: String,
+}
+
+impl WebhookConfig {
+    pub fn new(
+        listen_addr: SocketAddr,
+        path: &str,
+        url: Option<String>,
+        secret_token: &str,
+    ) -> Result<Self, anyhow::Error> {
+        // Telegram allows only 1-256 characters A-Z, a-z, 0-9, _ and -
+        if secret_token.is_empty()
+            || secret_token.len() > 256
+            || !secret_token
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(anyhow::format_err!("Invalid webhook secret token"));
+        }
+        if !path.starts_with('/') {
+            return Err(anyhow::format_err!("Webhook path should start with /"));
+        }
+
+        Ok(Self {
+            listen_addr,
+            path: path.to_owned(),
+            url,
+            secret_token: secret_token.to_owned(),
+        })
+    }
+}
+
+#[derive(Clone)]
+struct WebhookState {
+    secret_token: Arc<String>,
+    updates: mpsc::Sender<Update>,
+}
+
+/// Listen for updates until `shutdown` is cancelled
+#[tracing::instrument(name = "Webhook server", skip_all)]
+pub async fn serve(
+    config: &WebhookConfig,
+    updates: mpsc::Sender<Update>,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let state = WebhookState {
+        secret_token: Arc::new(config.secret_token.clone()),
+        updates,
+    };
+    let app = Router::new()
+        .route(&config.path, post(receive_update))
+        .with_state(state);
+
+    let listener = TcpListener::bind(config.listen_addr)
+        .await
+        .map_err(|e| anyhow::format_err!("Failed to bind {}: {e}", config.listen_addr))?;
+    tracing::info!("Webhook server listening on {}", config.listen_addr);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+        .map_err(|e| anyhow::format_err!("Webhook server failed: {e}"))
+}
+
+async fn receive_update(
+    State(state): State<WebhookState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let secret_token = headers
+        .get(SECRET_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok());
+    if secret_token != Some(state.secret_token.as_str()) {
+        tracing::warn!("Webhook request with wrong secret token");
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let update: Update = match serde_json::from_slice(&body) {
+        Ok(update) => update,
+        Err(e) => {
+            tracing::error!("Failed to parse webhook update: {e}");
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    if state.updates.send(update).await.is_err() {
+        tracing::error!("Updates channel closed");
+        return StatusCode::SERVICE_UNAVAILABLE;
+    }
+    StatusCode::OK
+}
+
+#[tracing::instrument(name = "Set webhook", skip_all)]
+pub async fn set_webhook(api: &Bot, config: &WebhookConfig) -> Result<(), anyhow::Error> {
+    let Some(url) = &config.url else {
+        tracing::info!("Webhook URL is not set, skip webhook registration");
+        return Ok(());
+    };
+
+    api.set_webhook(
+        &SetWebhookParams::builder()
+            .url(url.clone())
+            .secret_token(config.secret_token.clone())
+            .build(),
+    )
+    .await
+    .map_err(|e| anyhow::format_err!("Failed to set webhook: {e}"))?;
+    tracing::info!("Webhook registered");
+    Ok(())
+}
+
+/// Retry `set_webhook` until it succeeds or `shutdown` is cancelled.
+///
+/// Server keeps receiving updates meanwhile, e.g. ones of previous registration.
+pub async fn register_webhook(api: &Bot, config: &WebhookConfig, shutdown: CancellationToken) {
+    let mut delay = REGISTER_RETRY_DELAY;
+    loop {
+        match set_webhook(api, config).await {
+            Ok(()) => return,
+            Err(e) => tracing::error!("{e}, retry in {delay:?}"),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.cancelled() => return,
+        }
+        delay = (delay * 2).min(REGISTER_MAX_RETRY_DELAY);
+    }
+}
+
+#[tracing::instrument(name = "Delete webhook", skip_all)]
+pub async fn delete_webhook(api: &Bot, config: &WebhookConfig) -> Result<(), anyhow::Error> {
+    if config.url.is_none() {
+        return Ok(());
+    }
+
+    api.delete_webhook(&DeleteWebhookParams::builder().build())
+        .await
+        .map_err(|e| anyhow::format_err!("Failed to delete webhook: {e}"))?;
+    tracing::info!("Webhook deleted");
+    Ok(())
+}
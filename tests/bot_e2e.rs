@@ -0,0 +1,343 @@
+//! Whole bot binary against fake Bot API: photo, duplicate reply, voting and alert removal.
+//! Local Bot API server mode with absolute file paths, Prometheus metrics, health endpoints
+//! and webhook secret token check are covered too.
+
+mod common;
+
+use std::{
+    fs,
+    io::Cursor,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use common::{callback_update, photo_update, MockBotApi, BOT_TOKEN};
+use image::{DynamicImage, ImageFormat, RgbImage};
+
+const CHAT_ID: i64 = -1001234567890;
+const WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Running bot process, killed when test finishes
+struct BotProcess {
+    child: Child,
+    dir: PathBuf,
+}
+
+impl BotProcess {
+    /// Extra env is applied over default test setup
+    fn start(api_url: &str, env: &[(&str, &str)]) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("img_bot_e2e_{nanos}"));
+        fs::create_dir_all(dir.join("files")).unwrap();
+        let log = fs::File::create(dir.join("bot.log")).unwrap();
+
+        // Clean env and own directory, so developer's .env and hashes.db are not used
+        let child = Command::new(env!("CARGO_BIN_EXE_img_bot"))
+            .current_dir(&dir)
+            .env_clear()
+            .env("TELEGRAM_BOT_API_TOKEN", BOT_TOKEN)
+            .env("TELEGRAM_API_URL", api_url)
+            .env("OTLP_ENDPOINT", "http://127.0.0.1:9")
+            .env("OTLP_TOKEN", "test")
+            .env("STORAGE_BACKEND", "local")
+            .env("LOCAL_STORAGE_PATH", dir.join("files"))
+            .envs(env.iter().copied())
+            .stdout(log.try_clone().unwrap())
+            .stderr(log)
+            .stdin(Stdio::null())
+            .spawn()
+            .expect("Failed to start bot");
+        Self { child, dir }
+    }
+
+    fn db(&self) -> rusqlite::Connection {
+        rusqlite::Connection::open(self.dir.join("hashes.db")).unwrap()
+    }
+
+    /// Wait until query returns positive count
+    async fn wait_for_rows(&self, query: &str) {
+        let started = Instant::now();
+        loop {
+            let count: i64 = self
+                .db()
+                .query_row(query, [], |row| row.get(0))
+                .unwrap_or(0);
+            if count > 0 {
+                return;
+            }
+            assert!(started.elapsed() < WAIT_TIMEOUT, "No rows for `{query}`");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+impl Drop for BotProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if std::thread::panicking() {
+            let log = fs::read_to_string(self.dir.join("bot.log")).unwrap_or_default();
+            eprintln!("Bot log:\n{log}");
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Port which was free a moment ago
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn test_image(format: ImageFormat) -> Vec<u8> {
+    let image = RgbImage::from_fn(256, 192, |x, y| {
+        let circle = (x as i32 - 96).pow(2) + (y as i32 - 96).pow(2) < 48 * 48;
+        if circle {
+            image::Rgb([250, 250, 250])
+        } else {
+            image::Rgb([x as u8, y as u8, 128])
+        }
+    });
+    let mut data = vec![];
+    DynamicImage::ImageRgb8(image)
+        .write_to(&mut Cursor::new(&mut data), format)
+        .unwrap();
+    data
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn duplicate_is_reported_and_removed_after_voting() {
+    let api = MockBotApi::start().await;
+    let bot = BotProcess::start(&api.url, &[]);
+
+    // Original is indexed silently
+    api.add_file("original", "unique-original", test_image(ImageFormat::Png));
+    api.push_update(photo_update(CHAT_ID, 1, 1, "original", "unique-original"));
+    bot.wait_for_rows("SELECT COUNT(*) FROM hashes WHERE message_id = 1")
+        .await;
+    assert!(api.calls("sendMessage").is_empty());
+
+    // Same picture in other encoding is a new file, so it's found by hashes
+    api.add_file("repost", "unique-repost", test_image(ImageFormat::Jpeg));
+    api.push_update(photo_update(CHAT_ID, 2, 2, "repost", "unique-repost"));
+    let alert = api
+        .wait_for_call("sendMessage", WAIT_TIMEOUT, |params| {
+            params["chat_id"] == CHAT_ID && params["reply_parameters"]["message_id"] == 1
+        })
+        .await;
+    assert_eq!(
+        alert["reply_markup"]["inline_keyboard"][0][0]["callback_data"],
+        format!("wr {CHAT_ID} 1")
+    );
+    assert_eq!(
+        api.calls("downloadFile").len(),
+        2,
+        "both photos are downloaded"
+    );
+    // Mock gives first bot message this id
+    let alert_message_id = 1001;
+
+    // Not duplicate voting replaces alert buttons
+    api.push_update(callback_update(
+        "wrong",
+        2,
+        CHAT_ID,
+        alert_message_id,
+        &format!("wr {CHAT_ID} 1"),
+    ));
+    let voting = api
+        .wait_for_call("editMessageText", WAIT_TIMEOUT, |params| {
+            params["message_id"] == alert_message_id
+        })
+        .await;
+    assert_eq!(
+        voting["reply_markup"]["inline_keyboard"][0][0]["callback_data"],
+        "pro 1"
+    );
+    api.wait_for_call("answerCallbackQuery", WAIT_TIMEOUT, |params| {
+        params["callback_query_id"] == "wrong"
+    })
+    .await;
+
+    // Two of default five votes is enough majority
+    for user_id in [3, 4] {
+        api.push_update(callback_update(
+            &format!("pro{user_id}"),
+            user_id,
+            CHAT_ID,
+            alert_message_id,
+            "pro 1",
+        ));
+    }
+    let finished = api
+        .wait_for_call("editMessageText", WAIT_TIMEOUT, |params| {
+            params["text"]
+                .as_str()
+                .is_some_and(|text| text.contains("завершено"))
+        })
+        .await;
+    assert_eq!(finished["message_id"], alert_message_id);
+    api.wait_for_call("deleteMessage", WAIT_TIMEOUT, |params| {
+        params["chat_id"] == CHAT_ID && params["message_id"] == alert_message_id
+    })
+    .await;
+    bot.wait_for_rows("SELECT COUNT(*) FROM pair_exclusions WHERE file_id = 'unique-repost'")
+        .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn local_server_files_are_read_from_disk() {
+    let api = MockBotApi::start().await;
+    let bot = BotProcess::start(&api.url, &[]);
+
+    let path = bot.dir.join("local_photo.png");
+    fs::write(&path, test_image(ImageFormat::Png)).unwrap();
+    api.add_local_file("local", "unique-local", &path);
+    api.push_update(photo_update(CHAT_ID, 1, 1, "local", "unique-local"));
+    bot.wait_for_rows("SELECT COUNT(*) FROM hashes WHERE file_id = 'unique-local'")
+        .await;
+    assert!(api.calls("downloadFile").is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn prometheus_endpoint_serves_bot_metrics() {
+    let api = MockBotApi::start().await;
+    let listen_addr = format!("127.0.0.1:{}", free_port());
+    let bot = BotProcess::start(
+        &api.url,
+        &[
+            ("TELEMETRY_MODE", "prometheus"),
+            ("PROMETHEUS_LISTEN_ADDR", &listen_addr),
+        ],
+    );
+
+    api.add_file("photo", "unique-photo", test_image(ImageFormat::Png));
+    api.push_update(photo_update(CHAT_ID, 1, 1, "photo", "unique-photo"));
+    bot.wait_for_rows("SELECT COUNT(*) FROM hashes WHERE message_id = 1")
+        .await;
+
+    let started = Instant::now();
+    loop {
+        let response = reqwest::get(format!("http://{listen_addr}/metrics")).await;
+        if let Ok(response) = response {
+            assert!(response.status().is_success());
+            let body = response.text().await.unwrap();
+            if body.contains("images_count_total{user_id=\"1\"} 1") {
+                assert!(body.contains("# TYPE message_hashing_time gauge"));
+                return;
+            }
+        }
+        assert!(
+            started.elapsed() < WAIT_TIMEOUT,
+            "No bot metrics on endpoint"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Status code and JSON body, None while server isn't started
+async fn get_json(url: &str) -> Option<(u16, serde_json::Value)> {
+    let response = reqwest::get(url).await.ok()?;
+    let code = response.status().as_u16();
+    let body = response.text().await.ok()?;
+    Some((code, serde_json::from_str(&body).ok()?))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn health_endpoints_report_bot_state() {
+    let api = MockBotApi::start().await;
+    let listen_addr = format!("127.0.0.1:{}", free_port());
+    let bot = BotProcess::start(&api.url, &[("HEALTH_LISTEN_ADDR", &listen_addr)]);
+    let base_url = format!("http://{listen_addr}");
+
+    let started = Instant::now();
+    while get_json(&format!("{base_url}/readyz"))
+        .await
+        .map(|(code, _)| code)
+        != Some(200)
+    {
+        assert!(started.elapsed() < WAIT_TIMEOUT, "Bot isn't ready");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let healthz = reqwest::get(format!("{base_url}/healthz")).await.unwrap();
+    assert!(healthz.status().is_success());
+
+    api.add_file("photo", "unique-photo", test_image(ImageFormat::Png));
+    api.push_update(photo_update(CHAT_ID, 1, 1, "photo", "unique-photo"));
+    bot.wait_for_rows("SELECT COUNT(*) FROM hashes WHERE message_id = 1")
+        .await;
+    let (code, status) = get_json(&format!("{base_url}/status")).await.unwrap();
+    assert_eq!(code, 200);
+    assert_eq!(status["update_offset"], 2);
+    assert_eq!(status["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(
+        status["chats"],
+        serde_json::json!([{ "chat_id": CHAT_ID, "images": 1 }])
+    );
+
+    // Missing storage directory makes bot unready
+    fs::remove_dir_all(bot.dir.join("files")).unwrap();
+    let (code, readyz) = get_json(&format!("{base_url}/readyz")).await.unwrap();
+    assert_eq!(code, 503);
+    assert_eq!(readyz["checks"]["database"], "ok");
+    assert_ne!(readyz["checks"]["storage"], "ok");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn webhook_accepts_only_updates_with_secret_token() {
+    let api = MockBotApi::start().await;
+    let listen_addr = format!("127.0.0.1:{}", free_port());
+    // Fake API doesn't know setWebhook, so registration fails and is retried
+    let bot = BotProcess::start(
+        &api.url,
+        &[
+            ("BOT_DELIVERY_MODE", "webhook"),
+            ("WEBHOOK_LISTEN_ADDR", &listen_addr),
+            ("WEBHOOK_URL", "https://example.com/telegram"),
+            ("WEBHOOK_SECRET_TOKEN", "secret"),
+        ],
+    );
+    api.wait_for_call("setWebhook", WAIT_TIMEOUT, |params| {
+        params["secret_token"] == "secret"
+    })
+    .await;
+
+    api.add_file("photo", "unique-photo", test_image(ImageFormat::Png));
+    let mut update = photo_update(CHAT_ID, 1, 1, "photo", "unique-photo");
+    update["update_id"] = serde_json::json!(1);
+    let post = |secret_token: &'static str| {
+        reqwest::Client::new()
+            .post(format!("http://{listen_addr}/telegram"))
+            .header("Content-Type", "application/json")
+            .header("X-Telegram-Bot-Api-Secret-Token", secret_token)
+            .body(update.to_string())
+            .send()
+    };
+
+    let started = Instant::now();
+    let response = loop {
+        if let Ok(response) = post("wrong").await {
+            break response;
+        }
+        assert!(
+            started.elapsed() < WAIT_TIMEOUT,
+            "Webhook server isn't started"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    };
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = post("secret").await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    bot.wait_for_rows("SELECT COUNT(*) FROM hashes WHERE message_id = 1")
+        .await;
+    // Only accepted update is processed
+    assert_eq!(api.calls("getFile").len(), 1);
+}
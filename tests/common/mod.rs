@@ -0,0 +1,329 @@
+//! In-process fake Telegram Bot API for end-to-end tests.
+//!
+//! Serves scripted updates and files, answers bot methods with minimal valid objects
+//! and records every call, so tests can assert what the bot sent.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Router,
+};
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, sync::Notify};
+
+pub const BOT_TOKEN: &str = "123456:test-token";
+/// Empty `getUpdates` waits for new update this long, so bot doesn't spin
+const POLL_WAIT: Duration = Duration::from_millis(100);
+const FIRST_BOT_MESSAGE_ID: i32 = 1000;
+const MESSAGE_DATE: u64 = 1_700_000_000;
+
+#[derive(Debug, Clone)]
+pub struct RecordedCall {
+    pub method: String,
+    pub params: Value,
+}
+
+struct MockFile {
+    file_id: String,
+    file_unique_id: String,
+    file_path: String,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct MockState {
+    updates: Vec<Value>,
+    next_update_id: i64,
+    files: HashMap<String, MockFile>,
+    calls: Vec<RecordedCall>,
+    next_message_id: i32,
+}
+
+struct Shared {
+    state: Mutex<MockState>,
+    new_update: Notify,
+}
+
+pub struct MockBotApi {
+    /// Base URL to pass to bot instead of api.telegram.org
+    pub url: String,
+    shared: Arc<Shared>,
+}
+
+impl MockBotApi {
+    pub async fn start() -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(MockState {
+                next_update_id: 1,
+                next_message_id: FIRST_BOT_MESSAGE_ID,
+                ..Default::default()
+            }),
+            new_update: Notify::new(),
+        });
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .expect("Failed to bind mock api");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let app = Router::new().fallback(handle).with_state(shared.clone());
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.expect("Mock api failed");
+        });
+
+        Self { url, shared }
+    }
+
+    /// File returned by `getFile` and served from file endpoint
+    pub fn add_file(&self, file_id: &str, file_unique_id: &str, data: Vec<u8>) {
+        let file = MockFile {
+            file_id: file_id.to_owned(),
+            file_unique_id: file_unique_id.to_owned(),
+            file_path: format!("photos/{file_unique_id}.jpg"),
+            data,
+        };
+        self.state().files.insert(file_id.to_owned(), file);
+    }
+
+    /// File on disk returned with absolute path, like local Bot API server does
+    pub fn add_local_file(&self, file_id: &str, file_unique_id: &str, path: &std::path::Path) {
+        let file = MockFile {
+            file_id: file_id.to_owned(),
+            file_unique_id: file_unique_id.to_owned(),
+            file_path: path.to_str().expect("Non UTF-8 path").to_owned(),
+            data: std::fs::read(path).expect("Failed to read local file"),
+        };
+        self.state().files.insert(file_id.to_owned(), file);
+    }
+
+    /// Queue update content like `{"message": ...}`, update id is assigned here
+    pub fn push_update(&self, mut content: Value) {
+        {
+            let mut state = self.state();
+            content["update_id"] = json!(state.next_update_id);
+            state.next_update_id += 1;
+            state.updates.push(content);
+        }
+        self.shared.new_update.notify_waiters();
+    }
+
+    pub fn calls(&self, method: &str) -> Vec<Value> {
+        self.state()
+            .calls
+            .iter()
+            .filter(|call| call.method == method)
+            .map(|call| call.params.clone())
+            .collect()
+    }
+
+    /// Wait for call of method with params matching predicate
+    pub async fn wait_for_call(
+        &self,
+        method: &str,
+        timeout: Duration,
+        predicate: impl Fn(&Value) -> bool,
+    ) -> Value {
+        let started = Instant::now();
+        loop {
+            if let Some(params) = self
+                .calls(method)
+                .into_iter()
+                .find(|params| predicate(params))
+            {
+                return params;
+            }
+            if started.elapsed() > timeout {
+                let calls = self.state().calls.clone();
+                panic!("{method} call not found in {timeout:?}, recorded calls: {calls:#?}");
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.shared.state.lock().unwrap()
+    }
+}
+
+pub fn user_json(user_id: u64, username: &str) -> Value {
+    json!({
+        "id": user_id,
+        "is_bot": false,
+        "first_name": username,
+        "username": username,
+    })
+}
+
+pub fn chat_json(chat_id: i64) -> Value {
+    json!({"id": chat_id, "type": "supergroup", "title": "Test chat"})
+}
+
+/// Photo message update content with single photo size
+pub fn photo_update(
+    chat_id: i64,
+    message_id: i32,
+    user_id: u64,
+    file_id: &str,
+    file_unique_id: &str,
+) -> Value {
+    json!({
+        "message": {
+            "message_id": message_id,
+            "date": MESSAGE_DATE,
+            "chat": chat_json(chat_id),
+            "from": user_json(user_id, &format!("user{user_id}")),
+            "photo": [{
+                "file_id": file_id,
+                "file_unique_id": file_unique_id,
+                "width": 256,
+                "height": 192,
+            }],
+        }
+    })
+}
+
+/// Button press on bot message update content
+pub fn callback_update(
+    query_id: &str,
+    user_id: u64,
+    chat_id: i64,
+    message_id: i64,
+    data: &str,
+) -> Value {
+    json!({
+        "callback_query": {
+            "id": query_id,
+            "from": user_json(user_id, &format!("user{user_id}")),
+            "chat_instance": "test",
+            "message": {
+                "message_id": message_id,
+                "date": MESSAGE_DATE,
+                "chat": chat_json(chat_id),
+                "text": "",
+            },
+            "data": data,
+        }
+    })
+}
+
+async fn handle(State(shared): State<Arc<Shared>>, uri: Uri, body: Bytes) -> Response {
+    let path = uri.path();
+    if let Some(file_path) = path
+        .strip_prefix(&format!("/file/bot{BOT_TOKEN}/"))
+        .map(|file_path| file_path.trim_start_matches('/'))
+    {
+        return download_file(&shared, file_path);
+    }
+
+    let Some(method) = path.strip_prefix(&format!("/bot{BOT_TOKEN}/")) else {
+        return error(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+    let params: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    shared.state.lock().unwrap().calls.push(RecordedCall {
+        method: method.to_owned(),
+        params: params.clone(),
+    });
+
+    match method {
+        "getUpdates" => get_updates(&shared, &params).await,
+        "getFile" => get_file(&shared, &params),
+        "sendMessage" => {
+            let message_id = {
+                let mut state = shared.state.lock().unwrap();
+                state.next_message_id += 1;
+                state.next_message_id
+            };
+            ok(json!({
+                "message_id": message_id,
+                "date": MESSAGE_DATE,
+                "chat": chat_json(params["chat_id"].as_i64().unwrap_or_default()),
+                "text": params["text"],
+            }))
+        }
+        "editMessageText" => ok(json!({
+            "message_id": params["message_id"],
+            "date": MESSAGE_DATE,
+            "chat": chat_json(params["chat_id"].as_i64().unwrap_or_default()),
+            "text": params["text"],
+        })),
+        "deleteMessage" | "answerCallbackQuery" => ok(json!(true)),
+        _ => error(StatusCode::NOT_FOUND, "Not Found"),
+    }
+}
+
+async fn get_updates(shared: &Shared, params: &Value) -> Response {
+    let offset = params["offset"].as_i64().unwrap_or(0);
+    let is_empty = {
+        let mut state = shared.state.lock().unwrap();
+        // Updates before offset are confirmed by bot
+        state
+            .updates
+            .retain(|update| update["update_id"].as_i64().unwrap_or_default() >= offset);
+        state.updates.is_empty()
+    };
+    if is_empty {
+        let _ = tokio::time::timeout(POLL_WAIT, shared.new_update.notified()).await;
+    }
+    let updates = shared.state.lock().unwrap().updates.clone();
+    ok(json!(updates))
+}
+
+fn get_file(shared: &Shared, params: &Value) -> Response {
+    let state = shared.state.lock().unwrap();
+    match params["file_id"]
+        .as_str()
+        .and_then(|id| state.files.get(id))
+    {
+        Some(file) => ok(json!({
+            "file_id": file.file_id,
+            "file_unique_id": file.file_unique_id,
+            "file_size": file.data.len(),
+            "file_path": file.file_path,
+        })),
+        None => error(StatusCode::BAD_REQUEST, "Bad Request: invalid file_id"),
+    }
+}
+
+fn download_file(shared: &Shared, file_path: &str) -> Response {
+    let mut state = shared.state.lock().unwrap();
+    state.calls.push(RecordedCall {
+        method: "downloadFile".to_owned(),
+        params: json!({"file_path": file_path}),
+    });
+    match state
+        .files
+        .values()
+        .find(|file| file.file_path == file_path)
+    {
+        Some(file) => file.data.clone().into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+fn ok(result: Value) -> Response {
+    json_response(StatusCode::OK, json!({"ok": true, "result": result}))
+}
+
+fn error(status: StatusCode, description: &str) -> Response {
+    json_response(
+        status,
+        json!({"ok": false, "error_code": status.as_u16(), "description": description}),
+    )
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response {
+    (
+        status,
+        [(header::CONTENT_TYPE, "application/json")],
+        body.to_string(),
+    )
+        .into_response()
+}